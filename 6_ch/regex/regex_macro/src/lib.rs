@@ -0,0 +1,39 @@
+//! コンパイル時に正規表現を検査・コンパイルする`regex!`マクロ
+//!
+//! ```ignore
+//! use regex_macro::regex;
+//!
+//! let re = regex!("ab(c|d)*");
+//! assert!(re.do_matching("abcd", true).unwrap());
+//! ```
+//!
+//! 式が不正な場合はコンパイルエラーとなる。コンパイル済みのプログラムは
+//! `regex::Program`のバイナリ形式でstaticに埋め込まれる。
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, LitStr};
+
+#[proc_macro]
+pub fn regex(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    let expr = lit.value();
+
+    if let Err(e) = regex::parse(&expr) {
+        let msg = format!("invalid regular expression\n{}", e.render(&expr).trim_end());
+        return syn::Error::new(lit.span(), msg).to_compile_error().into();
+    }
+
+    let program = match regex::Program::new(&expr, true) {
+        Ok(program) => program,
+        Err(e) => return syn::Error::new(lit.span(), e.to_string()).to_compile_error().into(),
+    };
+    let bytes = program.to_bytes();
+
+    quote! {
+        {
+            static PROGRAM: ::regex::StaticProgram = ::regex::StaticProgram::new(&[#(#bytes),*]);
+            &PROGRAM
+        }
+    }
+    .into()
+}
@@ -0,0 +1,38 @@
+use regex::{compile, Instruction};
+use regex_macro::regex;
+
+#[test]
+fn test_regex() {
+    let re = regex!("ab(c|d)*e");
+    assert!(re.do_matching("abcdce", true).unwrap());
+    assert!(re.do_matching("abe", false).unwrap());
+    assert!(!re.do_matching("abx", true).unwrap());
+
+    assert_eq!(re.program().exprs, vec!["ab(c|d)*e".to_string()]);
+    assert_eq!(re.code(), compile("ab(c|d)*e", true).unwrap().as_slice());
+}
+
+#[test]
+fn test_escape() {
+    let re = regex!(r"\(\*\)");
+    assert!(re.do_matching("(*)", true).unwrap());
+    assert_eq!(re.code()[0], Instruction::Str(vec!['(', '*', ')']));
+}
+
+#[test]
+fn test_static() {
+    fn get() -> &'static regex::StaticProgram {
+        regex!("a+")
+    }
+
+    // 同じ呼び出し箇所は同じstaticを指す
+    assert!(std::ptr::eq(get(), get()));
+    assert!(get().do_matching("aaa", true).unwrap());
+}
+
+/// 不正な式は、文字列リテラルを指すコンパイルエラーになる
+#[test]
+fn test_compile_error() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/invalid.rs");
+}
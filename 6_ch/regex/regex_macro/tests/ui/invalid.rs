@@ -0,0 +1,5 @@
+use regex_macro::regex;
+
+fn main() {
+    let _ = regex!("ab(c|d");
+}
@@ -0,0 +1,93 @@
+//! `regex compare PATTERN1 PATTERN2`: 2つの式がマッチする文字列の集合を比べる
+use crate::grep;
+use regex::{Comparison, DynError, Flags};
+use std::io::{self, Write};
+
+pub const USAGE: &str = "\
+usage: regex compare [-i] [-w] PATTERN1 PATTERN2
+
+Compare the sets of whole strings matched by PATTERN1 and PATTERN2, and tell
+whether they are equivalent, one contains the other, they overlap or they are
+disjoint. For each of \"only PATTERN1\", \"only PATTERN2\" and \"both\", print the
+shortest such string, or \"none\".
+
+  -i          ignore case distinctions
+  -w          match only whole words
+
+Exit status is 0 if the patterns are equivalent, 1 if not, and 2 if an error occurred.";
+
+pub fn main(args: &[String]) -> i32 {
+    let (flags, patterns) = match grep::parse_subcommand_args(args, false, |_, _| Ok(false)) {
+        Ok(parsed) => parsed,
+        Err(e) => return grep::usage_error(&e, USAGE),
+    };
+    let [left, right] = patterns.as_slice() else {
+        return grep::usage_error("", USAGE);
+    };
+    for pattern in [left, right] {
+        if let Err(e) = grep::check_pattern(pattern) {
+            eprintln!("{e}");
+            return 2;
+        }
+    }
+
+    match compare(left, right, flags, &mut io::stdout().lock()) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("regex: {e}");
+            2
+        }
+    }
+}
+
+/// 比較結果を書き出し、等価であればtrueを返す
+fn compare<W: Write>(left: &str, right: &str, flags: Flags, out: &mut W) -> Result<bool, DynError> {
+    let c = regex::compare(left, right, flags)?;
+    writeln!(out, "{}", relation(&c))?;
+
+    let example = |s: &Option<String>| s.as_ref().map_or("none".to_string(), |s| format!("{s:?}"));
+    writeln!(out, "only PATTERN1: {}", example(&c.only_left))?;
+    writeln!(out, "only PATTERN2: {}", example(&c.only_right))?;
+    writeln!(out, "both:          {}", example(&c.both))?;
+    Ok(c.is_equivalent())
+}
+
+fn relation(c: &Comparison) -> &'static str {
+    if c.is_equivalent() {
+        "equivalent"
+    } else if c.is_subset() {
+        "PATTERN1 is a subset of PATTERN2"
+    } else if c.is_superset() {
+        "PATTERN2 is a subset of PATTERN1"
+    } else if c.is_overlapping() {
+        "overlapping"
+    } else {
+        "disjoint"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compare;
+
+    fn run(left: &str, right: &str) -> (String, bool) {
+        let mut out = Vec::new();
+        let is_equivalent = compare(left, right, Default::default(), &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), is_equivalent)
+    }
+
+    #[test]
+    fn test_compare() {
+        assert_eq!(
+            run("(a|b)*", "(a*b*)*"),
+            ("equivalent\nonly PATTERN1: none\nonly PATTERN2: none\nboth:          \"\"\n".into(), true)
+        );
+        assert_eq!(
+            run("ab?", "ab|ac"),
+            ("overlapping\nonly PATTERN1: \"a\"\nonly PATTERN2: \"ac\"\nboth:          \"ab\"\n".into(), false)
+        );
+        assert!(run("ab", "a.").0.starts_with("PATTERN1 is a subset of PATTERN2\n"));
+        assert!(run("a\\n", "b").0.starts_with("disjoint\nonly PATTERN1: \"a\\n\""));
+    }
+}
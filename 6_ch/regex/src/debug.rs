@@ -0,0 +1,273 @@
+//! `regex debug PATTERN STRING`: バックトラックするVMの動きを1命令ずつ表示する
+use crate::grep;
+use regex::{DynError, Program, Step};
+use std::io::{self, BufRead, IsTerminal, Write};
+
+pub const USAGE: &str = "\
+usage: regex debug [-i] [-w] [-x] [--trace] PATTERN STRING
+
+Search STRING for PATTERN with the backtracking VM and show every instruction
+it executes: the program counter, the position in STRING, the instruction and
+the stack of (PC, position) pairs to resume from when a thread fails. A line
+\"backtrack to PC at sp N\" marks where a failed thread resumes from the stack.
+A thread also fails when it comes back to the same jump or split without
+consuming input, so a loop whose body can match the empty string ends.
+
+When standard input is a terminal, stop before each instruction and read a
+command:
+
+  <Enter>, s  execute one instruction
+  c           run to the end without stopping
+  b PC        run until the instruction at PC
+  q           quit
+
+  -i          ignore case distinctions
+  -w          match only whole words
+  -x          match only the whole STRING
+  --trace     print the full trace without stopping
+
+Exit status is 0 if STRING matches, 1 if not or quit, and 2 if an error occurred.";
+
+pub fn main(args: &[String]) -> i32 {
+    let mut is_trace = false;
+    let parsed = grep::parse_subcommand_args(args, true, |arg, _| {
+        if arg != "--trace" {
+            return Ok(false);
+        }
+        is_trace = true;
+        Ok(true)
+    });
+    let (flags, operands) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return grep::usage_error(&e, USAGE),
+    };
+    let [pattern, line] = operands.as_slice() else {
+        return grep::usage_error("", USAGE);
+    };
+    if let Err(e) = grep::check_pattern(pattern) {
+        eprintln!("{e}");
+        return 2;
+    }
+
+    let is_interactive = !is_trace && io::stdin().is_terminal();
+    let result = Program::with_flags(pattern, flags, true).and_then(|program| {
+        debug(&program, line, is_interactive, &mut io::stdin().lock(), &mut io::stdout().lock())
+    });
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("regex: {e}");
+            2
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Step,         // 命令ごとに止まる
+    Continue,     // 最後まで止まらない
+    Break(usize), // 指定したpcの命令で止まる
+}
+
+/// トレースの表示と、対話的な操作
+struct Debugger<'a, R, W> {
+    line: &'a [char],
+    input: &'a mut R,
+    out: &'a mut W,
+    mode: Mode,
+    start: Option<usize>, // 表示中の試行の開始位置
+    n_steps: usize,
+    n_backtracks: usize,
+    is_quit: bool,
+    error: Option<io::Error>,
+}
+
+impl<R: BufRead, W: Write> Debugger<'_, R, W> {
+    /// Program::trace_atのフック。中断する場合はfalse
+    fn on_step(&mut self, step: &Step) -> bool {
+        match self.step(step) {
+            Ok(is_continue) => is_continue,
+            Err(e) => {
+                self.error = Some(e);
+                false
+            }
+        }
+    }
+
+    fn step(&mut self, step: &Step) -> io::Result<bool> {
+        if self.start != Some(step.start) {
+            writeln!(self.out, "\ntry at {}", step.start)?;
+            self.start = Some(step.start);
+        }
+        self.n_steps += 1;
+        if step.is_backtrack {
+            self.n_backtracks += 1;
+            writeln!(self.out, "backtrack to {:>04} at sp {}", step.pc, step.sp)?;
+        }
+
+        let stack = if step.stack.is_empty() {
+            "-".to_string()
+        } else {
+            let threads: Vec<String> = step.stack.iter().map(|(pc, sp)| format!("({pc:>04}, {sp})")).collect();
+            threads.join(" ")
+        };
+        let inst = step.inst.to_string();
+        writeln!(self.out, "  {:>04}  sp {:<3} {inst:<24} stack: {stack}", step.pc, step.sp)?;
+
+        if self.mode == Mode::Break(step.pc) {
+            self.mode = Mode::Step;
+        }
+        if self.mode != Mode::Step {
+            return Ok(true);
+        }
+
+        // 入力中の現在位置を示す
+        let text: String = self.line.iter().collect();
+        writeln!(self.out, "        {}", text.escape_debug())?;
+        let col: usize = self.line[..step.sp].iter().map(|c| c.escape_debug().count()).sum();
+        writeln!(self.out, "        {}^", " ".repeat(col))?;
+
+        loop {
+            write!(self.out, "(debug) ")?;
+            self.out.flush()?;
+            let mut command = String::new();
+            if self.input.read_line(&mut command)? == 0 {
+                self.mode = Mode::Continue;
+                return Ok(true);
+            }
+            match command.split_whitespace().collect::<Vec<_>>().as_slice() {
+                [] | ["s"] => return Ok(true),
+                ["c"] => {
+                    self.mode = Mode::Continue;
+                    return Ok(true);
+                }
+                ["b", pc] if pc.parse::<usize>().is_ok() => {
+                    self.mode = Mode::Break(pc.parse().unwrap());
+                    return Ok(true);
+                }
+                ["q"] => {
+                    self.is_quit = true;
+                    return Ok(false);
+                }
+                _ => writeln!(self.out, "commands: s (step), c (continue), b PC (break at PC), q (quit)")?,
+            }
+        }
+    }
+}
+
+/// 命令列とトレースを書き出し、マッチしたかを返す
+fn debug<R: BufRead, W: Write>(
+    program: &Program,
+    line: &str,
+    is_interactive: bool,
+    input: &mut R,
+    out: &mut W,
+) -> Result<bool, DynError> {
+    writeln!(out, "code:")?;
+    for (n, c) in program.code.iter().enumerate() {
+        writeln!(out, "{:>04}: {c}", n)?;
+    }
+
+    let chars: Vec<char> = line.chars().collect();
+    let mut debugger = Debugger {
+        line: &chars,
+        input,
+        out,
+        mode: if is_interactive { Mode::Step } else { Mode::Continue },
+        start: None,
+        n_steps: 0,
+        n_backtracks: 0,
+        is_quit: false,
+        error: None,
+    };
+    let result = program.trace_at(&chars, 0, |step| debugger.on_step(step));
+    if let Some(e) = debugger.error {
+        return Err(e.into());
+    }
+    if debugger.is_quit {
+        return Ok(false);
+    }
+
+    let out = debugger.out;
+    writeln!(out)?;
+    let is_match = match result? {
+        Some((begin, end)) => {
+            let s: String = chars[begin..end].iter().collect();
+            writeln!(out, "match at {begin}..{end}: {s:?}")?;
+            true
+        }
+        None => {
+            writeln!(out, "no match")?;
+            false
+        }
+    };
+    writeln!(out, "steps: {}, backtracks: {}", debugger.n_steps, debugger.n_backtracks)?;
+    Ok(is_match)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::debug;
+    use regex::Program;
+
+    fn run(expr: &str, line: &str, input: Option<&str>) -> (String, bool) {
+        let program = Program::new(expr, false).unwrap();
+        let mut out = Vec::new();
+        let is_match = debug(&program, line, input.is_some(), &mut input.unwrap_or("").as_bytes(), &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), is_match)
+    }
+
+    #[test]
+    fn test_trace() {
+        let (out, is_match) = run("ab|c", "c", None);
+        assert!(is_match);
+        assert_eq!(
+            out,
+            "\
+code:
+0000: split 0001, 0004
+0001: char a
+0002: char b
+0003: Jump 0005
+0004: char c
+0005: match 0
+
+try at 0
+  0000  sp 0   split 0001, 0004         stack: -
+  0001  sp 0   char a                   stack: (0004, 0)
+backtrack to 0004 at sp 0
+  0004  sp 0   char c                   stack: -
+  0005  sp 1   match 0                  stack: -
+
+match at 0..1: \"c\"
+steps: 4, backtracks: 1
+"
+        );
+
+        let (out, is_match) = run("ab", "xa", None);
+        assert!(!is_match);
+        assert!(out.contains("\ntry at 1\n") && out.contains("\ntry at 2\n"));
+        assert!(out.ends_with("no match\nsteps: 4, backtracks: 0\n"));
+
+        // 空文字列にマッチする繰り返しの本体は、入力を消費しなければ失敗して戻る
+        let (out, is_match) = run("(a?b?)*c", "xc", None);
+        assert!(is_match);
+        assert!(out.contains("\nmatch at 1..2: \"c\"\n"));
+    }
+
+    #[test]
+    fn test_interactive() {
+        // 1命令進めてから、pc 4で止まり、続きを実行する
+        let (out, is_match) = run("ab|c", "c", Some("s\nb 4\nx\nc\n"));
+        assert!(is_match);
+        assert_eq!(out.matches("(debug) ").count(), 4);
+        assert!(out.contains("  0004  sp 0   char c                   stack: -\n        c\n        ^\n(debug) "));
+        assert!(out.contains("commands: s (step)"));
+
+        // qで中断する
+        let (out, is_match) = run("ab|c", "c", Some("q\n"));
+        assert!(!is_match);
+        assert!(!out.contains("match at"));
+    }
+}
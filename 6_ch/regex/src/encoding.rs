@@ -0,0 +1,358 @@
+//! --encoding: Shift_JIS、EUC-JP、UTF-16の入力を検索前にUTF-8へ変換する
+//!
+//! 変換できないバイトは私用面の文字U+10FF00〜U+10FFFFに1バイトずつ退避し、
+//! 出力時に元のバイトへ戻す。そのため、不正なバイトを含む行もそのまま書き出せる。
+//! 入力に実際に含まれるU+10FF00〜U+10FFFFの文字も、UTF-8の各バイトを退避しておき、
+//! 退避した文字と取り違えずに書き出す。
+//! Shift_JISとEUC-JPの対応はWHATWG Encoding Standardと同じく、Windows-31Jの表を共用する
+mod jis0208;
+
+use std::{borrow::Cow, collections::HashMap, sync::OnceLock};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    ShiftJis,
+    EucJp,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// --encodingに指定できる名前。エラーメッセージに使う
+pub const NAMES: &str = "utf-8, shift_jis (sjis, cp932), euc-jp, utf-16le, utf-16be, utf-16";
+
+/// 1区の点の数
+const CELLS: usize = 94;
+
+/// 利用者外字領域 (95〜114区) の先頭の位置と、対応する私用領域の文字
+const USER_DEFINED: (usize, u32) = (94 * CELLS, 0xE000);
+const USER_DEFINED_END: usize = 114 * CELLS;
+
+/// 変換できないバイトを退避する文字の先頭
+const ESCAPE: u32 = 0x10FF00;
+
+impl Encoding {
+    /// 名前からエンコーディングを求める。大文字と小文字、'-'と'_'は区別しない
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase().replace('_', "-");
+        match name.as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "shift-jis" | "sjis" | "cp932" | "windows-31j" => Some(Encoding::ShiftJis),
+            "euc-jp" | "eucjp" => Some(Encoding::EucJp),
+            // BOMの無いUTF-16はリトルエンディアンとみなす
+            "utf-16" | "utf-16le" => Some(Encoding::Utf16Le),
+            "utf-16be" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    /// 先頭のBOMからエンコーディングを判定し、BOMのバイト数と共に返す
+    pub fn detect_bom(bytes: &[u8]) -> Option<(Self, usize)> {
+        match bytes {
+            [0xEF, 0xBB, 0xBF, ..] => Some((Encoding::Utf8, 3)),
+            [0xFF, 0xFE, ..] => Some((Encoding::Utf16Le, 2)),
+            [0xFE, 0xFF, ..] => Some((Encoding::Utf16Be, 2)),
+            _ => None,
+        }
+    }
+
+    /// バイト列を文字列に変換する。UTF-8の場合は不正なバイトも退避する
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => decode_utf8(bytes),
+            Encoding::ShiftJis => decode_sjis(bytes),
+            Encoding::EucJp => decode_euc_jp(bytes),
+            Encoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+        }
+    }
+
+    /// decodeで得た文字列を出力用のバイト列に戻す
+    ///
+    /// UTF-16の入力は端末などで扱いやすいようUTF-8で書き出す。
+    /// 対応の無い文字は'?'とする
+    pub fn encode(self, s: &str) -> Cow<'_, [u8]> {
+        if s.is_ascii() {
+            return Cow::Borrowed(s.as_bytes());
+        }
+
+        let mut out = Vec::with_capacity(s.len());
+        for c in s.chars() {
+            if let Some(b) = escaped(c) {
+                out.push(b);
+                continue;
+            }
+            match self {
+                Encoding::Utf8 | Encoding::Utf16Le | Encoding::Utf16Be => {
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes())
+                }
+                Encoding::ShiftJis => encode_sjis(c, &mut out),
+                Encoding::EucJp => encode_euc_jp(c, &mut out),
+            }
+        }
+        Cow::Owned(out)
+    }
+}
+
+/// バイトbを退避した文字
+fn escape(b: u8) -> char {
+    char::from_u32(ESCAPE + b as u32).unwrap()
+}
+
+/// 退避された文字であれば元のバイトを返す
+fn escaped(c: char) -> Option<u8> {
+    (c as u32).checked_sub(ESCAPE).map(|b| b as u8)
+}
+
+/// 入力から読んだ文字cを加える。退避に使う文字はUTF-8のバイトごとに退避する
+fn push_decoded(out: &mut String, c: char) {
+    if escaped(c).is_some() {
+        out.extend(c.encode_utf8(&mut [0; 4]).bytes().map(escape));
+    } else {
+        out.push(c);
+    }
+}
+
+/// 区点を0から数えた位置 ((区 - 1) * 94 + 点 - 1) で引く表
+fn table() -> &'static [char] {
+    static TABLE: OnceLock<Vec<char>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = Vec::with_capacity(jis0208::ROWS.len() * CELLS);
+        for row in jis0208::ROWS {
+            if row.is_empty() {
+                table.extend([char::REPLACEMENT_CHARACTER; CELLS]);
+            } else {
+                table.extend(row.chars());
+            }
+        }
+        for (i, c) in table[USER_DEFINED.0..USER_DEFINED_END].iter_mut().enumerate() {
+            *c = char::from_u32(USER_DEFINED.1 + i as u32).unwrap();
+        }
+        table
+    })
+}
+
+fn lookup(pointer: usize) -> Option<char> {
+    table().get(pointer).copied().filter(|c| *c != char::REPLACEMENT_CHARACTER)
+}
+
+/// 文字から位置への逆引き。同じ文字が複数の位置にある場合は先の位置を使う
+///
+/// Shift_JISではNEC選定IBM拡張文字 (89〜92区) を使わず、IBM拡張文字の方で書き出す
+fn reverse(is_sjis: bool) -> &'static HashMap<char, usize> {
+    static SJIS: OnceLock<HashMap<char, usize>> = OnceLock::new();
+    static EUC_JP: OnceLock<HashMap<char, usize>> = OnceLock::new();
+    let (map, range) = if is_sjis {
+        (&SJIS, 0..table().len())
+    } else {
+        (&EUC_JP, 0..USER_DEFINED.0)
+    };
+    map.get_or_init(|| {
+        let mut map = HashMap::new();
+        for pointer in range {
+            if is_sjis && (88 * CELLS..92 * CELLS).contains(&pointer) {
+                continue;
+            }
+            if let Some(c) = lookup(pointer) {
+                map.entry(c).or_insert(pointer);
+            }
+        }
+        map
+    })
+}
+
+fn decode_utf8(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        chunk.valid().chars().for_each(|c| push_decoded(&mut out, c));
+        out.extend(chunk.invalid().iter().map(|b| escape(*b)));
+    }
+    out
+}
+
+/// 半角カナ (0xA1〜0xDF) の文字
+fn half_width_kana(b: u8) -> char {
+    char::from_u32(0xFF61 + (b - 0xA1) as u32).unwrap()
+}
+
+fn decode_sjis(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        match b {
+            0x00..=0x7F => out.push(b as char),
+            0xA1..=0xDF => out.push(half_width_kana(b)),
+            0x81..=0x9F | 0xE0..=0xFC => {
+                let Some(&t @ (0x40..=0x7E | 0x80..=0xFC)) = bytes.get(i) else {
+                    // 2バイト目として不正なバイトは、改めて1バイト目として読む
+                    out.push(escape(b));
+                    continue;
+                };
+                i += 1;
+                let row = (b as usize - if b < 0xA0 { 0x81 } else { 0xC1 }) * 2;
+                let pointer = if t >= 0x9F {
+                    (row + 1) * CELLS + t as usize - 0x9F
+                } else {
+                    row * CELLS + t as usize - if t < 0x80 { 0x40 } else { 0x41 }
+                };
+                match lookup(pointer) {
+                    Some(c) => out.push(c),
+                    None => out.extend([escape(b), escape(t)]),
+                }
+            }
+            _ => out.push(escape(b)),
+        }
+    }
+    out
+}
+
+fn encode_sjis(c: char, out: &mut Vec<u8>) {
+    if c.is_ascii() {
+        return out.push(c as u8);
+    }
+    if ('\u{FF61}'..='\u{FF9F}').contains(&c) {
+        return out.push((c as u32 - 0xFF61 + 0xA1) as u8);
+    }
+    let Some(&pointer) = reverse(true).get(&c) else {
+        return out.push(b'?');
+    };
+    let (row, cell) = (pointer / CELLS, pointer % CELLS);
+    let lead = row / 2 + if row < 62 { 0x81 } else { 0xC1 };
+    let trail = if row % 2 == 1 {
+        cell + 0x9F
+    } else if cell < 63 {
+        cell + 0x40
+    } else {
+        cell + 0x41
+    };
+    out.extend([lead as u8, trail as u8]);
+}
+
+fn decode_euc_jp(bytes: &[u8]) -> String {
+    let is_trail = |b: Option<&u8>| b.is_some_and(|b| (0xA1..=0xFE).contains(b));
+    let mut out = String::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        match b {
+            0x00..=0x7F => out.push(b as char),
+            0x8E if bytes.get(i).is_some_and(|t| (0xA1..=0xDF).contains(t)) => {
+                out.push(half_width_kana(bytes[i]));
+                i += 1;
+            }
+            // JIS X 0212の3バイトの文字には対応しないので、まとめて退避する
+            0x8F if is_trail(bytes.get(i)) && is_trail(bytes.get(i + 1)) => {
+                out.extend(bytes[i - 1..i + 2].iter().map(|b| escape(*b)));
+                i += 2;
+            }
+            0xA1..=0xFE if is_trail(bytes.get(i)) => {
+                let t = bytes[i];
+                i += 1;
+                match lookup((b as usize - 0xA1) * CELLS + t as usize - 0xA1) {
+                    Some(c) => out.push(c),
+                    None => out.extend([escape(b), escape(t)]),
+                }
+            }
+            _ => out.push(escape(b)),
+        }
+    }
+    out
+}
+
+fn encode_euc_jp(c: char, out: &mut Vec<u8>) {
+    if c.is_ascii() {
+        return out.push(c as u8);
+    }
+    if ('\u{FF61}'..='\u{FF9F}').contains(&c) {
+        return out.extend([0x8E, (c as u32 - 0xFF61 + 0xA1) as u8]);
+    }
+    match reverse(false).get(&c) {
+        Some(&pointer) => out.extend([(pointer / CELLS + 0xA1) as u8, (pointer % CELLS + 0xA1) as u8]),
+        None => out.push(b'?'),
+    }
+}
+
+/// 対になっていないサロゲートと、末尾の半端な1バイトはU+FFFDとする
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> String {
+    let units = bytes.chunks_exact(2).map(|b| from_bytes([b[0], b[1]]));
+    let mut out = String::with_capacity(bytes.len());
+    for c in char::decode_utf16(units) {
+        push_decoded(&mut out, c.unwrap_or(char::REPLACEMENT_CHARACTER));
+    }
+    if bytes.len() % 2 == 1 {
+        out.push(char::REPLACEMENT_CHARACTER);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Encoding::from_name("Shift_JIS"), Some(Encoding::ShiftJis));
+        assert_eq!(Encoding::from_name("CP932"), Some(Encoding::ShiftJis));
+        assert_eq!(Encoding::from_name("euc_jp"), Some(Encoding::EucJp));
+        assert_eq!(Encoding::from_name("utf-16"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::from_name("latin1"), None);
+
+        assert_eq!(Encoding::detect_bom(b"\xEF\xBB\xBFa"), Some((Encoding::Utf8, 3)));
+        assert_eq!(Encoding::detect_bom(b"\xFE\xFF\x00a"), Some((Encoding::Utf16Be, 2)));
+        assert_eq!(Encoding::detect_bom(b"\x82\xa0"), None);
+    }
+
+    #[test]
+    fn test_sjis() {
+        let bytes = b"a\x82\xa0\x81\x60\xb1\x88\x9f\xfa\x40\xf0\x40\r\n";
+        let s = Encoding::ShiftJis.decode(bytes);
+        assert_eq!(s, "aあ～ｱ亜ⅰ\u{E000}\r\n");
+        assert_eq!(Encoding::ShiftJis.encode(&s), &bytes[..]);
+
+        // NEC選定IBM拡張文字はIBM拡張文字の符号で書き出す
+        assert_eq!(Encoding::ShiftJis.decode(b"\xed\x40"), "纊");
+        assert_eq!(Encoding::ShiftJis.encode("纊"), &b"\xfa\x5c"[..]);
+        assert_eq!(Encoding::ShiftJis.encode("é"), &b"?"[..]);
+
+        // 不正なバイトは元のバイトのまま書き出す
+        let bytes = b"\x80a\x82\xa0\x82\x20\x85\x40\xff\x82";
+        let s = Encoding::ShiftJis.decode(bytes);
+        assert_eq!(s.chars().filter(|c| *c == 'a' || *c == 'あ' || *c == ' ').count(), 3);
+        assert_eq!(Encoding::ShiftJis.encode(&s), &bytes[..]);
+    }
+
+    #[test]
+    fn test_euc_jp() {
+        let bytes = b"a\xa4\xa2\xa1\xc1\x8e\xb1\xb0\xa1\x8f\xb0\xa1\xa9\xa1\xff";
+        let s = Encoding::EucJp.decode(bytes);
+        assert!(s.starts_with("aあ～ｱ亜"), "{s}");
+        assert_eq!(Encoding::EucJp.encode(&s), &bytes[..]);
+        assert_eq!(Encoding::EucJp.encode("\u{E000}"), &b"?"[..]);
+    }
+
+    #[test]
+    fn test_utf16() {
+        assert_eq!(Encoding::Utf16Le.decode(b"a\x00B0\x3d\xd8\x00\xde"), "a\u{3042}\u{1F600}");
+        assert_eq!(Encoding::Utf16Be.decode(b"\x00a\xd8\x00\x00b\x00"), "a\u{FFFD}b\u{FFFD}");
+        assert_eq!(Encoding::Utf16Le.encode("a\u{3042}"), "a\u{3042}".as_bytes());
+        assert_eq!(Encoding::Utf8.decode(b"a\xffb"), "a\u{10FFFF}b");
+        assert_eq!(Encoding::Utf8.encode("a\u{10FFFF}b"), &b"a\xffb"[..]);
+    }
+
+    /// 入力に実際にある私用面の文字は、不正なバイトと取り違えない
+    #[test]
+    fn test_private_use() {
+        let bytes = "a\u{10FF41}\u{10FFFF}\u{10FEFF}b".as_bytes();
+        let s = Encoding::Utf8.decode(bytes);
+        assert!(!s.contains('\u{10FF41}') && s.contains('\u{10FEFF}'), "{s:?}");
+        assert_eq!(Encoding::Utf8.encode(&s), bytes);
+
+        // UTF-16の入力はUTF-8で書き出す
+        let s = Encoding::Utf16Le.decode(b"a\x00\xff\xdb\x41\xdf");
+        assert_eq!(Encoding::Utf16Le.encode(&s), "a\u{10FF41}".as_bytes());
+    }
+}
@@ -1,25 +1,123 @@
 mod codegen;
+mod derivative;
+mod dfa;
+mod dot;
+mod enumerate;
 mod evaluator;
+mod flags;
+mod generate;
+mod optimizer;
 mod parser;
+mod printer;
+mod profile;
+mod program;
+mod rustgen;
+mod set;
+mod simplifier;
+mod stream;
+mod table;
 
 use crate::helper::DynError;
-use std::fmt::{self, Display};
 
-#[derive(Debug)]
+pub use derivative::DerivativeMatcher;
+pub use dfa::{Alphabet, Comparison, Dfa, DfaError};
+pub use enumerate::Enumerate;
+pub use evaluator::Step;
+pub use flags::Flags;
+pub use generate::{Generator, Rng, DEFAULT_MAX_REPEAT};
+pub use parser::{is_meta, parse, parse_boolean, ParseError, Span, AST};
+pub use printer::{to_pattern, PrintError};
+pub use profile::Profile;
+pub use simplifier::simplify;
+pub use program::{Captures, LoadError, Program, StaticProgram, PROGRAM_VERSION};
+pub use set::RegexSet;
+pub use stream::{StreamError, StreamMatcher, StreamMatches};
+pub use table::DenseTable;
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::Path,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
     Char(char),
-    Match,
+    Str(Vec<char>),
+    Class(Vec<char>),
+    Any(bool), // 任意の1文字。falseの場合は改行を除く
+    Match(usize), // RegexSetではどのパターンにマッチしたかを表す
     Jump(usize),
     Split(usize, usize),
+    Assert(Assertion),
+    Save(usize), // 現在位置をキャプチャのスロットに記録する。グループnの開始は2n、終了は2n+1
+}
+
+/// 文字を消費せず、現在位置の前後のみを検査する
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assertion {
+    TextStart,    // 入力の先頭
+    TextEnd,      // 入力の末尾
+    NoWordBefore, // 直前が単語構成文字でない
+    NoWordAfter,  // 直後が単語構成文字でない
+    LineStart,    // 入力の先頭か改行の直後 ((?m)の^)
+    LineEnd,      // 入力の末尾か改行の直前 ((?m)の$)
+}
+
+impl Assertion {
+    pub fn is_match(&self, line: &[char], sp: usize) -> bool {
+        let prev = sp.checked_sub(1).and_then(|i| line.get(i)).copied();
+        self.is_match_between(prev, line.get(sp).copied())
+    }
+
+    /// 直前の文字prevと直後の文字nextの間で成り立つか。Noneは入力の先頭・末尾を表す
+    pub fn is_match_between(&self, prev: Option<char>, next: Option<char>) -> bool {
+        match self {
+            Assertion::TextStart    => prev.is_none(),
+            Assertion::TextEnd      => next.is_none(),
+            Assertion::NoWordBefore => !prev.is_some_and(is_word_char),
+            Assertion::NoWordAfter  => !next.is_some_and(is_word_char),
+            Assertion::LineStart    => prev.is_none_or(|c| c == '\n'),
+            Assertion::LineEnd      => next.is_none_or(|c| c == '\n'),
+        }
+    }
+}
+
+impl Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Assertion::TextStart    => write!(f, "text_start"),
+            Assertion::TextEnd      => write!(f, "text_end"),
+            Assertion::NoWordBefore => write!(f, "no_word_before"),
+            Assertion::NoWordAfter  => write!(f, "no_word_after"),
+            Assertion::LineStart    => write!(f, "line_start"),
+            Assertion::LineEnd      => write!(f, "line_end"),
+        }
+    }
+}
+
+/// Instruction::Anyが文字cにマッチするか
+pub fn is_any_match(is_newline: bool, c: char) -> bool {
+    is_newline || c != '\n'
+}
+
+/// 単語を構成する文字か。grep -wと同じく英数字とアンダースコア
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
 }
 
 impl Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Instruction::Char(c)                => write!(f, "char {}", c),
-            Instruction::Match                  => write!(f, "match"),
+            Instruction::Str(s)                 => write!(f, "str {}", s.iter().collect::<String>()),
+            Instruction::Class(cs)              => write!(f, "class [{}]", cs.iter().collect::<String>()),
+            Instruction::Any(false)             => write!(f, "any"),
+            Instruction::Any(true)              => write!(f, "any_newline"),
+            Instruction::Match(id)              => write!(f, "match {}", id),
             Instruction::Jump(addr)             => write!(f, "Jump {:>04}", addr),
             Instruction::Split(addr1, addr2)    => write!(f, "split {:>04}, {:>04}", addr1, addr2),
+            Instruction::Assert(a)              => write!(f, "assert {}", a),
+            Instruction::Save(slot)             => write!(f, "save {}", slot),
         }
     }
 }
@@ -28,10 +126,11 @@ pub fn print(expr: &str) -> Result<(), DynError> {
     println!("expr: {expr}");
     let ast = parser::parse(expr)?;
     println!("AST: {:?}", ast);
+    println!("simplified: {:?}", simplifier::simplify(&ast));
 
     println!();
     println!("code:");
-    let code = codegen::get_code(&ast)?;
+    let code = compile(expr, true)?;
     for (n, c) in code.iter().enumerate() {
         println!("{:>04}: {c}", n);
     }
@@ -39,9 +138,66 @@ pub fn print(expr: &str) -> Result<(), DynError> {
     Ok(())
 }
 
+/// 正規化後のASTをGraphvizのDOT形式で返す
+pub fn ast_dot(expr: &str) -> Result<String, DynError> {
+    let ast = simplifier::simplify(&parser::parse(expr)?);
+    Ok(dot::ast_to_dot(&ast))
+}
+
+/// コンパイルした命令列をGraphvizのDOT形式で返す
+pub fn code_dot(expr: &str, is_optimize: bool) -> Result<String, DynError> {
+    let code = compile(expr, is_optimize)?;
+    Ok(dot::code_to_dot(&code))
+}
+
+/// 正規化した式を、最小限の括弧で表した文字列として返す
+pub fn normalize(expr: &str) -> Result<String, DynError> {
+    let ast = simplifier::simplify(&parser::parse(expr)?);
+    Ok(printer::to_pattern(&ast)?)
+}
+
+/// 2つの式の言語 (文字列全体がマッチする文字列の集合) を比べる
+///
+/// 等価でなければ、一方だけがマッチする最短の文字列が反例として得られる
+pub fn compare(left: &str, right: &str, flags: Flags) -> Result<Comparison, DynError> {
+    let left = Dfa::with_flags(left, flags)?;
+    let right = Dfa::with_flags(right, flags)?;
+    Ok(Comparison::new(&left, &right)?)
+}
+
+/// 式にマッチするか判定する`pub fn {name}(line: &str) -> bool`のRustソースコードを返す
+///
+/// 生成される関数はdo_matching(expr, line, true)と同じ結果を返す
+pub fn gen_matcher(expr: &str, name: &str) -> Result<String, DynError> {
+    if !rustgen::is_ident(name) {
+        return Err(format!("invalid function name: {name}").into());
+    }
+    // 先頭から始まるマッチがあるかは、後ろに(?s).*を付けた式が文字列全体にマッチするかと同じ
+    let ast = AST::Seq(vec![parser::parse(expr)?, AST::Star(Box::new(AST::Any(true)))]);
+    let table = DenseTable::new(&Dfa::from_ast(&ast)?);
+    Ok(rustgen::gen_matcher(&table, expr, name))
+}
+
+/// gen_matcherの結果をファイルに書き出す。build.rsから使う
+pub fn write_matcher<P: AsRef<Path>>(path: P, name: &str, expr: &str) -> Result<(), DynError> {
+    fs::write(path, gen_matcher(expr, name)?)?;
+    Ok(())
+}
+
+/// 正規表現をコンパイルして命令列を返す
+///
+/// is_optimizeがfalseの場合、のぞき穴最適化を行わずにcodegenの出力をそのまま返す
+pub fn compile(expr: &str, is_optimize: bool) -> Result<Vec<Instruction>, DynError> {
+    Ok(Program::new(expr, is_optimize)?.code)
+}
+
 pub fn do_matching(expr: &str, line: &str, is_depth: bool) -> Result<bool,DynError> {
-    let ast = parser::parse(expr)?;
-    let code = codegen::get_code(&ast)?;
+    let code = compile(expr, true)?;
+    do_matching_code(&code, line, is_depth)
+}
+
+/// コンパイル済みの命令列でマッチングを行う
+pub fn do_matching_code(code: &[Instruction], line: &str, is_depth: bool) -> Result<bool, DynError> {
     let line = line.chars().collect::<Vec<char>>();
-    Ok(evaluator::eval(&code, &line, is_depth)?)
-}
\ No newline at end of file
+    Ok(evaluator::eval(code, &line, is_depth)?)
+}
@@ -1,4 +1,4 @@
-use super::{parser::AST, Instruction};
+use super::{parser::AST, Assertion, Instruction};
 use crate::helper::safe_add;
 use std::{
     error::Error,
@@ -11,6 +11,8 @@ pub enum CodeGenError {
     FailStar,
     FailOr,
     FailQuestion,
+    FailSet,
+    Unsupported, // &、~は命令列で表せない
 }
 
 impl Display for CodeGenError {
@@ -29,39 +31,75 @@ struct Generator {
 
 pub fn get_code(ast: &AST) -> Result<Vec<Instruction>, CodeGenError> {
     let mut generator = Generator::default();
-    generator.gen_code(ast)?;
+    generator.gen_code(ast, 0)?;
+    Ok(generator.insts)
+}
+
+/// 複数のASTを1つのプログラムにまとめる。i番目のASTはmatch iで終わる
+///
+/// ```text
+///     split L0, S1
+/// S1: split L1, S2
+///     ...
+/// L0: asts[0]のコード
+///     match 0
+/// L1: asts[1]のコード
+///     match 1
+///     ...
+/// ```
+pub fn get_code_set(asts: &[AST]) -> Result<Vec<Instruction>, CodeGenError> {
+    let mut generator = Generator::default();
+    let mut splits = Vec::new();
+    for _ in 1..asts.len() {
+        let split_addr = generator.pc;
+        generator.inc_pc()?;
+        generator.insts.push(Instruction::Split(0, generator.pc));
+        splits.push(split_addr);
+    }
+
+    for (id, ast) in asts.iter().enumerate() {
+        // 最後のパターンは直前のsplitの第2引数から落ちてくる
+        if let Some(split_addr) = splits.get(id) {
+            if let Some(Instruction::Split(l1, _)) = generator.insts.get_mut(*split_addr) {
+                *l1 = generator.pc;
+            } else {
+                return Err(CodeGenError::FailSet);
+            }
+        } else if let Some(split_addr) = splits.last() {
+            if let Some(Instruction::Split(_, l2)) = generator.insts.get_mut(*split_addr) {
+                *l2 = generator.pc;
+            } else {
+                return Err(CodeGenError::FailSet);
+            }
+        }
+        generator.gen_code(ast, id)?;
+    }
+
     Ok(generator.insts)
 }
 
 
 impl Generator {
-    fn gen_code(&mut self, ast: &AST) -> Result<(), CodeGenError> {
+    fn gen_code(&mut self, ast: &AST, id: usize) -> Result<(), CodeGenError> {
         self.gen_expr(ast)?;
         self.inc_pc()?;
-        self.insts.push(Instruction::Match);
+        self.insts.push(Instruction::Match(id));
         Ok(())
     }
 
     fn gen_expr(&mut self, ast: &AST) -> Result<(), CodeGenError> {
         match ast {
             AST::Char(c)            =>      self.gen_char(*c)?,
+            AST::Class(cs)          =>      self.gen_class(cs)?,
+            AST::Any(is_newline)    =>      self.gen_any(*is_newline)?,
+            AST::Assert(a)          =>      self.gen_assert(*a)?,
             AST::Or(e1, e2)         =>      self.gen_or(e1, e2)?,
-            AST::Plus(e1)           =>      self.gen_code(e1)?,
-            AST::Star(e1)           =>      {
-                match &**e1 {
-                    AST::Star(_)    =>  self.gen_expr(&e1)?,
-                    AST::Seq(e2) if e2.len() == 1 => {
-                        if let Some(e3 @ AST::Star(_)) = e2.get(0) {
-                            self.gen_expr(e3)?
-                        } else {
-                            self.gen_star(e1)?
-                        }
-                    }
-                    e => self.gen_star(&e)?,
-                }
-            }
+            AST::Plus(e1)           =>      self.gen_plus(e1)?,
+            AST::Star(e1)           =>      self.gen_star(e1)?,
             AST::Question(e) => self.gen_question(e)?,
             AST::Seq(v) => self.gen_seq(v)?,    
+            AST::Capture(i, _, e) => self.gen_capture(*i, e)?,
+            AST::And(..) | AST::Not(_) => return Err(CodeGenError::Unsupported),
         }
         Ok(())
     }
@@ -73,6 +111,25 @@ impl Generator {
         Ok(())
     }
 
+    fn gen_class(&mut self, cs: &[char]) -> Result<(), CodeGenError> {
+        let inst = Instruction::Class(cs.to_vec());
+        self.insts.push(inst);
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    fn gen_any(&mut self, is_newline: bool) -> Result<(), CodeGenError> {
+        self.insts.push(Instruction::Any(is_newline));
+        self.inc_pc()?;
+        Ok(())
+    }
+
+    fn gen_assert(&mut self, a: Assertion) -> Result<(), CodeGenError> {
+        self.insts.push(Instruction::Assert(a));
+        self.inc_pc()?;
+        Ok(())
+    }
+
     fn gen_or(&mut self, e1: &AST, e2: &AST) -> Result<(), CodeGenError> {
         // split L1, L2
         let split_addr = self.pc;
@@ -159,6 +216,16 @@ impl Generator {
         }
     }
 
+    /// save 2i; eのコード; save 2i+1
+    fn gen_capture(&mut self, i: usize, e: &AST) -> Result<(), CodeGenError> {
+        self.insts.push(Instruction::Save(2 * i));
+        self.inc_pc()?;
+        self.gen_expr(e)?;
+        self.insts.push(Instruction::Save(2 * i + 1));
+        self.inc_pc()?;
+        Ok(())
+    }
+
     fn gen_seq(&mut self, exprs: &[AST]) -> Result<(), CodeGenError> {
         for e in exprs {
             self.gen_expr(e)?;
@@ -0,0 +1,381 @@
+//! Brzozowskiの微分による照合
+//!
+//! 式rの文字cによる微分D_c(r)は、rにマッチする文字列のうちcで始まるものからcを
+//! 取り除いた集合を表す式。入力の各文字で順に微分し、最後の式が空文字列に
+//! マッチするかで、文字列全体がマッチするかを判定する (Dfaと同じ意味)。
+//!
+//! 微分は積 (`&`) と補集合 (`~`) にもそのまま分配できるため、命令列では表せない
+//! これらの演算子を扱える。D_c(r&s) = D_c(r)&D_c(s)、D_c(~r) = ~D_c(r)。
+//!
+//! 式は項として共有し、スマートコンストラクタで`|`と`&`の結合・交換・冪等と、
+//! ∅、ε、`~∅` (全ての文字列) に関する簡約を行う。これにより微分の種類が有限になり、
+//! 微分は (項, 直前の文字の種類, 文字) ごとにメモ化する。
+//!
+//! アサーションは直前と直後の文字で決まるため、空文字列へのマッチの判定は
+//! 直前の文字の種類と直後の文字を受け取る。
+use super::{flags, is_word_char, parser, parser::AST, Assertion, Flags};
+use crate::helper::DynError;
+use std::collections::HashMap;
+
+/// 項の番号
+type Id = usize;
+
+const EMPTY: Id = 0; // ∅。何にもマッチしない
+const EPSILON: Id = 1; // 空文字列にのみマッチする
+const ALL: Id = 2; // ~∅。全ての文字列にマッチする
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Term {
+    Empty,
+    Epsilon,
+    Chars(Vec<char>, bool), // 1文字。trueの場合は列挙した文字以外にマッチする
+    Assert(Assertion),
+    Seq(Id, Id),  // 左が連接でないよう右に寄せる
+    Or(Vec<Id>),  // 2つ以上。昇順で重複なし
+    And(Vec<Id>), // 2つ以上。昇順で重複なし
+    Not(Id),
+    Star(Id),
+}
+
+/// 直前の文字の種類。アサーションの判定にはこれだけで足りる
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Prev {
+    Start,
+    Newline,
+    Word,
+    Other,
+}
+
+impl Prev {
+    fn new(prev: Option<char>) -> Self {
+        match prev {
+            None => Prev::Start,
+            Some('\n') => Prev::Newline,
+            Some(c) if is_word_char(c) => Prev::Word,
+            Some(_) => Prev::Other,
+        }
+    }
+
+    /// この種類を代表する文字
+    fn example(self) -> Option<char> {
+        match self {
+            Prev::Start => None,
+            Prev::Newline => Some('\n'),
+            Prev::Word => Some('a'),
+            Prev::Other => Some(' '),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DerivativeMatcher {
+    terms: Vec<Term>,
+    ids: HashMap<Term, Id>,
+    start: Id,
+    memo: HashMap<(Id, Prev, char), Id>,
+}
+
+impl DerivativeMatcher {
+    /// parser::parse_booleanで式を解析する。`&`と`~`を使える
+    pub fn new(expr: &str) -> Result<Self, DynError> {
+        Self::with_flags(expr, Flags::default())
+    }
+
+    pub fn with_flags(expr: &str, flags: Flags) -> Result<Self, DynError> {
+        let ast = flags::apply(parser::parse_boolean(expr)?, &flags);
+        Ok(Self::from_ast(&ast))
+    }
+
+    pub fn from_ast(ast: &AST) -> Self {
+        let mut m = DerivativeMatcher { terms: Vec::new(), ids: HashMap::new(), start: EMPTY, memo: HashMap::new() };
+        m.intern(Term::Empty);
+        m.intern(Term::Epsilon);
+        m.intern(Term::Not(EMPTY));
+        m.start = m.build(ast);
+        m
+    }
+
+    /// 文字列全体がマッチするか
+    pub fn is_match(&mut self, s: &str) -> bool {
+        let mut id = self.start;
+        let mut prev = None;
+        for c in s.chars() {
+            match id {
+                EMPTY => return false,
+                ALL => return true,
+                _ => (),
+            }
+            id = self.derive(id, Prev::new(prev), c);
+            prev = Some(c);
+        }
+        self.is_nullable(id, Prev::new(prev), None)
+    }
+
+    /// これまでに作った項の数
+    pub fn n_terms(&self) -> usize {
+        self.terms.len()
+    }
+
+    fn intern(&mut self, term: Term) -> Id {
+        if let Some(id) = self.ids.get(&term) {
+            return *id;
+        }
+        let id = self.terms.len();
+        self.terms.push(term.clone());
+        self.ids.insert(term, id);
+        id
+    }
+
+    fn build(&mut self, ast: &AST) -> Id {
+        match ast {
+            AST::Char(c) => self.intern(Term::Chars(vec![*c], false)),
+            AST::Class(cs) => {
+                let mut cs = cs.clone();
+                cs.sort_unstable();
+                cs.dedup();
+                self.intern(Term::Chars(cs, false))
+            }
+            AST::Any(true) => self.intern(Term::Chars(Vec::new(), true)),
+            AST::Any(false) => self.intern(Term::Chars(vec!['\n'], true)),
+            AST::Assert(a) => self.intern(Term::Assert(*a)),
+            AST::Plus(e) => {
+                let e = self.build(e);
+                let star = self.star(e);
+                self.seq(e, star)
+            }
+            AST::Star(e) => {
+                let e = self.build(e);
+                self.star(e)
+            }
+            AST::Question(e) => {
+                let e = self.build(e);
+                self.or(vec![e, EPSILON])
+            }
+            AST::Or(e1, e2) => {
+                let v = vec![self.build(e1), self.build(e2)];
+                self.or(v)
+            }
+            AST::And(e1, e2) => {
+                let v = vec![self.build(e1), self.build(e2)];
+                self.and(v)
+            }
+            AST::Not(e) => {
+                let e = self.build(e);
+                self.not(e)
+            }
+            AST::Seq(v) => v.iter().rev().fold(EPSILON, |acc, e| {
+                let e = self.build(e);
+                self.seq(e, acc)
+            }),
+            AST::Capture(_, _, e) => self.build(e),
+        }
+    }
+
+    fn seq(&mut self, e1: Id, e2: Id) -> Id {
+        match (&self.terms[e1], e2) {
+            (Term::Empty, _) | (_, EMPTY) => EMPTY,
+            (Term::Epsilon, _) => e2,
+            (_, EPSILON) => e1,
+            (Term::Seq(a, b), _) => {
+                let (a, b) = (*a, *b);
+                let rest = self.seq(b, e2);
+                self.seq(a, rest)
+            }
+            _ => self.intern(Term::Seq(e1, e2)),
+        }
+    }
+
+    fn or(&mut self, v: Vec<Id>) -> Id {
+        let mut alts = Vec::new();
+        for id in v {
+            match &self.terms[id] {
+                Term::Or(w) => alts.extend(w),
+                Term::Empty => (),
+                _ => alts.push(id),
+            }
+        }
+        if alts.contains(&ALL) {
+            return ALL;
+        }
+        alts.sort_unstable();
+        alts.dedup();
+        match alts.len() {
+            0 => EMPTY,
+            1 => alts[0],
+            _ => self.intern(Term::Or(alts)),
+        }
+    }
+
+    fn and(&mut self, v: Vec<Id>) -> Id {
+        let mut terms = Vec::new();
+        for id in v {
+            match &self.terms[id] {
+                Term::And(w) => terms.extend(w),
+                _ if id == ALL => (),
+                _ => terms.push(id),
+            }
+        }
+        if terms.contains(&EMPTY) {
+            return EMPTY;
+        }
+        terms.sort_unstable();
+        terms.dedup();
+        match terms.len() {
+            0 => ALL,
+            1 => terms[0],
+            _ => self.intern(Term::And(terms)),
+        }
+    }
+
+    fn not(&mut self, e: Id) -> Id {
+        match &self.terms[e] {
+            Term::Not(e) => *e,
+            _ => self.intern(Term::Not(e)),
+        }
+    }
+
+    fn star(&mut self, e: Id) -> Id {
+        match &self.terms[e] {
+            Term::Empty | Term::Epsilon => EPSILON,
+            Term::Star(_) => e,
+            _ => self.intern(Term::Star(e)),
+        }
+    }
+
+    /// 直前の文字の種類がprev、直後の文字がnextの位置で、空文字列にマッチするか
+    fn is_nullable(&self, id: Id, prev: Prev, next: Option<char>) -> bool {
+        match &self.terms[id] {
+            Term::Empty | Term::Chars(..) => false,
+            Term::Epsilon | Term::Star(_) => true,
+            Term::Assert(a) => a.is_match_between(prev.example(), next),
+            Term::Seq(e1, e2) => self.is_nullable(*e1, prev, next) && self.is_nullable(*e2, prev, next),
+            Term::Or(v) => v.iter().any(|e| self.is_nullable(*e, prev, next)),
+            Term::And(v) => v.iter().all(|e| self.is_nullable(*e, prev, next)),
+            Term::Not(e) => !self.is_nullable(*e, prev, next),
+        }
+    }
+
+    /// 直前の文字の種類がprevの位置での、文字cによる微分
+    fn derive(&mut self, id: Id, prev: Prev, c: char) -> Id {
+        if let Some(d) = self.memo.get(&(id, prev, c)) {
+            return *d;
+        }
+
+        let d = match self.terms[id].clone() {
+            Term::Empty | Term::Epsilon | Term::Assert(_) => EMPTY,
+            Term::Chars(cs, is_negated) => {
+                if cs.binary_search(&c).is_ok() != is_negated {
+                    EPSILON
+                } else {
+                    EMPTY
+                }
+            }
+            Term::Seq(e1, e2) => {
+                // D_c(rs) = D_c(r)s | (rが空文字列にマッチすれば) D_c(s)
+                let d1 = self.derive(e1, prev, c);
+                let left = self.seq(d1, e2);
+                if self.is_nullable(e1, prev, Some(c)) {
+                    let right = self.derive(e2, prev, c);
+                    self.or(vec![left, right])
+                } else {
+                    left
+                }
+            }
+            Term::Or(v) => {
+                let v = v.iter().map(|e| self.derive(*e, prev, c)).collect();
+                self.or(v)
+            }
+            Term::And(v) => {
+                let v = v.iter().map(|e| self.derive(*e, prev, c)).collect();
+                self.and(v)
+            }
+            Term::Not(e) => {
+                let d = self.derive(e, prev, c);
+                self.not(d)
+            }
+            Term::Star(e) => {
+                // D_c(r*) = D_c(r)r*
+                let d = self.derive(e, prev, c);
+                self.seq(d, id)
+            }
+        };
+        self.memo.insert((id, prev, c), d);
+        d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DerivativeMatcher;
+    use crate::engine::{
+        codegen::{self, CodeGenError},
+        parse_boolean, Dfa, DfaError, Flags, Program,
+    };
+
+    fn matches(expr: &str, strings: &[&str]) -> Vec<bool> {
+        let mut m = DerivativeMatcher::new(expr).unwrap();
+        strings.iter().map(|s| m.is_match(s)).collect()
+    }
+
+    #[test]
+    fn test_boolean() {
+        // aが連続しない、aとbからなる文字列
+        let expr = "(a|b)*&~(.*aa.*)";
+        assert_eq!(matches(expr, &["", "abab", "baab", "bba", "abc"]), [true, true, false, true, false]);
+        assert_eq!(matches("~(a*)", &["", "aa", "ab", "b"]), [false, false, true, true]);
+        assert_eq!(matches("a.*&.*b", &["ab", "axb", "ba", "a"]), [true, true, false, false]);
+        assert_eq!(matches("a|b&c", &["a", "b", "c"]), [true, false, false]);
+        assert_eq!(matches("x~y*", &["x", "xy", "xyy", "xz", "xyz"]), [false, false, false, true, true]);
+        assert_eq!(matches("~~ab", &["ab", "a"]), [true, false]);
+        assert_eq!(matches("a\\&b\\~", &["a&b~"]), [true]);
+
+        // 補集合はアサーションを含む場合も文字列全体について取る
+        assert_eq!(matches("~(^a$)", &["a", "", "aa"]), [false, true, true]);
+        assert_eq!(matches("(?ms)~(.*a$.*)", &["ab", "a\nb", "ba"]), [true, false, false]);
+
+        let flags = Flags { is_ignore_case: true, ..Default::default() };
+        let mut m = DerivativeMatcher::with_flags("a.&~(.b)", flags).unwrap();
+        assert_eq!(["ac", "AC", "AB", "ab"].map(|s| m.is_match(s)), [true, true, false, false]);
+
+        // 命令列とDFAでは扱えない
+        let ast = parse_boolean("a&~b").unwrap();
+        assert!(matches!(codegen::get_code(&ast), Err(CodeGenError::Unsupported)));
+        assert!(matches!(Dfa::from_ast(&ast), Err(DfaError::Unsupported)));
+    }
+
+    #[test]
+    fn test_memo() {
+        // 微分の種類は有限で、長い入力でも項は増え続けない
+        let mut m = DerivativeMatcher::new("(a|b)*abb&~(.*bbb.*)").unwrap();
+        assert!(m.is_match(&format!("{}abb", "ba".repeat(10))));
+        assert!(!m.is_match("abbb"));
+        let n = m.n_terms();
+        assert!(m.is_match(&format!("{}abb", "ba".repeat(1000))));
+        assert_eq!(m.n_terms(), n);
+    }
+
+    #[test]
+    fn test_matches_vm() {
+        // 演算子を含まない式では、VMとDFAの判定と一致する
+        let exprs = [
+            "a(b|c)*d", "(ab|a)(b|c)*", "a?b?a?", "(?m)(^a|b$)+", "(a|b)*abb", "a.b", "(?s)a.*b",
+            "(?m)^a$\\n^b$", "(a*)*b", "((a|b)(a|b))*",
+        ];
+        let alphabet = ['a', 'b', 'c', 'd', ' ', '\n', 'z'];
+        for expr in exprs {
+            let mut m = DerivativeMatcher::new(expr).unwrap();
+            let dfa = Dfa::new(expr).unwrap();
+            let program = Program::with_flags(expr, Flags { is_line: true, ..Default::default() }, true).unwrap();
+
+            let mut level = vec![String::new()];
+            for _ in 0..4 {
+                for s in &level {
+                    let expected = program.is_match(s).unwrap();
+                    assert_eq!(m.is_match(s), expected, "{expr} {s:?}");
+                    assert_eq!(dfa.is_match(s), expected, "{expr} {s:?}");
+                }
+                level = level.iter().flat_map(|s| alphabet.iter().map(move |c| format!("{s}{c}"))).collect();
+            }
+        }
+    }
+}
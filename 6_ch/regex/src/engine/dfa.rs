@@ -0,0 +1,619 @@
+//! ASTから作るDFAと、直積構成による言語の比較
+//!
+//! DFAが受理するのは、文字列全体が式にマッチする文字列の集合 (`^(?:expr)$`と同じ) である。
+//! 文字は、式に現れる各文字と改行をそれぞれ1つの記号とし、残りの文字は単語構成文字か
+//! 否かで2つの記号にまとめる。アサーションは直前の文字の種類を状態に含めて判定するので、
+//! `\n`の後の`(?m)^`なども正しく扱える。
+//!
+//! 記号の集合が異なるDFA同士の演算では、両方の文字を合わせた記号の集合に揃えてから
+//! 直積を作る。
+use super::{flags, is_any_match, is_word_char, parser, parser::AST, Assertion, Flags};
+use crate::helper::DynError;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// DFAの状態数の上限。部分集合構成で状態が爆発した場合はエラーとする
+pub const MAX_STATES: usize = 10_000;
+
+#[derive(Debug)]
+pub enum DfaError {
+    TooManyStates,
+    Unsupported, // &、~を含む式
+}
+
+impl Display for DfaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DfaError: {:?}", self)
+    }
+}
+
+impl Error for DfaError {}
+
+/// DFAの記号の集合
+///
+/// 記号0..chars.len()はchars[i]そのもの。続く2つは、charsに無い単語構成文字と、
+/// charsに無いそれ以外の文字を表す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alphabet {
+    chars: Vec<char>, // 昇順。改行は常に含む
+    word: char,       // charsに無い単語構成文字の代表
+    other: char,      // charsに無い、単語構成文字でない文字の代表
+}
+
+impl Alphabet {
+    fn new(mut chars: Vec<char>) -> Self {
+        chars.push('\n');
+        chars.sort_unstable();
+        chars.dedup();
+
+        // 読みやすい文字を優先して代表を選ぶ
+        let unused = |preferred: &str, is_word: bool| {
+            preferred
+                .chars()
+                .chain((0..=char::MAX as u32).filter_map(char::from_u32))
+                .find(|c| is_word_char(*c) == is_word && *c != '\n' && chars.binary_search(c).is_err())
+                .unwrap()
+        };
+        let word = unused("abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789_", true);
+        let other = unused(" -.,:;!#%&=@~", false);
+        Alphabet { chars, word, other }
+    }
+
+    /// 1文字ずつの記号が表す文字。記号の番号の順に並ぶ
+    pub fn chars(&self) -> &[char] {
+        &self.chars
+    }
+
+    /// charsに無い単語構成文字の記号と、それ以外の文字の記号
+    pub fn default_symbols(&self) -> (usize, usize) {
+        (self.chars.len(), self.chars.len() + 1)
+    }
+
+    /// 記号の数
+    pub fn n_symbols(&self) -> usize {
+        self.chars.len() + 2
+    }
+
+    /// 文字cを表す記号
+    pub fn symbol(&self, c: char) -> usize {
+        match self.chars.binary_search(&c) {
+            Ok(i) => i,
+            Err(_) if is_word_char(c) => self.chars.len(),
+            Err(_) => self.chars.len() + 1,
+        }
+    }
+
+    /// 記号が表す文字の1つ
+    pub fn example(&self, symbol: usize) -> char {
+        match symbol.checked_sub(self.chars.len()) {
+            None => self.chars[symbol],
+            Some(0) => self.word,
+            Some(_) => self.other,
+        }
+    }
+
+    /// 両方の文字を含む記号の集合
+    fn merge(&self, other: &Alphabet) -> Alphabet {
+        Alphabet::new(self.chars.iter().chain(&other.chars).copied().collect())
+    }
+}
+
+/// Thompson構成によるNFAのノード
+#[derive(Debug)]
+enum Node {
+    Consume(Vec<bool>, usize), // 記号ごとに遷移できるか、遷移先
+    Epsilon(Vec<usize>),
+    Assert(Assertion, usize),
+    Match,
+}
+
+/// ASTからNFAを作る
+struct NfaBuilder<'a> {
+    alphabet: &'a Alphabet,
+    nodes: Vec<Node>,
+}
+
+impl NfaBuilder<'_> {
+    fn push(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn consume(&mut self, is_match: impl Fn(char) -> bool, next: usize) -> usize {
+        let set = (0..self.alphabet.n_symbols()).map(|s| is_match(self.alphabet.example(s))).collect();
+        self.push(Node::Consume(set, next))
+    }
+
+    /// astにマッチした後にnextへ進むノードを作り、その入口を返す
+    fn build(&mut self, ast: &AST, next: usize) -> usize {
+        match ast {
+            AST::Char(c) => self.consume(|x| x == *c, next),
+            AST::Class(cs) => self.consume(|x| cs.contains(&x), next),
+            AST::Any(is_newline) => self.consume(|x| is_any_match(*is_newline, x), next),
+            AST::Assert(a) => self.push(Node::Assert(*a, next)),
+            AST::Seq(v) => v.iter().rev().fold(next, |next, e| self.build(e, next)),
+            AST::Or(e1, e2) => {
+                let (s1, s2) = (self.build(e1, next), self.build(e2, next));
+                self.push(Node::Epsilon(vec![s1, s2]))
+            }
+            AST::Question(e) => {
+                let s = self.build(e, next);
+                self.push(Node::Epsilon(vec![s, next]))
+            }
+            AST::Star(e) | AST::Plus(e) => {
+                // 繰り返しの分岐は、eを作った後で遷移先を埋める
+                let split = self.push(Node::Epsilon(Vec::new()));
+                let s = self.build(e, split);
+                self.nodes[split] = Node::Epsilon(vec![s, next]);
+                if matches!(ast, AST::Star(_)) {
+                    split
+                } else {
+                    s
+                }
+            }
+            AST::Capture(_, _, e) => self.build(e, next),
+            AST::And(..) | AST::Not(_) => unreachable!("from_ast rejects boolean operators"),
+        }
+    }
+}
+
+/// 式中に現れる文字
+pub fn collect_chars(ast: &AST, chars: &mut Vec<char>) {
+    match ast {
+        AST::Char(c) => chars.push(*c),
+        AST::Class(cs) => chars.extend(cs),
+        AST::Any(_) | AST::Assert(_) => (),
+        AST::Plus(e) | AST::Star(e) | AST::Question(e) | AST::Capture(_, _, e) | AST::Not(e) => {
+            collect_chars(e, chars)
+        }
+        AST::Or(e1, e2) | AST::And(e1, e2) => {
+            collect_chars(e1, chars);
+            collect_chars(e2, chars);
+        }
+        AST::Seq(v) => v.iter().for_each(|e| collect_chars(e, chars)),
+    }
+}
+
+/// kernelからε遷移で辿れるノードのうち、文字を消費するノードと、Matchに届くか
+///
+/// アサーションは直前の文字の種類prevと、次の文字next (Noneは入力の末尾) で判定する
+fn closure(nodes: &[Node], kernel: &[usize], prev: Option<char>, next: Option<char>) -> (Vec<usize>, bool) {
+    let mut is_visited = vec![false; nodes.len()];
+    let mut stack = kernel.to_vec();
+    let mut consumers = Vec::new();
+    let mut is_match = false;
+    while let Some(n) = stack.pop() {
+        if std::mem::replace(&mut is_visited[n], true) {
+            continue;
+        }
+        match &nodes[n] {
+            Node::Consume(..) => consumers.push(n),
+            Node::Epsilon(v) => stack.extend(v),
+            Node::Assert(a, next_node) => {
+                if a.is_match_between(prev, next) {
+                    stack.push(*next_node);
+                }
+            }
+            Node::Match => is_match = true,
+        }
+    }
+    (consumers, is_match)
+}
+
+/// アサーションの判定に必要な、直前の文字の種類の代表。Noneは入力の先頭
+fn prev_kind(c: char) -> char {
+    if c == '\n' {
+        '\n'
+    } else if is_word_char(c) {
+        'a'
+    } else {
+        ' '
+    }
+}
+
+/// 記号の集合の上の完全なDFA。状態0が初期状態
+#[derive(Debug, Clone)]
+pub struct Dfa {
+    pub alphabet: Alphabet,
+    pub next: Vec<Vec<usize>>, // next[状態][記号]
+    pub is_accept: Vec<bool>,
+}
+
+impl Dfa {
+    pub fn new(expr: &str) -> Result<Self, DynError> {
+        Self::with_flags(expr, Flags::default())
+    }
+
+    pub fn with_flags(expr: &str, flags: Flags) -> Result<Self, DynError> {
+        let ast = flags::apply(parser::parse(expr)?, &flags);
+        Ok(Self::from_ast(&ast)?)
+    }
+
+    /// 部分集合構成でASTからDFAを作る
+    pub fn from_ast(ast: &AST) -> Result<Self, DfaError> {
+        if parser::is_boolean(ast) {
+            return Err(DfaError::Unsupported);
+        }
+
+        let mut chars = Vec::new();
+        collect_chars(ast, &mut chars);
+        let alphabet = Alphabet::new(chars);
+
+        let mut builder = NfaBuilder { alphabet: &alphabet, nodes: vec![Node::Match] };
+        let start = builder.build(ast, 0);
+        let nodes = builder.nodes;
+
+        // 状態はε遷移を辿る前のノードの集合と、直前の文字の種類
+        type Key = (Vec<usize>, Option<char>);
+        let mut ids: HashMap<Key, usize> = HashMap::new();
+        let mut queue = VecDeque::new();
+        let mut dfa = Dfa { alphabet: alphabet.clone(), next: Vec::new(), is_accept: Vec::new() };
+
+        let mut intern = |key: Key, dfa: &mut Dfa, queue: &mut VecDeque<Key>| -> Result<usize, DfaError> {
+            // 行き止まりの状態は直前の文字によらず1つにまとめる
+            let key = if key.0.is_empty() { (key.0, None) } else { key };
+            if let Some(id) = ids.get(&key) {
+                return Ok(*id);
+            }
+            if ids.len() == MAX_STATES {
+                return Err(DfaError::TooManyStates);
+            }
+            let id = ids.len();
+            ids.insert(key.clone(), id);
+            dfa.next.push(Vec::new());
+            dfa.is_accept.push(false);
+            queue.push_back(key);
+            Ok(id)
+        };
+
+        intern((vec![start], None), &mut dfa, &mut queue)?;
+        let mut id = 0;
+        while let Some((kernel, prev)) = queue.pop_front() {
+            dfa.is_accept[id] = closure(&nodes, &kernel, prev, None).1;
+            for s in 0..alphabet.n_symbols() {
+                let c = alphabet.example(s);
+                let mut targets = closure(&nodes, &kernel, prev, Some(c))
+                    .0
+                    .into_iter()
+                    .filter_map(|n| match &nodes[n] {
+                        Node::Consume(set, next) if set[s] => Some(*next),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                targets.sort_unstable();
+                targets.dedup();
+                let to = intern((targets, Some(prev_kind(c))), &mut dfa, &mut queue)?;
+                dfa.next[id].push(to);
+            }
+            id += 1;
+        }
+
+        Ok(dfa)
+    }
+
+    /// 状態数
+    pub fn len(&self) -> usize {
+        self.next.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.next.is_empty()
+    }
+
+    /// 文字列全体を受理するか
+    pub fn is_match(&self, s: &str) -> bool {
+        let state = s.chars().fold(0, |state, c| self.next[state][self.alphabet.symbol(c)]);
+        self.is_accept[state]
+    }
+
+    /// 補集合を受理するDFA
+    pub fn complement(&self) -> Dfa {
+        let mut dfa = self.clone();
+        dfa.is_accept.iter_mut().for_each(|a| *a = !*a);
+        dfa
+    }
+
+    pub fn intersection(&self, other: &Dfa) -> Result<Dfa, DfaError> {
+        self.product(other, |a, b| a && b)
+    }
+
+    pub fn union(&self, other: &Dfa) -> Result<Dfa, DfaError> {
+        self.product(other, |a, b| a || b)
+    }
+
+    /// selfが受理し、otherが受理しない文字列を受理するDFA
+    pub fn difference(&self, other: &Dfa) -> Result<Dfa, DfaError> {
+        self.product(other, |a, b| a && !b)
+    }
+
+    /// 両方を同時に動かすDFAを作る。受理するかは両方の受理の有無をopで組み合わせて決める
+    pub fn product(&self, other: &Dfa, op: impl Fn(bool, bool) -> bool) -> Result<Dfa, DfaError> {
+        let alphabet = self.alphabet.merge(&other.alphabet);
+        // 合わせた記号から、それぞれのDFAの記号への対応
+        let symbols = |dfa: &Dfa| {
+            (0..alphabet.n_symbols())
+                .map(|s| dfa.alphabet.symbol(alphabet.example(s)))
+                .collect::<Vec<_>>()
+        };
+        let (sym1, sym2) = (symbols(self), symbols(other));
+
+        let mut ids = HashMap::from([((0, 0), 0)]);
+        let mut queue = VecDeque::from([(0, 0)]);
+        let mut dfa = Dfa { alphabet, next: Vec::new(), is_accept: Vec::new() };
+        while let Some((q1, q2)) = queue.pop_front() {
+            dfa.is_accept.push(op(self.is_accept[q1], other.is_accept[q2]));
+            let mut row = Vec::with_capacity(sym1.len());
+            for (s1, s2) in sym1.iter().zip(&sym2) {
+                let key = (self.next[q1][*s1], other.next[q2][*s2]);
+                let len = ids.len();
+                let id = *ids.entry(key).or_insert(len);
+                if id == len {
+                    if len == MAX_STATES {
+                        return Err(DfaError::TooManyStates);
+                    }
+                    queue.push_back(key);
+                }
+                row.push(id);
+            }
+            dfa.next.push(row);
+        }
+
+        Ok(dfa)
+    }
+
+    /// Hopcroftのアルゴリズムで状態数を最小にしたDFAを返す
+    ///
+    /// 状態は初期状態から幅優先で辿った順に番号を付け直すので、同じ言語のDFAからは
+    /// 記号の集合が同じであれば同じ結果が得られる
+    pub fn minimize(&self) -> Dfa {
+        let n_symbols = self.alphabet.n_symbols();
+        // inverse[記号][状態]: その記号で状態へ遷移する状態
+        let mut inverse = vec![vec![Vec::new(); self.len()]; n_symbols];
+        for (q, row) in self.next.iter().enumerate() {
+            for (s, &to) in row.iter().enumerate() {
+                inverse[s][to].push(q);
+            }
+        }
+
+        // 受理状態とそれ以外から始め、区別できる状態を含むブロックを分割していく
+        let mut block = self.is_accept.iter().map(|a| *a as usize).collect::<Vec<_>>();
+        let mut blocks = vec![Vec::new(), Vec::new()];
+        for (q, b) in block.iter().enumerate() {
+            blocks[*b].push(q);
+        }
+        blocks.retain(|b| !b.is_empty());
+        for (i, b) in blocks.iter().enumerate() {
+            b.iter().for_each(|q| block[*q] = i);
+        }
+
+        let mut work = (0..blocks.len()).collect::<Vec<_>>();
+        let mut is_work = vec![true; blocks.len()];
+        while let Some(splitter) = work.pop() {
+            is_work[splitter] = false;
+            let splitter = blocks[splitter].clone();
+            for pred in &inverse {
+                // 記号で分割ブロックへ遷移する状態を、属するブロックごとに集める
+                let mut hits: HashMap<usize, Vec<usize>> = HashMap::new();
+                for q in splitter.iter().flat_map(|q| &pred[*q]) {
+                    hits.entry(block[*q]).or_default().push(*q);
+                }
+                let mut hits = hits.into_iter().collect::<Vec<_>>();
+                hits.sort_unstable();
+
+                for (b, mut inside) in hits {
+                    inside.sort_unstable();
+                    inside.dedup();
+                    if inside.len() == blocks[b].len() {
+                        continue;
+                    }
+                    let outside = blocks[b].iter().copied().filter(|q| inside.binary_search(q).is_err()).collect();
+                    let new = blocks.len();
+                    for q in &inside {
+                        block[*q] = new;
+                    }
+                    blocks[b] = outside;
+                    blocks.push(inside);
+
+                    // 作業リストにあるブロックは両方を、無ければ小さい方を加える
+                    if is_work[b] || blocks[new].len() <= blocks[b].len() {
+                        work.push(new);
+                        is_work.push(true);
+                    } else {
+                        is_work.push(false);
+                        work.push(b);
+                        is_work[b] = true;
+                    }
+                }
+            }
+        }
+
+        // 初期状態のブロックから幅優先で番号を付ける
+        let mut ids = vec![None; blocks.len()];
+        let mut order = vec![block[0]];
+        ids[block[0]] = Some(0);
+        let mut i = 0;
+        while i < order.len() {
+            let q = blocks[order[i]][0];
+            for &to in &self.next[q] {
+                if ids[block[to]].is_none() {
+                    ids[block[to]] = Some(order.len());
+                    order.push(block[to]);
+                }
+            }
+            i += 1;
+        }
+
+        Dfa {
+            alphabet: self.alphabet.clone(),
+            next: order
+                .iter()
+                .map(|b| self.next[blocks[*b][0]].iter().map(|to| ids[block[*to]].unwrap()).collect())
+                .collect(),
+            is_accept: order.iter().map(|b| self.is_accept[blocks[*b][0]]).collect(),
+        }
+    }
+
+    /// 受理する文字列のうち最短で、その中で記号の順に最小のもの。言語が空ならNone
+    pub fn shortest(&self) -> Option<String> {
+        let mut parent = vec![None; self.len()];
+        let mut is_visited = vec![false; self.len()];
+        let mut queue = VecDeque::from([0]);
+        is_visited[0] = true;
+
+        while let Some(q) = queue.pop_front() {
+            if self.is_accept[q] {
+                let mut s = Vec::new();
+                let mut q = q;
+                while let Some((p, symbol)) = parent[q] {
+                    s.push(self.alphabet.example(symbol));
+                    q = p;
+                }
+                return Some(s.into_iter().rev().collect());
+            }
+            for (symbol, &to) in self.next[q].iter().enumerate() {
+                if !is_visited[to] {
+                    is_visited[to] = true;
+                    parent[to] = Some((q, symbol));
+                    queue.push_back(to);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// 2つの式の言語の比較結果。それぞれ該当する最短の文字列を持つ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comparison {
+    pub only_left: Option<String>,  // 左の式だけがマッチする文字列
+    pub only_right: Option<String>, // 右の式だけがマッチする文字列
+    pub both: Option<String>,       // 両方の式がマッチする文字列
+}
+
+impl Comparison {
+    pub fn new(left: &Dfa, right: &Dfa) -> Result<Self, DfaError> {
+        Ok(Comparison {
+            only_left: left.difference(right)?.shortest(),
+            only_right: right.difference(left)?.shortest(),
+            both: left.intersection(right)?.shortest(),
+        })
+    }
+
+    pub fn is_equivalent(&self) -> bool {
+        self.only_left.is_none() && self.only_right.is_none()
+    }
+
+    /// 左の言語が右の言語に含まれるか
+    pub fn is_subset(&self) -> bool {
+        self.only_left.is_none()
+    }
+
+    pub fn is_superset(&self) -> bool {
+        self.only_right.is_none()
+    }
+
+    pub fn is_overlapping(&self) -> bool {
+        self.both.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Comparison, Dfa};
+    use crate::engine::{Flags, Program};
+
+    fn compare(left: &str, right: &str) -> Comparison {
+        Comparison::new(&Dfa::new(left).unwrap(), &Dfa::new(right).unwrap()).unwrap()
+    }
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn test_compare() {
+        assert!(compare("a(b|c)", "ab|ac").is_equivalent());
+        assert!(compare("(a*)*", "a*").is_equivalent());
+        assert!(compare("a+", "aa*").is_equivalent());
+        assert!(compare("(?s).", "(?s:.)|\\n").is_equivalent());
+
+        let c = compare("a*", "a+");
+        assert_eq!(c.only_left, some(""));
+        assert!(c.is_superset() && !c.is_subset());
+
+        let c = compare("ab|c", "c|abc");
+        assert_eq!((c.only_left, c.only_right, c.both), (some("ab"), some("abc"), some("c")));
+
+        let c = compare("a.", "ab");
+        assert!(c.is_superset());
+        assert_eq!(c.only_left, some("aa"));
+        assert_eq!(compare(".", "(?s).").only_right, some("\n"));
+        assert!(!compare("a", "b").is_overlapping());
+    }
+
+    #[test]
+    fn test_assertion() {
+        // 文字列全体の言語なので、先頭と末尾のアサーションは意味を持たない
+        assert!(compare("^a$", "a").is_equivalent());
+        assert!(compare("(?m)a\\n^b$", "a\\nb").is_equivalent());
+        assert!(Dfa::new("a^b").unwrap().shortest().is_none());
+        assert_eq!(compare("(?m)(a|\\n)*^b", "(a|\\n)*b").only_right, some("ab"));
+
+        let word = Flags { is_word: true, ..Default::default() };
+        let dfa = Dfa::with_flags("a.|.a", word).unwrap();
+        assert!(dfa.is_match("ab") && dfa.is_match("-a"));
+        let inner = Dfa::new("(?s).*").unwrap().intersection(&Dfa::new("x(?m:$)").unwrap()).unwrap();
+        assert_eq!(inner.shortest(), some("x"));
+    }
+
+    #[test]
+    fn test_complement() {
+        let dfa = Dfa::new("a*").unwrap().complement();
+        assert!(dfa.is_match("b") && dfa.is_match("aab") && !dfa.is_match("aa"));
+        assert_eq!(dfa.shortest(), some("\n"));
+        assert!(Dfa::new("a|b").unwrap().union(&Dfa::new("c").unwrap()).unwrap().is_match("c"));
+    }
+
+    #[test]
+    fn test_minimize() {
+        let n_states = |expr: &str| Dfa::new(expr).unwrap().minimize().len();
+        // 行き止まりの状態を含む
+        assert_eq!(n_states("a*"), 2);
+        assert_eq!(n_states("(a|b)*abb"), 5);
+        assert_eq!(n_states("(?m)^(ab|a)*$"), n_states("(a|ab)*"));
+        assert_eq!(n_states("a^b"), 1);
+
+        for expr in ["(a|b)*a(a|b)", "(?m)(^a|b$)+", "x(?s:.)*y"] {
+            let dfa = Dfa::new(expr).unwrap();
+            let min = dfa.minimize();
+            assert!(min.len() <= dfa.len(), "{expr}");
+            assert!(Comparison::new(&dfa, &min).unwrap().is_equivalent(), "{expr}");
+            assert_eq!(min.minimize().next, min.next);
+        }
+    }
+
+    #[test]
+    fn test_matches_program() {
+        let exprs = ["(ab|a)*b?", "(?m)^a*$\\n?b", "a.(?s:.)", "(a|b)*a(a|b)", "(?m)(^a|b$)+"];
+        let alphabet = ['a', 'b', '\n', 'c'];
+        for expr in exprs {
+            let dfa = Dfa::new(expr).unwrap();
+            let line = Flags { is_line: true, ..Default::default() };
+            let program = Program::with_flags(expr, line, true).unwrap();
+
+            // 長さ4までの全ての文字列でプログラムと結果が一致する
+            let mut strings = vec![String::new()];
+            let mut level = strings.clone();
+            for _ in 0..4 {
+                level = level.iter().flat_map(|s| alphabet.map(|c| format!("{s}{c}"))).collect();
+                strings.extend(level.iter().cloned());
+            }
+            for s in strings {
+                assert_eq!(dfa.is_match(&s), program.is_match(&s).unwrap(), "{expr} {s:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! ASTと命令列をGraphvizのDOT形式で出力する
+use super::{parser::AST, Instruction};
+use std::fmt::Write;
+
+/// ASTを木として出力する
+pub fn ast_to_dot(ast: &AST) -> String {
+    let mut out = String::new();
+    out.push_str("digraph ast {\n");
+    out.push_str("    node [shape=ellipse];\n");
+    let mut n = 0;
+    write_ast(ast, &mut n, &mut out);
+    out.push_str("}\n");
+    out
+}
+
+/// ノードを出力し、そのノード番号を返す
+fn write_ast(ast: &AST, n: &mut usize, out: &mut String) -> usize {
+    let id = *n;
+    *n += 1;
+
+    let (label, children): (String, Vec<&AST>) = match ast {
+        AST::Char(c) => (format!("Char '{c}'"), vec![]),
+        AST::Class(cs) => (format!("Class [{}]", cs.iter().collect::<String>()), vec![]),
+        AST::Any(false) => ("Any".to_string(), vec![]),
+        AST::Any(true) => ("Any (?s)".to_string(), vec![]),
+        AST::Assert(a) => (format!("Assert {a}"), vec![]),
+        AST::Plus(e) => ("Plus".to_string(), vec![e]),
+        AST::Star(e) => ("Star".to_string(), vec![e]),
+        AST::Question(e) => ("Question".to_string(), vec![e]),
+        AST::Or(e1, e2) => ("Or".to_string(), vec![e1, e2]),
+        AST::Seq(v) => ("Seq".to_string(), v.iter().collect()),
+        AST::Capture(i, Some(name), e) => (format!("Capture {i} <{name}>"), vec![e]),
+        AST::Capture(i, None, e) => (format!("Capture {i}"), vec![e]),
+        AST::And(e1, e2) => ("And".to_string(), vec![e1, e2]),
+        AST::Not(e) => ("Not".to_string(), vec![e]),
+    };
+    writeln!(out, "    n{id} [label=\"{}\"];", escape(&label)).unwrap();
+
+    for child in children {
+        let child_id = write_ast(child, n, out);
+        writeln!(out, "    n{id} -> n{child_id};").unwrap();
+    }
+
+    id
+}
+
+/// 命令列を制御フローグラフとして出力する
+///
+/// splitの辺には優先順位を1, 2のラベルで付け、jumpの辺は破線で表す
+pub fn code_to_dot(code: &[Instruction]) -> String {
+    let mut out = String::new();
+    out.push_str("digraph code {\n");
+    out.push_str("    rankdir=LR;\n");
+    out.push_str("    node [shape=box];\n");
+
+    for (pc, inst) in code.iter().enumerate() {
+        let label = escape(&format!("{:>04}: {inst}", pc));
+        match inst {
+            Instruction::Match(_) => {
+                writeln!(out, "    i{pc} [label=\"{label}\", shape=doublecircle];").unwrap()
+            }
+            _ => writeln!(out, "    i{pc} [label=\"{label}\"];").unwrap(),
+        }
+    }
+
+    for (pc, inst) in code.iter().enumerate() {
+        match inst {
+            Instruction::Char(_)
+            | Instruction::Str(_)
+            | Instruction::Class(_)
+            | Instruction::Any(_)
+            | Instruction::Assert(_)
+            | Instruction::Save(_) => writeln!(out, "    i{pc} -> i{};", pc + 1).unwrap(),
+            Instruction::Match(_) => (),
+            Instruction::Jump(addr) => writeln!(out, "    i{pc} -> i{addr} [style=dashed];").unwrap(),
+            Instruction::Split(addr1, addr2) => {
+                writeln!(out, "    i{pc} -> i{addr1} [label=\"1\"];").unwrap();
+                writeln!(out, "    i{pc} -> i{addr2} [label=\"2\"];").unwrap();
+            }
+        }
+    }
+
+    out.push_str("}\n");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ast_to_dot, code_to_dot};
+    use crate::engine::{compile, parser::parse};
+
+    #[test]
+    fn test_ast_to_dot() {
+        let dot = ast_to_dot(&parse("a|\\\\").unwrap());
+        assert!(dot.starts_with("digraph ast {\n"));
+        assert!(dot.contains("n0 [label=\"Or\"];"));
+        assert!(dot.contains("[label=\"Char 'a'\"];"));
+        assert!(dot.contains("[label=\"Char '\\\\'\"];"));
+        assert!(dot.contains("n0 -> n1;"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_code_to_dot() {
+        let code = compile("a*b", false).unwrap();
+        let dot = code_to_dot(&code);
+        assert!(dot.contains("i0 [label=\"0000: split 0001, 0003\"];"));
+        assert!(dot.contains("i0 -> i1 [label=\"1\"];"));
+        assert!(dot.contains("i0 -> i3 [label=\"2\"];"));
+        assert!(dot.contains("i2 -> i0 [style=dashed];"));
+        assert!(dot.contains("i4 [label=\"0004: match 0\", shape=doublecircle];"));
+    }
+}
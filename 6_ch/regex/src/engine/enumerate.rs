@@ -0,0 +1,253 @@
+//! 式がマッチする文字列を短い順、同じ長さでは辞書順 (shortlex順) に列挙する
+//!
+//! コンパイル済みの命令列の全てのスレッドを同時に進め、文字列全体を消費した時点で
+//! match命令に到達できるものを返す。長さごとに深さ優先で探索し、スレッドが全て
+//! 失敗した接頭辞の先は辿らない。
+//!
+//! `.`や文字クラスが表す文字は無数にありうるため、列挙に使う文字 (アルファベット) は
+//! 有限に限る。既定ではプログラムに現れる文字と、`.`があればそれ以外の単語構成文字、
+//! 単語構成文字でない文字を1つずつ (改行にもマッチする`.`なら改行も) 使う。
+use super::{is_any_match, is_word_char, Instruction, Program};
+
+/// スレッド。(pc, str命令内で照合済みの文字数)
+type Thread = (usize, usize);
+
+/// 長さごとの探索の途中の接頭辞
+#[derive(Debug)]
+struct Frame {
+    threads: Vec<Thread>, // 接頭辞を消費した直後のスレッド。空遷移は辿っていない
+    index: usize,         // 次に試すアルファベットの位置
+}
+
+/// shortlex順に文字列を返すイテレータ
+#[derive(Debug)]
+pub struct Enumerate<'a> {
+    code: &'a [Instruction],
+    alphabet: Vec<char>,
+    max_len: usize,
+    limit: usize,
+    count: usize,         // 返した文字列の数
+    len: usize,           // 探索中の長さ
+    is_alive: bool,       // 探索中の長さの文字列まで失敗せずに辿れたか
+    prefix: Vec<char>,
+    stack: Vec<Frame>,
+}
+
+impl<'a> Enumerate<'a> {
+    /// max_len文字以下の文字列を最大limit個返す
+    pub fn new(program: &'a Program, max_len: usize, limit: usize) -> Self {
+        Self::with_alphabet(program, default_alphabet(&program.code), max_len, limit)
+    }
+
+    /// alphabetの文字のみからなる文字列を列挙する
+    pub fn with_alphabet(program: &'a Program, mut alphabet: Vec<char>, max_len: usize, limit: usize) -> Self {
+        alphabet.sort_unstable();
+        alphabet.dedup();
+        Enumerate {
+            code: &program.code,
+            alphabet,
+            max_len,
+            limit,
+            count: 0,
+            len: 0,
+            is_alive: false,
+            prefix: Vec::new(),
+            stack: vec![Frame { threads: vec![(0, 0)], index: 0 }],
+        }
+    }
+
+    /// 列挙に使う文字。昇順
+    pub fn alphabet(&self) -> &[char] {
+        &self.alphabet
+    }
+}
+
+impl Iterator for Enumerate<'_> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        loop {
+            if self.count >= self.limit || self.len > self.max_len {
+                return None;
+            }
+
+            let Some(frame) = self.stack.last_mut() else {
+                // この長さの文字列に1つも辿り着けなければ、より長い文字列も無い
+                if !self.is_alive {
+                    return None;
+                }
+                self.len += 1;
+                self.is_alive = false;
+                self.stack.push(Frame { threads: vec![(0, 0)], index: 0 });
+                continue;
+            };
+
+            let prev = self.prefix.last().copied();
+            if self.prefix.len() == self.len {
+                self.is_alive = true;
+                let (_, is_match) = closure(self.code, &frame.threads, prev, None);
+                let s = is_match.then(|| self.prefix.iter().collect());
+                self.stack.pop();
+                self.prefix.pop();
+                if s.is_some() {
+                    self.count += 1;
+                    return s;
+                }
+                continue;
+            }
+
+            let Some(&c) = self.alphabet.get(frame.index) else {
+                self.stack.pop();
+                self.prefix.pop();
+                continue;
+            };
+            frame.index += 1;
+
+            let threads = step(self.code, &frame.threads, prev, c);
+            if !threads.is_empty() {
+                self.prefix.push(c);
+                self.stack.push(Frame { threads, index: 0 });
+            }
+        }
+    }
+}
+
+/// プログラムに現れる文字と、`.`の代わりに使う文字
+fn default_alphabet(code: &[Instruction]) -> Vec<char> {
+    let mut chars = Vec::new();
+    let mut any = None;
+    for inst in code {
+        match inst {
+            Instruction::Char(c) => chars.push(*c),
+            Instruction::Str(s) | Instruction::Class(s) => chars.extend(s),
+            Instruction::Any(is_newline) => any = Some(any.unwrap_or(false) || *is_newline),
+            _ => (),
+        }
+    }
+
+    if let Some(is_newline) = any {
+        let unused = |preferred: &str, is_word: bool| {
+            preferred.chars().find(|c| is_word_char(*c) == is_word && !chars.contains(c))
+        };
+        let word = unused("abcdefghijklmnopqrstuvwxyz0123456789_", true);
+        let other = unused(" -.,:;!#%&=@~", false);
+        chars.extend(word.into_iter().chain(other));
+        if is_newline {
+            chars.push('\n');
+        }
+    }
+    chars
+}
+
+/// 直前の文字prevと直後の文字nextの間で空遷移を辿り、文字を照合するスレッドと、
+/// match命令に到達したかを返す
+fn closure(code: &[Instruction], threads: &[Thread], prev: Option<char>, next: Option<char>) -> (Vec<Thread>, bool) {
+    let mut result = Vec::new();
+    let mut is_match = false;
+    let mut visited = vec![false; code.len()];
+    let mut stack: Vec<Thread> = threads.iter().rev().copied().collect();
+    while let Some((pc, n)) = stack.pop() {
+        if n > 0 {
+            // str命令の途中
+            result.push((pc, n));
+            continue;
+        }
+        match visited.get(pc) {
+            Some(false) => visited[pc] = true,
+            _ => continue,
+        }
+
+        match &code[pc] {
+            Instruction::Jump(addr) => stack.push((*addr, 0)),
+            Instruction::Split(addr1, addr2) => {
+                stack.push((*addr2, 0));
+                stack.push((*addr1, 0));
+            }
+            Instruction::Assert(a) => {
+                if a.is_match_between(prev, next) {
+                    stack.push((pc + 1, 0));
+                }
+            }
+            Instruction::Save(_) => stack.push((pc + 1, 0)),
+            Instruction::Match(_) => is_match = true,
+            _ => result.push((pc, 0)),
+        }
+    }
+    (result, is_match)
+}
+
+/// 文字cを消費した後のスレッド。重複は除く
+fn step(code: &[Instruction], threads: &[Thread], prev: Option<char>, c: char) -> Vec<Thread> {
+    let (threads, _) = closure(code, threads, prev, Some(c));
+    let mut next = Vec::new();
+    for (pc, n) in threads {
+        let thread = match &code[pc] {
+            Instruction::Char(ch) if *ch == c => (pc + 1, 0),
+            Instruction::Class(cs) if cs.contains(&c) => (pc + 1, 0),
+            Instruction::Any(is_newline) if is_any_match(*is_newline, c) => (pc + 1, 0),
+            Instruction::Str(s) if s.get(n) == Some(&c) => {
+                if n + 1 < s.len() {
+                    (pc, n + 1)
+                } else {
+                    (pc + 1, 0)
+                }
+            }
+            _ => continue,
+        };
+        if !next.contains(&thread) {
+            next.push(thread);
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{Dfa, Flags, Program};
+
+    fn enumerate(expr: &str, max_len: usize, limit: usize) -> Vec<String> {
+        Program::new(expr, true).unwrap().enumerate(max_len, limit).collect()
+    }
+
+    #[test]
+    fn test_enumerate() {
+        assert_eq!(enumerate("a(b|c)?", 5, 100), ["a", "ab", "ac"]);
+        assert_eq!(enumerate("(a|b)*", 2, 100), ["", "a", "b", "aa", "ab", "ba", "bb"]);
+        assert_eq!(enumerate("(a|b)*", 10, 4), ["", "a", "b", "aa"]);
+        assert_eq!(enumerate("abc|abd", 5, 100), ["abc", "abd"]);
+        assert_eq!(enumerate("a+", 3, 100), ["a", "aa", "aaa"]);
+        assert_eq!(enumerate("a^b", 5, 100), Vec::<String>::new());
+        assert_eq!(enumerate("(?m)a$\\n^b", 5, 100), ["a\nb"]);
+        assert_eq!(enumerate("(?m)a$(\\n|b)*", 3, 100), ["a", "a\n", "a\n\n", "a\nb"]);
+        assert_eq!(enumerate("a.", 2, 100), ["a ", "aa", "ab"]);
+
+        // 無限の言語でも、長さの上限で止まる
+        assert_eq!(enumerate("(a*)*", 3, 100), ["", "a", "aa", "aaa"]);
+
+        let program = Program::with_flags("ab", Flags { is_ignore_case: true, ..Default::default() }, true).unwrap();
+        assert_eq!(program.enumerate(2, 100).collect::<Vec<_>>(), ["AB", "Ab", "aB", "ab"]);
+        let program = Program::new("x.", true).unwrap();
+        let e = super::Enumerate::with_alphabet(&program, vec!['y', 'x'], 2, 100);
+        assert_eq!(e.alphabet(), ['x', 'y']);
+        assert_eq!(e.collect::<Vec<_>>(), ["xx", "xy"]);
+    }
+
+    #[test]
+    fn test_matches_dfa() {
+        // アルファベット上の5文字以下の文字列のうち、DFAが受理するものと一致する
+        for expr in ["(ab|a)(b|c)*", "a?b?a?", "(?m)(^a|b$)+", "(a|b)*abb", "a.b"] {
+            let dfa = Dfa::new(expr).unwrap();
+            let program = Program::new(expr, true).unwrap();
+            let e = program.enumerate(5, usize::MAX);
+
+            let mut expected = vec![String::new()];
+            let mut level = vec![String::new()];
+            for _ in 0..5 {
+                level = level.iter().flat_map(|s| e.alphabet().iter().map(move |c| format!("{s}{c}"))).collect();
+                expected.extend(level.iter().cloned());
+            }
+            expected.retain(|s| dfa.is_match(s));
+            assert_eq!(e.collect::<Vec<_>>(), expected, "{expr}");
+        }
+    }
+}
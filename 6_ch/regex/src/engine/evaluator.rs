@@ -1,7 +1,6 @@
-use super::Instruction;
+use super::{is_any_match, Instruction};
 use crate::helper::safe_add;
 use std::{
-    collections::VecDeque,
     error::Error,
     fmt::{self, Display},
 };
@@ -9,9 +8,9 @@ use std::{
 #[derive(Debug)]
 pub enum EvalError {
     PCOverFlow,
-    SPOverFlow,
     InvalidPC,
     InvalidContext,
+    Stopped, // eval_traceのフックが中断した
 }
 
 impl Display for EvalError {
@@ -23,119 +22,391 @@ impl Display for EvalError {
 impl Error for EvalError {}
 
 pub fn eval(inst: &[Instruction], line: &[char], is_depth: bool) -> Result<bool, EvalError> {
+    Ok(eval_at(inst, line, 0, is_depth)?.is_some())
+}
+
+/// eval_traceが命令を実行する直前の状態
+#[derive(Debug)]
+pub struct Step<'a> {
+    pub start: usize, // この試行でマッチを始める位置
+    pub pc: usize,
+    pub sp: usize,
+    pub inst: &'a Instruction,
+    pub stack: &'a [(usize, usize)], // 失敗したときに戻る(pc, sp)。末尾から試す
+    pub is_backtrack: bool,          // 直前の命令が失敗し、stackから取り出して再開したか
+}
+
+/// line[start]から始まるマッチを探し、マッチした範囲の終端を返す
+///
+/// アサーションが前後の文字を参照できるよう、line全体を受け取る
+pub fn eval_at(
+    inst: &[Instruction],
+    line: &[char],
+    start: usize,
+    is_depth: bool,
+) -> Result<Option<usize>, EvalError> {
     if is_depth {
-        eval_width(inst, line)
+        eval_trace(inst, line, start, |_| true)
     } else {
-        eval_width(inst, line)
+        eval_width(inst, line, start)
     }
 }
 
-fn eval_depth(
+/// 深さ優先で探索しながら、命令を実行するたびにtraceを呼ぶ
+///
+/// 再帰の代わりに戻り先をスタックに積むので、入力が長くてもネイティブのスタックを
+/// 使い果たさず、traceからはスタックの内容も見える。traceがfalseを返した場合は
+/// EvalError::Stoppedで中断する
+///
+/// 入力を消費しないまま同じjump命令かsplit命令に戻ってきたスレッドは失敗とする。
+/// `(a?b?)*`のように空文字列にマッチする繰り返しが、空のまま回り続けるのを防ぐ
+pub fn eval_trace<F: FnMut(&Step) -> bool>(
     inst: &[Instruction],
     line: &[char],
-    mut pc: usize,
-    mut sp: usize,
-) -> Result<bool, EvalError> {
+    start: usize,
+    trace: F,
+) -> Result<Option<usize>, EvalError> {
+    backtrack(inst, line, start, &mut [], trace)
+}
+
+/// eval_traceの本体。save命令はslotsに位置を記録し、失敗したスレッドの記録は元に戻す
+fn backtrack<F: FnMut(&Step) -> bool>(
+    inst: &[Instruction],
+    line: &[char],
+    start: usize,
+    slots: &mut [Option<usize>],
+    mut trace: F,
+) -> Result<Option<usize>, EvalError> {
+    let mut stack = Vec::new();
+    let mut marks = Vec::new(); // stackの各要素を積んだときの(loopsの長さ, savesの長さ)
+    let mut loops = Vec::new(); // 現在のスレッドが実行したjump命令とsplit命令の(pc, sp)
+    let mut saves = Vec::new(); // 現在のスレッドが書き換えたスロットと、書き換える前の値
+    let mut pc = 0;
+    let mut sp = start;
+    let mut is_backtrack = false;
+
     loop {
-        let next = if let Some(i) = inst.get(pc) {
-            i
-        } else {
-            return Err(EvalError::InvalidPC);
-        };
+        let next = inst.get(pc).ok_or(EvalError::InvalidPC)?;
+        if !trace(&Step { start, pc, sp, inst: next, stack: &stack, is_backtrack }) {
+            return Err(EvalError::Stopped);
+        }
+        is_backtrack = false;
 
-        match next {
-            Instruction::Char(c)                => {
-                if let Some(sp_c) = line.get(sp) {
-                    if c == sp_c {
-                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                        safe_add(&mut pc, &1, || EvalError::SPOverFlow)?;
-                    } else {
-                        return Ok(false);
-                    }
-                } else {
-                    return Ok(false);
-                }
+        let is_ok = match next {
+            Instruction::Char(c) if line.get(sp) == Some(c) => {
+                sp += 1;
+                true
             }
-            Instruction::Match                  => {
-                return Ok(true);
+            Instruction::Class(cs) if line.get(sp).is_some_and(|c| cs.contains(c)) => {
+                sp += 1;
+                true
             }
-            Instruction::Jump(addr)             => {
+            Instruction::Any(is_newline) if line.get(sp).is_some_and(|c| is_any_match(*is_newline, *c)) => {
+                sp += 1;
+                true
+            }
+            Instruction::Str(s) if starts_with(line, sp, s) => {
+                sp += s.len();
+                true
+            }
+            Instruction::Assert(a) => a.is_match(line, sp),
+            Instruction::Save(slot) => {
+                if let Some(s) = slots.get_mut(*slot) {
+                    saves.push((*slot, *s));
+                    *s = Some(sp);
+                }
+                true
+            }
+            Instruction::Match(_) => return Ok(Some(sp)),
+            Instruction::Jump(_) | Instruction::Split(..) if is_empty_loop(&loops, pc, sp) => false,
+            Instruction::Jump(addr) => {
+                loops.push((pc, sp));
                 pc = *addr;
+                continue;
             }
-            Instruction::Split(addr1, addr2)    => {
-                if eval_depth(inst, line, *addr1, sp)? || eval_depth(inst, line, *addr2, sp)? {
-                    return Ok(true);
-                } else {
-                    return Ok(false);
+            Instruction::Split(addr1, addr2) => {
+                loops.push((pc, sp));
+                stack.push((*addr2, sp));
+                marks.push((loops.len(), saves.len()));
+                pc = *addr1;
+                continue;
+            }
+            Instruction::Char(_) | Instruction::Class(_) | Instruction::Any(_) | Instruction::Str(_) => false,
+        };
+
+        if is_ok {
+            safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
+        } else if let Some((p, s)) = stack.pop() {
+            let (n_loops, n_saves) = marks.pop().ok_or(EvalError::InvalidContext)?;
+            loops.truncate(n_loops);
+            for (slot, value) in saves.drain(n_saves..).rev() {
+                slots[slot] = value;
+            }
+            pc = p;
+            sp = s;
+            is_backtrack = true;
+        } else {
+            return Ok(None);
+        }
+    }
+}
+
+/// 入力を消費しないまま、pcの命令を再び実行しようとしているか
+///
+/// スレッドの中でspは減らないので、同じspで実行した命令はloopsの末尾にまとまっている
+fn is_empty_loop(loops: &[(usize, usize)], pc: usize, sp: usize) -> bool {
+    loops.iter().rev().take_while(|(_, s)| *s == sp).any(|(p, _)| *p == pc)
+}
+
+/// キャプチャのスロットごとに記録した位置
+pub type Slots = Vec<Option<usize>>;
+
+/// eval_traceと同じ順序で探索し、マッチした範囲の終端とキャプチャのスロットを返す
+///
+/// スロットの数はn_slotsで、記録されなかったスロットはNoneとなる
+pub fn eval_captures(
+    inst: &[Instruction],
+    line: &[char],
+    start: usize,
+    n_slots: usize,
+) -> Result<Option<(usize, Slots)>, EvalError> {
+    let mut slots = vec![None; n_slots];
+    let end = backtrack(inst, line, start, &mut slots, |_| true)?;
+    Ok(end.map(|end| (end, slots)))
+}
+
+/// line[sp..]がsで始まるか
+fn starts_with(line: &[char], sp: usize, s: &[char]) -> bool {
+    line.get(sp..).is_some_and(|l| l.starts_with(s))
+}
+
+/// 全てのスレッドを優先度の順に同時に進め、eval_traceと同じマッチの終端を返す
+///
+/// 各位置で同じ命令のスレッドは1つしか持たないので、バックトラックせずに入力を1回だけ
+/// 走査する。match命令に到達したら、それより優先度の低いスレッドは捨てる
+fn eval_width(inst: &[Instruction], line: &[char], start: usize) -> Result<Option<usize>, EvalError> {
+    let mut matched = None;
+    let mut clist = Vec::new(); // (pc, str命令内で照合済みの文字数)。優先度の高い順
+    let mut nlist = Vec::new();
+    let mut visited = vec![false; inst.len()];
+
+    if add_priority_thread(inst, line, start, 0, &mut clist, &mut visited)? {
+        matched = Some(start);
+    }
+
+    let mut sp = start;
+    while let (false, Some(c)) = (clist.is_empty(), line.get(sp)) {
+        visited.iter_mut().for_each(|v| *v = false);
+        for (pc, n) in clist.drain(..) {
+            let next = match &inst[pc] {
+                Instruction::Char(ch) if ch == c => pc + 1,
+                Instruction::Class(cs) if cs.contains(c) => pc + 1,
+                Instruction::Any(is_newline) if is_any_match(*is_newline, *c) => pc + 1,
+                Instruction::Str(s) if s.get(n) == Some(c) => {
+                    if n + 1 < s.len() {
+                        nlist.push((pc, n + 1));
+                        continue;
+                    }
+                    pc + 1
                 }
+                _ => continue,
+            };
+            if add_priority_thread(inst, line, sp + 1, next, &mut nlist, &mut visited)? {
+                matched = Some(sp + 1);
+                break;
             }
         }
+        std::mem::swap(&mut clist, &mut nlist);
+        sp += 1;
     }
+    Ok(matched)
 }
 
-fn pop_ctx(
-    pc: &mut usize, 
-    sp: &mut usize,
-    ctx: &mut VecDeque<(usize, usize)>,
-) -> Result<(), EvalError> {
-    if let Some((p, s)) = ctx.pop_back() {
-        *pc = p;
-        *sp = s;
-        Ok(())
-    } else {
-        Err(EvalError::InvalidContext)
+/// pcから空遷移で辿れる文字照合命令を、優先度の順にスレッドとして登録する
+///
+/// match命令に到達した場合はtrueを返し、それより優先度の低いスレッドは登録しない
+fn add_priority_thread(
+    inst: &[Instruction],
+    line: &[char],
+    sp: usize,
+    pc: usize,
+    list: &mut Vec<(usize, usize)>,
+    visited: &mut [bool],
+) -> Result<bool, EvalError> {
+    let mut stack = vec![pc];
+    while let Some(pc) = stack.pop() {
+        match visited.get(pc) {
+            Some(true) => continue,
+            Some(false) => visited[pc] = true,
+            None => return Err(EvalError::InvalidPC),
+        }
+
+        match &inst[pc] {
+            Instruction::Jump(addr) => stack.push(*addr),
+            Instruction::Split(addr1, addr2) => {
+                stack.push(*addr2);
+                stack.push(*addr1);
+            }
+            Instruction::Assert(a) => {
+                if a.is_match(line, sp) {
+                    stack.push(pc + 1);
+                }
+            }
+            Instruction::Save(_) => stack.push(pc + 1),
+            Instruction::Match(_) => return Ok(true),
+            _ => list.push((pc, 0)),
+        }
     }
+    Ok(false)
 }
 
-fn eval_width(inst: &[Instruction], line: &[char]) -> Result<bool, EvalError> {
-    let mut ctx = VecDeque::new();
-    let mut pc = 0;
-    let mut sp = 0;
+/// 全てのスレッドを同時に進めながら入力を1回だけ走査し、マッチしたmatch命令のidを返す
+///
+/// lineのどの位置から始まるマッチも対象とする。戻り値はidの昇順
+pub fn eval_set(inst: &[Instruction], line: &[char]) -> Result<Vec<usize>, EvalError> {
+    let mut matched = Vec::new();
+    let mut clist = Vec::new(); // (pc, str命令内で照合済みの文字数)
+    let mut nlist = Vec::new();
+    let mut visited = vec![false; inst.len()];
 
-    loop {
-        let next = if let Some(i) = inst.get(pc) {
-            i
+    for sp in 0..=line.len() {
+        // 各位置から新たにマッチを開始する
+        add_thread(inst, line, sp, 0, &mut clist, &mut visited, &mut matched)?;
+        visited.iter_mut().for_each(|v| *v = false);
+
+        let c = if let Some(c) = line.get(sp) {
+            c
         } else {
-            return Err(EvalError::InvalidPC);
+            break;
         };
 
-        match next {
-            Instruction::Char(c) => {
-                if let Some(sp_c) = line.get(sp) {
-                    if c == sp_c {
-                        safe_add(&mut pc, &1, || EvalError::PCOverFlow)?;
-                        safe_add(&mut sp, &1, || EvalError::SPOverFlow)?;
-                    } else {
-                        if ctx.is_empty() {
-                            return Ok(false);
-                        } else {
-                            pop_ctx(&mut pc, &mut sp, &mut ctx)?;
-                        }
-                    }
-                } else {
-                    if ctx.is_empty() {
-                        return Ok(false);
-                    } else {
-                        pop_ctx(&mut pc, &mut sp, &mut ctx)?;
+        for (pc, n) in clist.drain(..) {
+            let next = match &inst[pc] {
+                Instruction::Char(ch) if ch == c => pc + 1,
+                Instruction::Class(cs) if cs.contains(c) => pc + 1,
+                Instruction::Any(is_newline) if is_any_match(*is_newline, *c) => pc + 1,
+                Instruction::Str(s) if s.get(n) == Some(c) => {
+                    if n + 1 < s.len() {
+                        nlist.push((pc, n + 1));
+                        continue;
                     }
+                    pc + 1
                 }
-            }
-            Instruction::Match => {
-                return Ok(true);
-            }
-            Instruction::Jump(addr) => {
-                pc = *addr;
-            }
+                _ => continue,
+            };
+            add_thread(inst, line, sp + 1, next, &mut nlist, &mut visited, &mut matched)?;
+        }
+
+        std::mem::swap(&mut clist, &mut nlist);
+    }
+
+    matched.sort_unstable();
+    matched.dedup();
+    Ok(matched)
+}
+
+/// pcから空遷移で辿れる文字照合命令をスレッドとして登録する
+fn add_thread(
+    inst: &[Instruction],
+    line: &[char],
+    sp: usize,
+    pc: usize,
+    list: &mut Vec<(usize, usize)>,
+    visited: &mut [bool],
+    matched: &mut Vec<usize>,
+) -> Result<(), EvalError> {
+    let mut stack = vec![pc];
+    while let Some(pc) = stack.pop() {
+        match visited.get(pc) {
+            Some(true) => continue,
+            Some(false) => visited[pc] = true,
+            None => return Err(EvalError::InvalidPC),
+        }
+
+        match &inst[pc] {
+            Instruction::Jump(addr) => stack.push(*addr),
             Instruction::Split(addr1, addr2) => {
-                pc = *addr1;
-                ctx.push_back((*addr2, sp));
-                continue;
+                stack.push(*addr2);
+                stack.push(*addr1);
+            }
+            Instruction::Assert(a) => {
+                if a.is_match(line, sp) {
+                    stack.push(pc + 1);
+                }
             }
+            Instruction::Save(_) => stack.push(pc + 1),
+            Instruction::Match(id) => matched.push(*id),
+            _ => list.push((pc, 0)),
         }
+    }
+    Ok(())
+}
 
-        if !ctx.is_empty() {
-            ctx.push_back((pc, sp));
-            pop_ctx(&mut pc, &mut sp, &mut ctx)?;
+#[cfg(test)]
+mod tests {
+    use super::{eval, eval_at, eval_captures};
+    use crate::engine::{compile, Flags, Program};
+
+    #[test]
+    fn test_long_line() {
+        // 1文字ごとにsplitを実行しても、ネイティブのスタックを使い果たさない
+        let line: Vec<char> = "a".repeat(100_000).chars().collect();
+        let code = compile("a+", true).unwrap();
+        assert_eq!(eval_at(&code, &line, 0, true).unwrap(), Some(100_000));
+        let code = compile("(ab|a)*$", true).unwrap();
+        assert_eq!(eval_at(&code, &line, 0, true).unwrap(), Some(100_000));
+    }
+
+    #[test]
+    fn test_captures() {
+        // 失敗した枝で記録したスロットは元に戻す
+        let program = Program::with_captures("(a)(b)|(a)c", Flags::default(), false).unwrap();
+        let line: Vec<char> = "ac".chars().collect();
+        let caps = eval_captures(&program.code, &line, 0, 8).unwrap();
+        assert_eq!(caps, Some((2, vec![None, None, None, None, None, None, Some(0), Some(1)])));
+
+        // 繰り返しごとにスロットを書き換えても、スタックを使い果たさない
+        let program = Program::with_captures("(a)+", Flags::default(), true).unwrap();
+        let line: Vec<char> = "a".repeat(100_000).chars().collect();
+        let caps = eval_captures(&program.code, &line, 0, 4).unwrap();
+        assert_eq!(caps, Some((100_000, vec![None, None, Some(99_999), Some(100_000)])));
+    }
+
+    #[test]
+    fn test_empty_loop() {
+        // 空文字列にマッチする繰り返しの本体が、入力を消費しないまま回り続けない
+        for (expr, line, end) in [
+            ("(a?b?)*c", "c", Some(1)),
+            ("(a?b?)*c", "abbac", Some(5)),
+            ("(a?b?)*c", "x", None),
+            ("(a*)*b", "b", Some(1)),
+            ("(a?)+b", "aab", Some(3)),
+            ("(^)*a", "a", Some(1)),
+        ] {
+            let code = compile(expr, false).unwrap();
+            let line: Vec<char> = line.chars().collect();
+            assert_eq!(eval_at(&code, &line, 0, true).unwrap(), end, "{expr}");
+            assert_eq!(eval_at(&code, &line, 0, false).unwrap(), end, "{expr}");
         }
+        let code = compile("(a?b?)*c", true).unwrap();
+        assert!(!eval(&code, &['x', 'y'], true).unwrap());
+        assert!(!eval(&code, &['x', 'y'], false).unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_width() {
+        // 幅優先でも、深さ優先と同じ優先度でマッチの終端を選ぶ
+        let exprs = ["a*", "(a|ab)(c|bcd)", "(ab|a)*b", "x?(abc|ab)", "(?m)a$|ab", "a.*b", "(a*|b)*c", "(a?)+b"];
+        let lines = ["", "a", "aab", "abcd", "abab", "xabc", "a\nb", "acb", "babc"];
+        for expr in exprs {
+            let code = compile(expr, true).unwrap();
+            for line in lines {
+                let line: Vec<char> = line.chars().collect();
+                for start in 0..=line.len() {
+                    let depth = eval_at(&code, &line, start, true).unwrap();
+                    assert_eq!(eval_at(&code, &line, start, false).unwrap(), depth, "{expr} {line:?} {start}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! コンパイル時のオプション。ASTの書き換えとして実現する
+use super::{parser::AST, Assertion};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub is_ignore_case: bool, // 大文字と小文字を区別しない (grep -i)
+    pub is_word: bool,        // 前後が単語構成文字でない箇所にのみマッチ (grep -w)
+    pub is_line: bool,        // 行全体にのみマッチ (grep -x)
+}
+
+pub fn apply(ast: AST, flags: &Flags) -> AST {
+    let mut ast = if flags.is_ignore_case { fold_case(&ast) } else { ast };
+
+    if flags.is_word {
+        ast = AST::Seq(vec![
+            AST::Assert(Assertion::NoWordBefore),
+            ast,
+            AST::Assert(Assertion::NoWordAfter),
+        ]);
+    }
+
+    if flags.is_line {
+        ast = AST::Seq(vec![
+            AST::Assert(Assertion::TextStart),
+            ast,
+            AST::Assert(Assertion::TextEnd),
+        ]);
+    }
+
+    ast
+}
+
+/// 各文字を、大文字・小文字の両方にマッチする文字クラスに置き換える
+fn fold_case(ast: &AST) -> AST {
+    match ast {
+        AST::Char(c) => {
+            let cs = case_variants(*c);
+            if cs.len() == 1 {
+                AST::Char(*c)
+            } else {
+                AST::Class(cs)
+            }
+        }
+        AST::Class(cs) => {
+            let mut folded = Vec::new();
+            for c in cs {
+                for v in case_variants(*c) {
+                    if !folded.contains(&v) {
+                        folded.push(v);
+                    }
+                }
+            }
+            AST::Class(folded)
+        }
+        AST::Any(is_newline) => AST::Any(*is_newline),
+        AST::Assert(a) => AST::Assert(*a),
+        AST::Plus(e) => AST::Plus(Box::new(fold_case(e))),
+        AST::Star(e) => AST::Star(Box::new(fold_case(e))),
+        AST::Question(e) => AST::Question(Box::new(fold_case(e))),
+        AST::Or(e1, e2) => AST::Or(Box::new(fold_case(e1)), Box::new(fold_case(e2))),
+        AST::Seq(v) => AST::Seq(v.iter().map(fold_case).collect()),
+        AST::Capture(i, name, e) => AST::Capture(*i, name.clone(), Box::new(fold_case(e))),
+        AST::And(e1, e2) => AST::And(Box::new(fold_case(e1)), Box::new(fold_case(e2))),
+        AST::Not(e) => AST::Not(Box::new(fold_case(e))),
+    }
+}
+
+/// cと、1文字に対応する大文字・小文字
+fn case_variants(c: char) -> Vec<char> {
+    let mut cs = vec![c];
+    for v in c.to_lowercase().chain(c.to_uppercase()) {
+        if c.to_lowercase().count() == 1 && c.to_uppercase().count() == 1 && !cs.contains(&v) {
+            cs.push(v);
+        }
+    }
+    cs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Flags;
+    use crate::engine::Program;
+
+    fn find(expr: &str, flags: Flags, line: &str) -> Option<(usize, usize)> {
+        let program = Program::with_flags(expr, flags, true).unwrap();
+        let line = line.chars().collect::<Vec<char>>();
+        program.find_at(&line, 0).unwrap()
+    }
+
+    #[test]
+    fn test_ignore_case() {
+        let flags = Flags { is_ignore_case: true, ..Default::default() };
+        assert_eq!(find("hello", flags, "say HeLLo"), Some((4, 9)));
+        assert_eq!(find("(ab|c)+", flags, "xABcCab"), Some((1, 7)));
+        assert_eq!(find("hello", Flags::default(), "say HeLLo"), None);
+    }
+
+    #[test]
+    fn test_word() {
+        let flags = Flags { is_word: true, ..Default::default() };
+        assert_eq!(find("foo", flags, "foobar foo_x (foo)"), Some((14, 17)));
+        assert_eq!(find("foo", flags, "foobar"), None);
+        assert_eq!(find("-a", flags, "x -a b"), Some((2, 4)));
+        assert_eq!(find("-a", flags, "x-a b"), None);
+    }
+
+    #[test]
+    fn test_line() {
+        let flags = Flags { is_line: true, ..Default::default() };
+        assert_eq!(find("ab*", flags, "abbb"), Some((0, 4)));
+        assert_eq!(find("ab*", flags, "abbbc"), None);
+        assert_eq!(find("ab*", flags, "xab"), None);
+    }
+}
@@ -0,0 +1,247 @@
+//! 式にマッチする文字列と、マッチしない近い文字列を乱数で作る
+//!
+//! ASTを辿り、選択では枝を、繰り返しでは回数を乱数で選んで文字列を組み立てる。
+//! アサーションは組み立てでは扱わず、文字列全体が式にマッチするか (Dfaと同じ意味) を
+//! プログラムで確かめて、満たさなかったものは作り直す。
+//!
+//! マッチしない文字列は、マッチする文字列に1文字の削除、挿入、置換、隣接する文字の
+//! 入れ替えのいずれかを施して作る。
+use super::{dfa::collect_chars, flags, parser, parser::AST, Flags, Program};
+use crate::helper::DynError;
+
+/// `*`、`+`の繰り返し回数の既定の上限
+pub const DEFAULT_MAX_REPEAT: usize = 8;
+
+/// 文字列を作り直す回数の上限。これを超えた場合は作れなかったものとする
+const MAX_ATTEMPTS: usize = 1000;
+
+/// `.`や、マッチしない文字列の挿入・置換に使う、式に現れる文字以外の文字
+const EXTRA_CHARS: &str = "abcxyzABCXYZ0189 _-.,;:/\\\"'()[]{}<>!?#$%&*+=@^|~`";
+
+/// SplitMix64による擬似乱数。同じシードからは同じ列を返す
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// 0以上n未満の整数。nは1以上
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    fn choose<T: Copy>(&mut self, v: &[T]) -> T {
+        v[self.below(v.len())]
+    }
+}
+
+#[derive(Debug)]
+pub struct Generator {
+    ast: AST,
+    program: Program, // 文字列全体がマッチするかを確かめる
+    rng: Rng,
+    max_repeat: usize,
+    chars: Vec<char>, // 式に現れる文字とEXTRA_CHARS。改行は除く
+}
+
+impl Generator {
+    pub fn new(expr: &str, flags: Flags, seed: u64) -> Result<Self, DynError> {
+        Self::with_max_repeat(expr, flags, seed, DEFAULT_MAX_REPEAT)
+    }
+
+    /// `*`、`+`の繰り返しを最大max_repeat回とする
+    pub fn with_max_repeat(expr: &str, flags: Flags, seed: u64, max_repeat: usize) -> Result<Self, DynError> {
+        let ast = flags::apply(parser::parse(expr)?, &flags);
+        let program = Program::with_flags(expr, Flags { is_line: true, ..flags }, true)?;
+
+        let mut chars = Vec::new();
+        collect_chars(&ast, &mut chars);
+        chars.extend(EXTRA_CHARS.chars());
+        chars.retain(|c| *c != '\n');
+        chars.sort_unstable();
+        chars.dedup();
+
+        Ok(Generator { ast, program, rng: Rng::new(seed), max_repeat, chars })
+    }
+
+    /// 式にマッチする文字列を1つ作る。言語が空などで作れなければNone
+    pub fn sample(&mut self) -> Result<Option<String>, DynError> {
+        for _ in 0..MAX_ATTEMPTS {
+            let mut s = String::new();
+            let mut sampler = Sampler { rng: &mut self.rng, chars: &self.chars, max_repeat: self.max_repeat };
+            sampler.build(&self.ast, &mut s);
+            if self.program.is_match(&s)? {
+                return Ok(Some(s));
+            }
+        }
+        Ok(None)
+    }
+
+    /// マッチする文字列を少しだけ変えた、マッチしない文字列を1つ作る
+    ///
+    /// 全ての文字列にマッチする式などで作れなければNone
+    pub fn near_miss(&mut self) -> Result<Option<String>, DynError> {
+        for _ in 0..MAX_ATTEMPTS {
+            let Some(s) = self.sample()? else {
+                return Ok(None);
+            };
+            let s = self.mutate(s.chars().collect());
+            if !self.program.is_match(&s)? {
+                return Ok(Some(s));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 1文字の削除、挿入、置換、または隣接する2文字の入れ替えを行う
+    fn mutate(&mut self, mut s: Vec<char>) -> String {
+        let c = random_char(&mut self.rng, &self.chars, true);
+        match (s.len(), self.rng.below(4)) {
+            (0, _) | (_, 0) => s.insert(self.rng.below(s.len() + 1), c),
+            (n, 1) => {
+                s.remove(self.rng.below(n));
+            }
+            (n, 2) => s[self.rng.below(n)] = c,
+            (1, _) => s.push(c),
+            (n, _) => {
+                let i = self.rng.below(n - 1);
+                s.swap(i, i + 1);
+            }
+        }
+        s.into_iter().collect()
+    }
+}
+
+/// ASTを辿って文字列を組み立てる
+struct Sampler<'a> {
+    rng: &'a mut Rng,
+    chars: &'a [char],
+    max_repeat: usize,
+}
+
+impl Sampler<'_> {
+    fn build(&mut self, ast: &AST, out: &mut String) {
+        match ast {
+            AST::Char(c) => out.push(*c),
+            AST::Class(cs) => out.push(self.rng.choose(cs)),
+            AST::Any(is_newline) => out.push(random_char(self.rng, self.chars, *is_newline)),
+            AST::Assert(_) => (),
+            AST::Plus(e) => self.repeat(e, 1, out),
+            AST::Star(e) => self.repeat(e, 0, out),
+            AST::Question(e) => self.repeat_up_to(e, 0, 1, out),
+            AST::Or(e1, e2) => {
+                // 右結合のOrを枝のリストに展開して、各枝を等しい確率で選ぶ
+                let mut alts = vec![e1.as_ref()];
+                let mut rest = e2.as_ref();
+                while let AST::Or(l, r) = rest {
+                    alts.push(l);
+                    rest = r;
+                }
+                alts.push(rest);
+                let e = self.rng.choose(&alts);
+                self.build(e, out);
+            }
+            AST::Seq(v) => v.iter().for_each(|e| self.build(e, out)),
+            AST::Capture(_, _, e) => self.build(e, out),
+            AST::And(..) | AST::Not(_) => unreachable!("parse does not produce boolean operators"),
+        }
+    }
+
+    fn repeat(&mut self, e: &AST, min: usize, out: &mut String) {
+        self.repeat_up_to(e, min, self.max_repeat.max(min), out)
+    }
+
+    fn repeat_up_to(&mut self, e: &AST, min: usize, max: usize, out: &mut String) {
+        let n = min + self.rng.below(max - min + 1);
+        for _ in 0..n {
+            self.build(e, out);
+        }
+    }
+}
+
+/// charsから1文字を選ぶ。is_newlineであれば、改行も他の文字と同じ確率で選ぶ
+fn random_char(rng: &mut Rng, chars: &[char], is_newline: bool) -> char {
+    let i = rng.below(chars.len() + is_newline as usize);
+    chars.get(i).copied().unwrap_or('\n')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Generator, Rng};
+    use crate::engine::{Flags, Program};
+
+    fn samples(expr: &str, seed: u64) -> Vec<String> {
+        let mut g = Generator::new(expr, Flags::default(), seed).unwrap();
+        (0..50).map(|_| g.sample().unwrap().unwrap()).collect()
+    }
+
+    fn is_full_match(expr: &str, s: &str) -> bool {
+        let flags = Flags { is_line: true, ..Default::default() };
+        Program::with_flags(expr, flags, true).unwrap().is_match(s).unwrap()
+    }
+
+    #[test]
+    fn test_rng() {
+        let (mut r1, mut r2) = (Rng::new(42), Rng::new(42));
+        let v1: Vec<u64> = (0..8).map(|_| r1.next_u64()).collect();
+        let v2: Vec<u64> = (0..8).map(|_| r2.next_u64()).collect();
+        assert_eq!(v1, v2);
+        assert_ne!(v1[0], Rng::new(43).next_u64());
+        assert!((0..100).all(|_| r1.below(3) < 3));
+    }
+
+    #[test]
+    fn test_sample() {
+        for expr in ["abc", "a(b|c)*d", "x+y?", "(?m)^ab.$", "^(ab|cd)+$", "(?m)a$\\n^b", "(?s)a.b", "a|b|c|d"] {
+            for s in samples(expr, 1) {
+                assert!(is_full_match(expr, &s), "{expr} {s:?}");
+            }
+        }
+
+        // 同じシードからは同じ列
+        assert_eq!(samples("(a|b)*c", 7), samples("(a|b)*c", 7));
+
+        // 繰り返しの上限と、全ての枝が選ばれること
+        let v = samples("(a|b|c)*", 3);
+        assert!(v.iter().all(|s| s.len() <= super::DEFAULT_MAX_REPEAT));
+        assert!(["a", "b", "c"].iter().all(|c| v.iter().any(|s| s.contains(c))));
+        let mut g = Generator::with_max_repeat("a+", Flags::default(), 5, 2).unwrap();
+        assert!((0..20).all(|_| matches!(g.sample().unwrap().as_deref(), Some("a" | "aa"))));
+
+        // 大文字小文字を区別しない
+        let flags = Flags { is_ignore_case: true, ..Default::default() };
+        let mut g = Generator::new("ab", flags, 4).unwrap();
+        let v: Vec<String> = (0..50).map(|_| g.sample().unwrap().unwrap()).collect();
+        assert!(v.iter().all(|s| s.eq_ignore_ascii_case("ab")));
+        assert!(v.iter().any(|s| s != "ab"));
+
+        // 満たせないアサーション
+        let mut g = Generator::new("a^b", Flags::default(), 0).unwrap();
+        assert_eq!(g.sample().unwrap(), None);
+    }
+
+    #[test]
+    fn test_near_miss() {
+        for expr in ["abc", "a(b|c)*d", "x+y?", "(?m)a$\\n^b"] {
+            let mut g = Generator::new(expr, Flags::default(), 2).unwrap();
+            for _ in 0..50 {
+                let s = g.near_miss().unwrap().unwrap();
+                assert!(!is_full_match(expr, &s), "{expr} {s:?}");
+            }
+        }
+
+        // 全ての文字列にマッチする式からは作れない
+        let mut g = Generator::new("(?s).*", Flags::default(), 0).unwrap();
+        assert_eq!(g.near_miss().unwrap(), None);
+    }
+}
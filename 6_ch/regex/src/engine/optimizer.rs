@@ -0,0 +1,254 @@
+//! codegenが出力した命令列に対するのぞき穴最適化
+//!
+//! 以下の変換を順に適用する。いずれもマッチ結果を変えない。
+//!
+//! 1. ジャンプスレッディング: jump/splitの飛び先がjumpであれば最終的な飛び先に付け替える
+//! 2. 両方の飛び先が同じsplitをjumpに置き換える
+//! 3. 到達不能な命令と、実質的に直後の命令へ飛ぶだけのjumpを取り除く
+//! 4. 途中に飛び込まれないcharの連続をstr命令1つにまとめる
+use super::Instruction;
+
+pub fn optimize(code: &[Instruction]) -> Vec<Instruction> {
+    let mut code = code.to_vec();
+    thread_jumps(&mut code);
+    let code = remove_dead_code(&code);
+    fuse_literals(&code)
+}
+
+/// jumpを辿った先のアドレスを返す。jumpが循環している場合は元のアドレスを返す
+fn resolve(code: &[Instruction], addr: usize) -> usize {
+    let mut pc = addr;
+    for _ in 0..code.len() {
+        match code.get(pc) {
+            Some(Instruction::Jump(next)) => pc = *next,
+            _ => return pc,
+        }
+    }
+    addr
+}
+
+fn thread_jumps(code: &mut [Instruction]) {
+    loop {
+        let mut changed = false;
+
+        for pc in 0..code.len() {
+            let new_inst = match &code[pc] {
+                Instruction::Jump(addr) => {
+                    let dst = resolve(code, *addr);
+                    match code.get(dst) {
+                        Some(Instruction::Match(id)) => Instruction::Match(*id),
+                        _ => Instruction::Jump(dst),
+                    }
+                }
+                Instruction::Split(addr1, addr2) => {
+                    let dst1 = resolve(code, *addr1);
+                    let dst2 = resolve(code, *addr2);
+                    if dst1 == dst2 {
+                        Instruction::Jump(dst1)
+                    } else {
+                        Instruction::Split(dst1, dst2)
+                    }
+                }
+                _ => continue,
+            };
+
+            if new_inst != code[pc] {
+                code[pc] = new_inst;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return;
+        }
+    }
+}
+
+/// 各命令から次に実行され得るアドレス
+fn successors(inst: &Instruction, pc: usize) -> Vec<usize> {
+    match inst {
+        Instruction::Char(_)
+        | Instruction::Str(_)
+        | Instruction::Class(_)
+        | Instruction::Any(_)
+        | Instruction::Assert(_)
+        | Instruction::Save(_) => vec![pc + 1],
+        Instruction::Match(_) => vec![],
+        Instruction::Jump(addr) => vec![*addr],
+        Instruction::Split(addr1, addr2) => vec![*addr1, *addr2],
+    }
+}
+
+fn remove_dead_code(code: &[Instruction]) -> Vec<Instruction> {
+    let mut reachable = vec![false; code.len()];
+    let mut stack = vec![0];
+    while let Some(pc) = stack.pop() {
+        if pc >= code.len() || reachable[pc] {
+            continue;
+        }
+        reachable[pc] = true;
+        stack.extend(successors(&code[pc], pc));
+    }
+
+    let keep = code
+        .iter()
+        .enumerate()
+        .map(|(pc, inst)| match inst {
+            // 間に到達可能な命令が無ければ、飛ばなくても同じ命令に辿り着く
+            Instruction::Jump(addr) if *addr > pc => {
+                reachable[pc] && reachable[pc + 1..*addr].iter().any(|r| *r)
+            }
+            _ => reachable[pc],
+        })
+        .collect::<Vec<bool>>();
+
+    relocate(code, &keep)
+}
+
+fn fuse_literals(code: &[Instruction]) -> Vec<Instruction> {
+    // 先頭の命令からは実行が開始されるので、飛び込まれるものとして扱う
+    let mut is_target = vec![false; code.len() + 1];
+    is_target[0] = true;
+    for inst in code {
+        match inst {
+            Instruction::Jump(addr) => is_target[*addr] = true,
+            Instruction::Split(addr1, addr2) => {
+                is_target[*addr1] = true;
+                is_target[*addr2] = true;
+            }
+            _ => (),
+        }
+    }
+
+    let mut fused = code.to_vec();
+    let mut keep = vec![true; code.len()];
+    let mut pc = 0;
+    while pc < code.len() {
+        let mut chars = Vec::new();
+        let mut end = pc;
+        while let Some(inst) = code.get(end) {
+            if end != pc && is_target[end] {
+                break;
+            }
+            match inst {
+                Instruction::Char(c) => chars.push(*c),
+                Instruction::Str(s) => chars.extend(s),
+                _ => break,
+            }
+            end += 1;
+        }
+
+        if end - pc > 1 {
+            fused[pc] = Instruction::Str(chars);
+            keep[pc + 1..end].iter_mut().for_each(|k| *k = false);
+        }
+        pc = end.max(pc + 1);
+    }
+
+    relocate(&fused, &keep)
+}
+
+/// keepがfalseの命令を取り除き、飛び先のアドレスを付け替える
+///
+/// 取り除かれた命令へのジャンプは、その後ろで最初に残る命令へのジャンプとなる
+fn relocate(code: &[Instruction], keep: &[bool]) -> Vec<Instruction> {
+    let mut map = Vec::with_capacity(code.len() + 1);
+    let mut n = 0;
+    for k in keep {
+        map.push(n);
+        if *k {
+            n += 1;
+        }
+    }
+    map.push(n);
+
+    code.iter()
+        .zip(keep)
+        .filter(|(_, k)| **k)
+        .map(|(inst, _)| match inst {
+            Instruction::Jump(addr) => Instruction::Jump(map[*addr]),
+            Instruction::Split(addr1, addr2) => Instruction::Split(map[*addr1], map[*addr2]),
+            inst => inst.clone(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use crate::engine::{compile, evaluator::eval, Instruction};
+
+    #[test]
+    fn test_fuse_literals() {
+        let code = compile("abc", true).unwrap();
+        assert_eq!(
+            code,
+            vec![Instruction::Str(vec!['a', 'b', 'c']), Instruction::Match(0)]
+        );
+
+        // ループの先頭には飛び込まれるので、そこで区切る
+        let code = compile("ab(cd)*", true).unwrap();
+        assert_eq!(code[0], Instruction::Str(vec!['a', 'b']));
+        assert!(code.contains(&Instruction::Str(vec!['c', 'd'])));
+    }
+
+    #[test]
+    fn test_thread_jumps() {
+        use Instruction::*;
+
+        let code = vec![
+            Split(1, 3),
+            Char('a'),
+            Jump(4),
+            Char('b'),
+            Jump(5),
+            Jump(6),
+            Match(0),
+        ];
+        assert_eq!(
+            optimize(&code),
+            vec![Split(1, 3), Char('a'), Match(0), Char('b'), Match(0)]
+        );
+
+        let code = vec![Split(1, 2), Jump(3), Jump(3), Char('a'), Match(0)];
+        assert_eq!(optimize(&code), vec![Char('a'), Match(0)]);
+    }
+
+    #[test]
+    fn test_equivalence() {
+        let exprs = [
+            "abc|def",
+            "(abc)*",
+            "(ab|cd)+",
+            "abc?",
+            "a**b",
+            "(a*)*b",
+            "x(ab|ac|ad)y",
+            "a?a?a?aaa",
+            "(a|b)(c|d)*e",
+            "ab+c",
+        ];
+        let lines = [
+            "", "a", "b", "abc", "def", "efa", "abcabc", "abcdcd", "ab", "acb", "aaab", "xacy",
+            "xaey", "aaa", "aa", "bde", "acdcde", "abbbc", "ac",
+        ];
+
+        for expr in exprs {
+            let plain = compile(expr, false).unwrap();
+            let optimized = compile(expr, true).unwrap();
+            assert!(optimized.len() <= plain.len(), "{expr}");
+
+            for line in lines {
+                let line = line.chars().collect::<Vec<char>>();
+                for is_depth in [true, false] {
+                    assert_eq!(
+                        eval(&plain, &line, is_depth).unwrap(),
+                        eval(&optimized, &line, is_depth).unwrap(),
+                        "expr = {expr}, line = {:?}",
+                        line
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
+use super::Assertion;
 use std::{
     error::Error,
     fmt::{self, Display},
-    mem::take,
+    mem::{replace, take},
 };
 
 #[derive(Debug)]
@@ -9,8 +10,12 @@ pub enum ParseError {
     InvalidEscape(usize, char),
     InvalidRightParen(usize),
     NoPrev(usize),
-    NoRightParen,
-    Empty,
+    NoRightParen(usize), // 対応する)が無い(の位置
+    NoEscapedChar(usize),
+    Empty(usize),        // 式の長さ
+    InvalidGroup(usize), // 解釈できない(?の位置
+    DuplicateGroupName(usize, String), // 2度目に現れた名前の位置と名前
+    NoOperand(usize), // 後に式の無い&や~の位置
 }
 
 impl Display for ParseError {
@@ -25,67 +30,292 @@ impl Display for ParseError {
             ParseError::NoPrev(pos) => {
                 write!(f, "ParseError: no previous expression: pos = {pos}")
             }
-            ParseError::NoRightParen => {
-                write!(f, "ParseError: no right parenthesis")
+            ParseError::NoRightParen(pos) => {
+                write!(f, "ParseError: no right parenthesis: pos = {pos}")
+            }
+            ParseError::NoEscapedChar(pos) => {
+                write!(f, "ParseError: no escaped character: pos = {pos}")
+            }
+            ParseError::Empty(_) => write!(f, "ParseError: empty expression"),
+            ParseError::InvalidGroup(pos) => {
+                write!(f, "ParseError: invalid group: pos = {pos}")
+            }
+            ParseError::DuplicateGroupName(pos, name) => {
+                write!(f, "ParseError: duplicate group name: pos = {pos}, name = {name}")
+            }
+            ParseError::NoOperand(pos) => {
+                write!(f, "ParseError: no operand: pos = {pos}")
             }
-            ParseError::Empty => write!(f, "ParseError: empty expression"),
         }
     }
 }
 
 impl Error for ParseError {}
 
-#[derive(Debug)]
+/// 式中の範囲。文字単位で、endは含まない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        let (start, end) = match self {
+            ParseError::InvalidEscape(pos, _) => (pos.saturating_sub(1), pos + 1),
+            ParseError::InvalidRightParen(pos)
+            | ParseError::NoPrev(pos)
+            | ParseError::NoRightParen(pos)
+            | ParseError::NoEscapedChar(pos)
+            | ParseError::NoOperand(pos) => (*pos, pos + 1),
+            ParseError::Empty(len) => (0, *len),
+            ParseError::InvalidGroup(pos) => (*pos, pos + 2),
+            ParseError::DuplicateGroupName(pos, name) => (*pos, pos + name.chars().count()),
+        };
+        Span { start, end }
+    }
+
+    pub fn hint(&self) -> &'static str {
+        match self {
+            ParseError::InvalidEscape(_, _) => {
+                r"only \\, \(, \), \|, \+, \*, \?, \., \^, \$, \&, \~ and \n can be escaped"
+            }
+            ParseError::InvalidRightParen(_) => {
+                r"this ')' has no matching '('; write \) to match it literally"
+            }
+            ParseError::NoPrev(_) => {
+                r"nothing precedes this operator; escape it with \ to match it literally"
+            }
+            ParseError::NoRightParen(_) => "this '(' is never closed; add a matching ')'",
+            ParseError::NoEscapedChar(_) => r"write \\ to match a backslash",
+            ParseError::Empty(_) => "the expression must contain at least one character",
+            ParseError::InvalidGroup(_) => {
+                r"write (?:...) for a non-capturing group, (?<name>...) for a named one or (?ms) for flags"
+            }
+            ParseError::DuplicateGroupName(_, _) => "each group name may be used only once",
+            ParseError::NoOperand(_) => {
+                r"nothing follows this operator; escape it with \ to match it literally"
+            }
+        }
+    }
+
+    /// 式を表示し、エラーの箇所に^を付けてヒントを添える
+    ///
+    /// ```text
+    /// ParseError: no right parenthesis: pos = 2
+    ///   |
+    ///   | ab(cd
+    ///   |   ^
+    ///   = hint: this '(' is never closed; add a matching ')'
+    /// ```
+    pub fn render(&self, expr: &str) -> String {
+        let span = self.span();
+        let col: usize = expr.chars().take(span.start).map(char_width).sum();
+        let width: usize = expr
+            .chars()
+            .skip(span.start)
+            .take(span.end - span.start)
+            .map(char_width)
+            .sum();
+
+        let mut out = String::new();
+        out.push_str(&format!("{self}\n"));
+        out.push_str("  |\n");
+        out.push_str(&format!("  | {expr}\n"));
+        out.push_str(&format!("  | {}{}\n", " ".repeat(col), "^".repeat(width.max(1))));
+        out.push_str(&format!("  = hint: {}\n", self.hint()));
+        out
+    }
+}
+
+/// 端末上での表示幅。全角文字は2とする
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AST {
     Char(char),
+    Class(Vec<char>), // simplifierが1文字の選択をまとめたもの
+    Any(bool),        // `.`。trueの場合は改行にもマッチする ((?s))
+    Assert(Assertion),
     Plus(Box<AST>),
     Star(Box<AST>),
     Question(Box<AST>),
     Or(Box<AST>, Box<AST>),
     Seq(Vec<AST>),
+    Capture(usize, Option<String>, Box<AST>), // 番号は1から、開き括弧の順
+    And(Box<AST>, Box<AST>), // `&`。両方にマッチする。parse_booleanのみが作る
+    Not(Box<AST>),           // `~`。マッチしない文字列全体にマッチする。parse_booleanのみが作る
 }
 
+#[allow(clippy::upper_case_acronyms)]
 enum PSQ {
     Plus,
     Star,
     Question,
 }
 
+/// 式を解析する。括弧はグループ化のみを表し、キャプチャは記録しない
 pub fn parse(expr: &str) -> Result<AST, ParseError> {
+    parse_impl(expr, false, false)
+}
+
+/// 式を解析し、`(...)`と`(?<name>...)`をAST::Captureとして残す
+///
+/// `(?:...)`はparseと同じくグループ化のみを表す
+pub fn parse_with_captures(expr: &str) -> Result<AST, ParseError> {
+    parse_impl(expr, true, false)
+}
+
+/// `&` (積) と`~` (補集合) を演算子として加えた式を解析する
+///
+/// `&`は`|`より強く連接より弱く結合し、`~`は直後の要素 (後置演算子を含む) に
+/// 掛かる。`a|b&~c*d`は`a|(b&((~(c*))d))`となる。どちらも`\&`、`\~`で文字として書ける
+pub fn parse_boolean(expr: &str) -> Result<AST, ParseError> {
+    parse_impl(expr, false, true)
+}
+
+/// `&`、`~`を含むか
+pub fn is_boolean(ast: &AST) -> bool {
+    match ast {
+        AST::Char(_) | AST::Class(_) | AST::Any(_) | AST::Assert(_) => false,
+        AST::Plus(e) | AST::Star(e) | AST::Question(e) | AST::Capture(_, _, e) => is_boolean(e),
+        AST::Or(e1, e2) => is_boolean(e1) || is_boolean(e2),
+        AST::Seq(v) => v.iter().any(is_boolean),
+        AST::And(..) | AST::Not(_) => true,
+    }
+}
+
+/// キャプチャグループの名前を番号順に返す。名前の無いグループは空文字列とする
+pub fn group_names(ast: &AST) -> Vec<String> {
+    fn walk(ast: &AST, names: &mut Vec<(usize, String)>) {
+        match ast {
+            AST::Char(_) | AST::Class(_) | AST::Any(_) | AST::Assert(_) => (),
+            AST::Plus(e) | AST::Star(e) | AST::Question(e) | AST::Not(e) => walk(e, names),
+            AST::Or(e1, e2) | AST::And(e1, e2) => {
+                walk(e1, names);
+                walk(e2, names);
+            }
+            AST::Seq(v) => v.iter().for_each(|e| walk(e, names)),
+            AST::Capture(i, name, e) => {
+                names.push((*i, name.clone().unwrap_or_default()));
+                walk(e, names);
+            }
+        }
+    }
+
+    let mut names = Vec::new();
+    walk(ast, &mut names);
+    names.sort_by_key(|(i, _)| *i);
+    names.dedup_by_key(|(i, _)| *i);
+    names.into_iter().map(|(_, name)| name).collect()
+}
+
+/// `(?ms)`などで切り替える、式の途中から有効になるフラグ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct InlineFlags {
+    is_multi_line: bool, // m: ^と$が各行の先頭と末尾にもマッチする
+    is_dot_all: bool,    // s: .が改行にもマッチする
+}
+
+fn parse_impl(expr: &str, is_capture: bool, is_boolean: bool) -> Result<AST, ParseError> {
     enum ParseState {
         Char,
         Escape,
     }
 
+    let chars = expr.chars().collect::<Vec<char>>();
     let mut seq     = Vec::new();
     let mut seq_or  = Vec::new();
+    let mut seq_and = Vec::new(); // (&の左の項, &の位置)
+    let mut nots    = Vec::new(); // (~を掛けるseqの要素の位置, ~の位置)
     let mut stack   = Vec::new();
     let mut state   = ParseState::Char;
+    let mut skip    = 0; // (?の後の読み終えた文字数
+    let mut n_groups = 0;
+    let mut names   = Vec::new();
+    let mut flags   = InlineFlags::default(); // 括弧を閉じると開く前の値に戻る
+
+    for (i, &c) in chars.iter().enumerate() {
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
 
-    for (i, c) in expr.chars().enumerate() {
         match &state {
             ParseState::Char => {
                 match c {
+                    '+' | '*' | '?' if nots.last().is_some_and(|(index, _)| *index == seq.len()) => {
+                        // ~の直後の後置演算子
+                        return Err(ParseError::NoPrev(i));
+                    }
                     '+' => parse_plus_star_question(&mut seq, PSQ::Plus, i)?,
                     '*' => parse_plus_star_question(&mut seq, PSQ::Star, i)?,
                     '?' => parse_plus_star_question(&mut seq, PSQ::Question, i)?,
                     '(' => {
+                        let (group, len) = parse_group(&chars, i, flags)?;
+                        skip = len;
+                        let capture = match group {
+                            Group::Flags(new_flags) => {
+                                // (?ms)は括弧を開かず、囲むグループの残りに効く
+                                flags = new_flags;
+                                continue;
+                            }
+                            Group::NonCapture(new_flags) => {
+                                stack.push((take(&mut seq), take(&mut seq_or), take(&mut seq_and), take(&mut nots), i, None, flags));
+                                flags = new_flags;
+                                continue;
+                            }
+                            Group::Capture(name) => {
+                                if let Some(name) = &name {
+                                    if names.contains(name) {
+                                        return Err(ParseError::DuplicateGroupName(i + len - name.chars().count(), name.clone()));
+                                    }
+                                    names.push(name.clone());
+                                }
+                                n_groups += 1;
+                                is_capture.then_some((n_groups, name))
+                            }
+                        };
+
                         let prev = take(&mut seq);
                         let prev_or = take(&mut seq_or);
-                        stack.push((prev, prev_or));
+                        stack.push((prev, prev_or, take(&mut seq_and), take(&mut nots), i, capture, flags));
                     } 
                     ')' => {
-                        if let Some((mut prev, prev_or)) = stack.pop() {
-                            if !seq.is_empty() {
-                                seq_or.push(AST::Seq(seq));
-                            }
+                        if let Some((mut prev, prev_or, prev_and, prev_nots, _, capture, prev_flags)) = stack.pop() {
+                            push_branch(seq, take(&mut nots), take(&mut seq_and), &mut seq_or)?;
 
-                            if let Some(ast) = fold_or(seq_or) {
-                                prev.push(ast);
+                            match (fold_or(seq_or), capture) {
+                                (ast, Some((n, name))) => {
+                                    let ast = ast.unwrap_or(AST::Seq(Vec::new()));
+                                    prev.push(AST::Capture(n, name, Box::new(ast)));
+                                }
+                                (Some(ast), None) => prev.push(ast),
+                                (None, None) => (),
                             }
 
                             seq = prev;
                             seq_or = prev_or;
+                            seq_and = prev_and;
+                            nots = prev_nots;
+                            flags = prev_flags;
                         } else {
                             return Err(ParseError::InvalidRightParen(i));
                         }
@@ -94,11 +324,28 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
                         if seq.is_empty() {
                             return Err(ParseError::NoPrev(i));
                         } else {
-                            let prev = take(&mut seq);
-                            seq_or.push(AST::Seq(prev));
+                            push_branch(take(&mut seq), take(&mut nots), take(&mut seq_and), &mut seq_or)?;
                         }
                     }
+                    '&' if is_boolean => {
+                        if seq.is_empty() {
+                            return Err(ParseError::NoPrev(i));
+                        }
+                        seq_and.push((apply_nots(take(&mut seq), take(&mut nots))?, i));
+                    }
+                    '~' if is_boolean => nots.push((seq.len(), i)),
                     '\\' => state = ParseState::Escape,
+                    '.' => seq.push(AST::Any(flags.is_dot_all)),
+                    '^' => seq.push(AST::Assert(if flags.is_multi_line {
+                        Assertion::LineStart
+                    } else {
+                        Assertion::TextStart
+                    })),
+                    '$' => seq.push(AST::Assert(if flags.is_multi_line {
+                        Assertion::LineEnd
+                    } else {
+                        Assertion::TextEnd
+                    })),
                     _ => seq.push(AST::Char(c)),
                 };
             }
@@ -110,19 +357,79 @@ pub fn parse(expr: &str) -> Result<AST, ParseError> {
         }
     }
 
-    if !stack.is_empty() {
-        return Err(ParseError::NoRightParen);
+    let len = chars.len();
+    if let ParseState::Escape = state {
+        return Err(ParseError::NoEscapedChar(len - 1));
     }
 
-    if !seq.is_empty() {
-        seq_or.push(AST::Seq(seq));
+    if let Some((_, _, _, _, pos, _, _)) = stack.pop() {
+        return Err(ParseError::NoRightParen(pos));
     }
 
+    push_branch(seq, nots, seq_and, &mut seq_or)?;
+
     if let Some(ast) = fold_or(seq_or) {
         Ok(ast)
     } else {
-        Err(ParseError::Empty)
+        Err(ParseError::Empty(len))
+    }
+}
+
+enum Group {
+    NonCapture(InlineFlags), // (?:...)と(?ms:...)。グループ内のフラグを持つ
+    Capture(Option<String>),
+    Flags(InlineFlags), // (?ms)。以降のフラグを持つ
+}
+
+/// chars[pos]の(に続くグループの種類と、(の後に読み飛ばす文字数を返す
+///
+/// flagsは(の位置で有効なフラグ
+fn parse_group(chars: &[char], pos: usize, flags: InlineFlags) -> Result<(Group, usize), ParseError> {
+    let rest = &chars[pos + 1..];
+    if rest.first() != Some(&'?') {
+        return Ok((Group::Capture(None), 0));
+    }
+
+    let prefix = match rest.get(1) {
+        Some(':') => return Ok((Group::NonCapture(flags), 2)),
+        Some('<') => 2,
+        Some('P') if rest.get(2) == Some(&'<') => 3,
+        Some('m' | 's' | '-') => return parse_flags(rest, pos, flags),
+        _ => return Err(ParseError::InvalidGroup(pos)),
+    };
+
+    let name = rest[prefix..].iter().take_while(|c| **c != '>').collect::<String>();
+    let is_ident = name.chars().next().is_some_and(|c| c == '_' || c.is_alphabetic())
+        && name.chars().all(|c| c == '_' || c.is_alphanumeric());
+    if !is_ident || rest.get(prefix + name.chars().count()) != Some(&'>') {
+        return Err(ParseError::InvalidGroup(pos));
+    }
+
+    let len = prefix + name.chars().count() + 1;
+    Ok((Group::Capture(Some(name)), len))
+}
+
+/// `(?ms-s)`や`(?m:`のフラグ部分を読む。restは(の直後から
+fn parse_flags(rest: &[char], pos: usize, mut flags: InlineFlags) -> Result<(Group, usize), ParseError> {
+    let mut is_negated = false;
+    let mut is_empty = true;
+    for (i, c) in rest.iter().enumerate().skip(1) {
+        match c {
+            'm' => flags.is_multi_line = !is_negated,
+            's' => flags.is_dot_all = !is_negated,
+            '-' if !is_negated => {
+                // -の後にも1つ以上のフラグが必要
+                is_negated = true;
+                is_empty = true;
+                continue;
+            }
+            ')' if !is_empty => return Ok((Group::Flags(flags), i + 1)),
+            ':' if !is_empty => return Ok((Group::NonCapture(flags), i + 1)),
+            _ => break,
+        }
+        is_empty = false;
     }
+    Err(ParseError::InvalidGroup(pos))
 }
 
 fn parse_plus_star_question(
@@ -143,9 +450,17 @@ fn parse_plus_star_question(
     }
 }
 
+/// \\を前に付けると文字そのものを表す特殊文字か
+///
+/// &と~はparse_booleanでのみ演算子となるが、どちらの構文でもエスケープできる
+pub fn is_meta(c: char) -> bool {
+    matches!(c, '\\' | '(' | ')' | '|' | '+' | '*' | '?' | '.' | '^' | '$' | '&' | '~')
+}
+
 fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
     match c {
-        '\\' | '(' | ')' | '|' | '+' | '*' | '?' => Ok(AST::Char(c)),
+        c if is_meta(c) => Ok(AST::Char(c)),
+        'n' => Ok(AST::Char('\n')),
         _=> {
             let err = ParseError::InvalidEscape(pos, c);
             Err(err)
@@ -153,6 +468,40 @@ fn parse_escape(pos: usize, c: char) -> Result<AST, ParseError> {
     }
 }
 
+/// seqの要素に~を掛ける。~の後に要素が無ければエラー
+fn apply_nots(mut seq: Vec<AST>, nots: Vec<(usize, usize)>) -> Result<AST, ParseError> {
+    // 後の~ほど内側に掛かる (~~a)
+    for (index, pos) in nots.into_iter().rev() {
+        let e = seq.get_mut(index).ok_or(ParseError::NoOperand(pos))?;
+        *e = AST::Not(Box::new(replace(e, AST::Seq(Vec::new()))));
+    }
+    Ok(AST::Seq(seq))
+}
+
+/// 連接seqに~を掛け、&の左の項と右結合のAndにまとめてseq_orに加える
+///
+/// seqが空の場合は何も加えない (`()`など)。ただし&や~の後であればエラー
+fn push_branch(
+    seq: Vec<AST>,
+    nots: Vec<(usize, usize)>,
+    mut seq_and: Vec<(AST, usize)>,
+    seq_or: &mut Vec<AST>,
+) -> Result<(), ParseError> {
+    if seq.is_empty() {
+        return match (nots.last(), seq_and.last()) {
+            (Some((_, pos)), _) | (None, Some((_, pos))) => Err(ParseError::NoOperand(*pos)),
+            (None, None) => Ok(()),
+        };
+    }
+
+    let mut ast = apply_nots(seq, nots)?;
+    while let Some((e, _)) = seq_and.pop() {
+        ast = AST::And(Box::new(e), Box::new(ast));
+    }
+    seq_or.push(ast);
+    Ok(())
+}
+
 fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
     if seq_or.len() > 1 {
         let mut ast = seq_or.pop().unwrap();
@@ -165,3 +514,114 @@ fn fold_or(mut seq_or: Vec<AST>) -> Option<AST> {
         seq_or.pop()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{group_names, parse, parse_boolean, parse_with_captures, ParseError, Span, AST};
+    use crate::engine::simplifier::simplify;
+
+    #[test]
+    fn test_span() {
+        let span = |expr| parse(expr).unwrap_err().span();
+        assert_eq!(span("ab\\dc"), Span { start: 2, end: 4 });
+        assert_eq!(span("ab)c"), Span { start: 2, end: 3 });
+        assert_eq!(span("a|*b"), Span { start: 2, end: 3 });
+        assert_eq!(span("a(b(c)d"), Span { start: 1, end: 2 });
+        assert_eq!(span("abc\\"), Span { start: 3, end: 4 });
+        assert_eq!(span("()"), Span { start: 0, end: 2 });
+
+        assert!(matches!(parse("(a(b)"), Err(ParseError::NoRightParen(0))));
+    }
+
+    #[test]
+    fn test_group() {
+        let capture = |n, name: Option<&str>, e| AST::Capture(n, name.map(|s| s.to_string()), Box::new(e));
+        let ast = parse_with_captures("(a)(?:b)(?<x>c(d))").unwrap();
+        assert_eq!(
+            simplify(&ast),
+            AST::Seq(vec![
+                capture(1, None, AST::Char('a')),
+                AST::Char('b'),
+                capture(2, Some("x"), AST::Seq(vec![AST::Char('c'), capture(3, None, AST::Char('d'))])),
+            ])
+        );
+        assert_eq!(group_names(&ast), vec!["", "x", ""]);
+
+        // キャプチャを記録しない場合は括弧と同じ
+        assert_eq!(parse("(a)(?:b)(?P<x>c)").unwrap(), parse("(a)(b)(c)").unwrap());
+        assert_eq!(simplify(&parse_with_captures("()").unwrap()), capture(1, None, AST::Seq(Vec::new())));
+
+        let err = |expr| parse(expr).unwrap_err();
+        assert!(matches!(err("a(?=b)"), ParseError::InvalidGroup(1)));
+        assert!(matches!(err("(?<1a>b)"), ParseError::InvalidGroup(0)));
+        assert!(matches!(err("(?<ab"), ParseError::InvalidGroup(0)));
+        assert!(matches!(err("(?<x>a)(?<x>b)"), ParseError::DuplicateGroupName(10, _)));
+        assert_eq!(err("(?<x>a)(?<x>b)").span(), Span { start: 10, end: 11 });
+    }
+
+    #[test]
+    fn test_inline_flags() {
+        use crate::engine::Assertion::*;
+
+        let ast = |expr| simplify(&parse(expr).unwrap());
+        assert_eq!(ast("^.$"), AST::Seq(vec![AST::Assert(TextStart), AST::Any(false), AST::Assert(TextEnd)]));
+        assert_eq!(ast("(?ms)^.$"), AST::Seq(vec![AST::Assert(LineStart), AST::Any(true), AST::Assert(LineEnd)]));
+        // (?s:...)と括弧の中の(?s)は括弧の外に効かない
+        assert_eq!(ast("(?s:.).(?m)^"), ast("(?s).(?-s).(?m:^)"));
+        assert_eq!(ast("((?s).)."), ast("(?s:.)."));
+        assert_eq!(ast("\\.\\^\\$\\n"), AST::Seq(vec![AST::Char('.'), AST::Char('^'), AST::Char('$'), AST::Char('\n')]));
+
+        // フラグだけのグループはキャプチャの番号を進めない
+        assert_eq!(group_names(&parse_with_captures("(?m:(a))(?s)(?<x>b)").unwrap()), vec!["", "x"]);
+
+        let err = |expr| parse(expr).unwrap_err();
+        assert!(matches!(err("a(?x)"), ParseError::InvalidGroup(1)));
+        assert!(matches!(err("(?)"), ParseError::InvalidGroup(0)));
+        assert!(matches!(err("(?m-)a"), ParseError::InvalidGroup(0)));
+        assert!(matches!(err("(?m"), ParseError::InvalidGroup(0)));
+        assert!(matches!(err("(?s)"), ParseError::Empty(4)));
+    }
+
+    #[test]
+    fn test_boolean() {
+        let ast = |expr| simplify(&parse_boolean(expr).unwrap());
+        let and = |e1, e2| AST::And(Box::new(e1), Box::new(e2));
+        let not = |e| AST::Not(Box::new(e));
+        let c = AST::Char;
+
+        // &は|より強く連接より弱く、~は直後の要素に掛かる
+        assert_eq!(ast("a|b&c"), AST::Or(Box::new(c('a')), Box::new(and(c('b'), c('c')))));
+        assert_eq!(ast("ab&~c*d"), and(AST::Seq(vec![c('a'), c('b')]), AST::Seq(vec![not(AST::Star(Box::new(c('c')))), c('d')])));
+        assert_eq!(ast("a&b&c"), and(c('a'), and(c('b'), c('c'))));
+        assert_eq!(ast("~~(a&b)"), not(not(and(c('a'), c('b')))));
+        assert_eq!(ast("(?:a&b)c"), AST::Seq(vec![and(c('a'), c('b')), c('c')]));
+
+        // parseでは文字。エスケープはどちらでも使える
+        assert_eq!(simplify(&parse("a&~b").unwrap()), AST::Seq(vec![c('a'), c('&'), c('~'), c('b')]));
+        assert_eq!(ast("a\\&\\~"), AST::Seq(vec![c('a'), c('&'), c('~')]));
+        assert_eq!(parse("a\\&").unwrap(), parse("a&").unwrap());
+
+        let err = |expr| parse_boolean(expr).unwrap_err();
+        assert!(matches!(err("&a"), ParseError::NoPrev(0)));
+        assert!(matches!(err("a|&b"), ParseError::NoPrev(2)));
+        assert!(matches!(err("a&"), ParseError::NoOperand(1)));
+        assert!(matches!(err("(a&)"), ParseError::NoOperand(2)));
+        assert!(matches!(err("a~"), ParseError::NoOperand(1)));
+        assert!(matches!(err("~|a"), ParseError::NoPrev(1)));
+        assert!(matches!(err("a~*"), ParseError::NoPrev(2)));
+    }
+
+    #[test]
+    fn test_render() {
+        let expr = "ab(cd";
+        assert_eq!(
+            parse(expr).unwrap_err().render(expr),
+            "ParseError: no right parenthesis: pos = 2\n  |\n  | ab(cd\n  |   ^\n  = hint: this '(' is never closed; add a matching ')'\n"
+        );
+
+        // 全角文字は2桁分ずらす
+        let expr = "あい\\d";
+        let rendered = parse(expr).unwrap_err().render(expr);
+        assert!(rendered.contains("\n  |     ^^\n"), "{rendered}");
+    }
+}
@@ -0,0 +1,294 @@
+//! ASTを正規表現の文字列に戻す
+//!
+//! parseが返したASTはそのままの形で書き戻し、`parse(to_pattern(ast)) == ast`が成り立つ。
+//! 括弧はparseがSeqやOrを入れ子にした箇所にのみ付け、`.`、`^`、`$`の意味は`(?s)`や
+//! `(?-m)`で切り替える。parse_booleanが返したASTも同様に戻せる。Captureを含むASTは
+//! キャプチャしない括弧を`(?:...)`と書き、parse_with_capturesで読み戻せる。
+//! simplifierが作るClassなど、parseが返さない形は同じ言語を表す式として書く。
+//!
+//! -wで付くNoWordBefore/NoWordAfterは対応する構文がないため、文字列に戻せない。
+use super::{parser, parser::AST, Assertion};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+#[derive(Debug)]
+pub enum PrintError {
+    NoSyntax(Assertion), // parseが受け付ける構文で表せない
+}
+
+impl Display for PrintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PrintError: {:?}", self)
+    }
+}
+
+impl Error for PrintError {}
+
+pub fn to_pattern(ast: &AST) -> Result<String, PrintError> {
+    let mut printer = Printer { is_capture: has_capture(ast), ..Default::default() };
+    printer.write_or(ast)?;
+    Ok(printer.out)
+}
+
+#[derive(Default)]
+struct Printer {
+    out: String,
+    is_capture: bool,    // parse_with_capturesで読み戻せるよう、キャプチャしない括弧を(?:)とするか
+    is_multi_line: bool, // 出力した位置で有効な(?m)
+    is_dot_all: bool,    // 出力した位置で有効な(?s)
+}
+
+impl Printer {
+    /// `|`で区切った選択。parseのfold_orと同じく右結合で書く
+    fn write_or(&mut self, ast: &AST) -> Result<(), PrintError> {
+        match ast {
+            AST::Or(e1, e2) => {
+                if matches!(**e1, AST::Or(..) | AST::Class(_)) {
+                    self.write_or(e1)?;
+                } else {
+                    self.write_and(e1)?;
+                }
+                self.out.push('|');
+                self.write_or(e2)
+            }
+            AST::Class(cs) => {
+                for (i, c) in cs.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push('|');
+                    }
+                    write_char(*c, &mut self.out);
+                }
+                Ok(())
+            }
+            _ => self.write_and(ast),
+        }
+    }
+
+    /// `&`で区切った積。parseと同じく右結合で書く
+    fn write_and(&mut self, ast: &AST) -> Result<(), PrintError> {
+        if let AST::And(e1, e2) = ast {
+            self.write_seq(e1)?;
+            self.out.push('&');
+            self.write_and(e2)
+        } else {
+            self.write_seq(ast)
+        }
+    }
+
+    /// 連接。parseは選択肢をSeqで返すので、Seq以外は1要素の連接として書く
+    fn write_seq(&mut self, ast: &AST) -> Result<(), PrintError> {
+        if let AST::Seq(v) = ast {
+            for e in v {
+                self.write_item(e)?;
+            }
+            Ok(())
+        } else {
+            self.write_item(ast)
+        }
+    }
+
+    /// 連接の1要素。Seq、Or、Andは括弧で囲む
+    fn write_item(&mut self, ast: &AST) -> Result<(), PrintError> {
+        match ast {
+            AST::Char(c) => write_char(*c, &mut self.out),
+            AST::Any(is_dot_all) => {
+                self.set_flags(self.is_multi_line, *is_dot_all);
+                self.out.push('.');
+            }
+            AST::Assert(a) => {
+                let (is_multi_line, c) = match a {
+                    Assertion::TextStart => (false, '^'),
+                    Assertion::TextEnd => (false, '$'),
+                    Assertion::LineStart => (true, '^'),
+                    Assertion::LineEnd => (true, '$'),
+                    Assertion::NoWordBefore | Assertion::NoWordAfter => return Err(PrintError::NoSyntax(*a)),
+                };
+                self.set_flags(is_multi_line, self.is_dot_all);
+                self.out.push(c);
+            }
+            AST::Plus(e) => self.write_postfix(e, '+')?,
+            AST::Star(e) => self.write_postfix(e, '*')?,
+            AST::Question(e) => self.write_postfix(e, '?')?,
+            AST::Not(e) => {
+                self.out.push('~');
+                self.write_item(e)?;
+            }
+            AST::Capture(_, name, e) => {
+                let prefix = name.as_ref().map(|name| format!("?<{name}>")).unwrap_or_default();
+                self.write_group(&prefix, e)?;
+            }
+            AST::Class(_) | AST::Or(..) | AST::Seq(_) | AST::And(..) => self.write_group(self.non_capture(), ast)?,
+        }
+        Ok(())
+    }
+
+    /// 後置演算子。parseは直前の要素に掛けるので、要素でないものは括弧で囲む
+    fn write_postfix(&mut self, e: &AST, op: char) -> Result<(), PrintError> {
+        match e {
+            AST::Not(_) => self.write_group(self.non_capture(), e)?,
+            _ => self.write_item(e)?,
+        }
+        self.out.push(op);
+        Ok(())
+    }
+
+    /// 括弧で囲む。括弧の中で切り替えたフラグは、閉じると元に戻る
+    fn write_group(&mut self, prefix: &str, ast: &AST) -> Result<(), PrintError> {
+        let flags = (self.is_multi_line, self.is_dot_all);
+        self.out.push('(');
+        self.out.push_str(prefix);
+        self.write_or(ast)?;
+        self.out.push(')');
+        (self.is_multi_line, self.is_dot_all) = flags;
+        Ok(())
+    }
+
+    fn non_capture(&self) -> &'static str {
+        if self.is_capture {
+            "?:"
+        } else {
+            ""
+        }
+    }
+
+    /// 以降の`.`、`^`、`$`の意味を`(?ms)`や`(?-m)`で切り替える
+    fn set_flags(&mut self, is_multi_line: bool, is_dot_all: bool) {
+        let mut on = String::new();
+        let mut off = String::new();
+        for (flag, is_new, is_old) in [('m', is_multi_line, self.is_multi_line), ('s', is_dot_all, self.is_dot_all)] {
+            if is_new && !is_old {
+                on.push(flag);
+            } else if !is_new && is_old {
+                off.push(flag);
+            }
+        }
+        if on.is_empty() && off.is_empty() {
+            return;
+        }
+        self.out.push_str("(?");
+        self.out.push_str(&on);
+        if !off.is_empty() {
+            self.out.push('-');
+            self.out.push_str(&off);
+        }
+        self.out.push(')');
+        self.is_multi_line = is_multi_line;
+        self.is_dot_all = is_dot_all;
+    }
+}
+
+fn has_capture(ast: &AST) -> bool {
+    match ast {
+        AST::Char(_) | AST::Class(_) | AST::Any(_) | AST::Assert(_) => false,
+        AST::Plus(e) | AST::Star(e) | AST::Question(e) | AST::Not(e) => has_capture(e),
+        AST::Or(e1, e2) | AST::And(e1, e2) => has_capture(e1) || has_capture(e2),
+        AST::Seq(v) => v.iter().any(has_capture),
+        AST::Capture(..) => true,
+    }
+}
+
+fn write_char(c: char, out: &mut String) {
+    if c == '\n' {
+        return out.push_str("\\n");
+    }
+    // &と~はparse_booleanでも文字として読めるよう常にエスケープする
+    if parser::is_meta(c) {
+        out.push('\\');
+    }
+    out.push(c);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_pattern, PrintError};
+    use crate::engine::{
+        flags,
+        parser::{parse, parse_boolean, parse_with_captures},
+        simplifier::simplify,
+        Assertion, Flags,
+    };
+
+    fn normalized(expr: &str) -> String {
+        to_pattern(&simplify(&parse(expr).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_to_pattern() {
+        assert_eq!(to_pattern(&parse("abc|def").unwrap()).unwrap(), "abc|def");
+        assert_eq!(to_pattern(&parse("(ab|cd)+e").unwrap()).unwrap(), "(ab|cd)+e");
+        assert_eq!(to_pattern(&parse("((a))((b)c)").unwrap()).unwrap(), "((a))((b)c)");
+        assert_eq!(to_pattern(&parse("(a)*(b?)+").unwrap()).unwrap(), "(a)*(b?)+");
+        assert_eq!(to_pattern(&parse("a\\*\\(\\\\").unwrap()).unwrap(), "a\\*\\(\\\\");
+        // フラグは必要な箇所でだけ切り替え、(?:)は括弧として残る
+        assert_eq!(to_pattern(&parse("(?ms)^a.(?-m:$)").unwrap()).unwrap(), "(?m)^a(?s).((?-m)$)");
+        assert_eq!(to_pattern(&parse("(?s:.)(?s).").unwrap()).unwrap(), "((?s).)(?s).");
+
+        assert_eq!(normalized("abc|abd"), "ab(c|d)");
+        assert_eq!(normalized("(a|b|c)*"), "(a|b|c)*");
+        assert_eq!(normalized("((a*)*)+b|x"), "a*b|x");
+
+        // 出力はparseが受け付ける構文だけで書く。-wの単語境界は書けないのでエラーとする
+        let ast = flags::apply(parse("a.").unwrap(), &Flags { is_word: true, ..Default::default() });
+        assert!(matches!(to_pattern(&ast), Err(PrintError::NoSyntax(Assertion::NoWordBefore))));
+        let ast = flags::apply(parse("a.").unwrap(), &Flags { is_line: true, ..Default::default() });
+        let printed = to_pattern(&ast).unwrap();
+        assert_eq!(parse(&printed).unwrap(), ast, "{printed}");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let exprs = [
+            "abc|def",
+            "(ab|cd)+e",
+            "x(a|b|c)?y",
+            "a**b",
+            "(a|bc|d|e)*f",
+            "\\(\\)\\|\\+\\*\\?\\\\",
+            "((ab)c)(d(ef))",
+            "a(b(c|d)|e)f",
+            "(ab|a)(c|)",
+            "((a|b)|c)|(d)",
+            "^a.b$|\\.\\^\\$\\n",
+            "(?ms)^(a.)*$",
+            "(?s)a(?-s:.(?m)$).^",
+            "(?m:(?s)(.)+)?.",
+            "\\&\\~",
+        ];
+
+        // parseが返したASTは、そのまま書き戻せる
+        for expr in exprs {
+            let ast = parse(expr).unwrap();
+            let printed = to_pattern(&ast).unwrap();
+            assert_eq!(parse(&printed).unwrap(), ast, "{expr} => {printed}");
+        }
+
+        // Captureを含むASTは、parse_with_capturesで読み戻せる
+        for expr in ["(a)(?:b)(?<x>c(d))", "((a|b)|(?:c))*", "(?s:(.))(?m)(^)", "()|(?<y>a)?"] {
+            let ast = parse_with_captures(expr).unwrap();
+            let printed = to_pattern(&ast).unwrap();
+            assert_eq!(parse_with_captures(&printed).unwrap(), ast, "{expr} => {printed}");
+        }
+        let ast = parse_with_captures("(?<x>a(b)())|(?:c)").unwrap();
+        assert_eq!(to_pattern(&ast).unwrap(), "(?<x>a(b)())|(?:c)");
+
+        // simplifierが変形したASTも、同じ式として読み戻せる
+        for expr in exprs {
+            let ast = simplify(&parse(expr).unwrap());
+            let printed = to_pattern(&ast).unwrap();
+            assert_eq!(simplify(&parse(&printed).unwrap()), ast, "{expr} => {printed}");
+        }
+
+        for expr in ["a|b&c", "(a|b)&~c*d", "~(ab)", "(~a)*", "~~a&b", "a\\&b&~(c&d)", "a&b&c|d&(e&f)"] {
+            let ast = parse_boolean(expr).unwrap();
+            let printed = to_pattern(&ast).unwrap();
+            assert_eq!(parse_boolean(&printed).unwrap(), ast, "{expr} => {printed}");
+
+            let ast = simplify(&ast);
+            let printed = to_pattern(&ast).unwrap();
+            assert_eq!(simplify(&parse_boolean(&printed).unwrap()), ast, "{expr} => {printed}");
+        }
+        assert_eq!(to_pattern(&parse_boolean("(~a)*&(b|c)").unwrap()).unwrap(), "(~a)*&(b|c)");
+    }
+}
@@ -0,0 +1,162 @@
+//! 命令ごとの実行回数と、split命令ごとのバックトラック回数を数える
+//!
+//! Program::trace_atのフックで集計するため、通常のfind_atより遅い。
+//! 遅いパターンのどの部分で時間を使っているかを調べるときだけ使う。
+use super::{evaluator::Step, Instruction, Program};
+use crate::helper::DynError;
+use std::fmt::{self, Display};
+
+/// ヒートマップの棒の最大の長さ
+const BAR_WIDTH: usize = 20;
+
+/// 集計しながらマッチを探す。Displayで命令列のヒートマップを表示する
+#[derive(Debug)]
+pub struct Profile<'a> {
+    program: &'a Program,
+    counts: Vec<usize>,     // pcごとの実行回数
+    backtracks: Vec<usize>, // pcごとの、そのsplit命令が積んだスレッドへ戻った回数
+    splits: Vec<usize>,     // VMのスタックの各要素を積んだsplit命令のpc
+}
+
+impl<'a> Profile<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let n = program.code.len();
+        Profile { program, counts: vec![0; n], backtracks: vec![0; n], splits: Vec::new() }
+    }
+
+    /// Program::find_atと同じくマッチを探し、実行した命令を数える
+    pub fn find_at(&mut self, line: &[char], start: usize) -> Result<Option<(usize, usize)>, DynError> {
+        let program = self.program;
+        program.trace_at(line, start, |step| {
+            self.record(step);
+            true
+        })
+    }
+
+    /// Program::find_allと同じく重ならないマッチを全て探し、実行した命令を数える
+    pub fn find_all(&mut self, line: &[char]) -> Result<Vec<(usize, usize)>, DynError> {
+        let mut result = Vec::new();
+        let mut start = 0;
+        while let Some((begin, end)) = self.find_at(line, start)? {
+            result.push((begin, end));
+            start = if end == begin { end + 1 } else { end };
+            if start > line.len() {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    fn record(&mut self, step: &Step) {
+        self.counts[step.pc] += 1;
+        // 戻り先を積んだsplit命令は、VMのスタックと同じ位置に記録してある
+        if step.is_backtrack {
+            if let Some(&pc) = self.splits.get(step.stack.len()) {
+                self.backtracks[pc] += 1;
+            }
+        }
+        self.splits.truncate(step.stack.len());
+        if let Instruction::Split(..) = step.inst {
+            self.splits.push(step.pc);
+        }
+    }
+
+    /// pcごとの実行回数
+    pub fn counts(&self) -> &[usize] {
+        &self.counts
+    }
+
+    /// pcごとのバックトラック回数。split命令以外は0
+    pub fn backtracks(&self) -> &[usize] {
+        &self.backtracks
+    }
+
+    /// 実行した命令の総数
+    pub fn n_steps(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// バックトラックの総数
+    pub fn n_backtracks(&self) -> usize {
+        self.backtracks.iter().sum()
+    }
+}
+
+impl Display for Profile<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.n_steps();
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        writeln!(f, "{:<30} {:>8} {:>6} {:>10}", "code", "count", "%", "backtracks")?;
+        for (pc, inst) in self.program.code.iter().enumerate() {
+            let count = self.counts[pc];
+            let percent = if total == 0 { 0.0 } else { count as f64 * 100.0 / total as f64 };
+            let backtracks = match inst {
+                Instruction::Split(..) => self.backtracks[pc].to_string(),
+                _ => String::new(),
+            };
+            // 0回でなければ最低でも1文字の棒を表示する
+            let bar = if count == 0 { 0 } else { (count * BAR_WIDTH).div_ceil(max) };
+            let line = format!("{pc:>04}: {:<24} {count:>8} {percent:>5.1}% {backtracks:>10}  {}", inst.to_string(), "#".repeat(bar));
+            writeln!(f, "{}", line.trim_end())?;
+        }
+        write!(f, "steps: {}, backtracks: {}", total, self.n_backtracks())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::{Instruction, Program};
+
+    #[test]
+    fn test_profile() {
+        // ab|cに"c"を与えると、char aで失敗してsplitの第2引数へ戻る
+        let program = Program::new("ab|c", false).unwrap();
+        let mut profile = program.profile();
+        assert_eq!(profile.find_at(&['c'], 0).unwrap(), Some((0, 1)));
+        assert_eq!(profile.counts(), [1, 1, 0, 0, 1, 1]);
+        assert_eq!(profile.backtracks(), [1, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            profile.to_string(),
+            "\
+code                              count      % backtracks
+0000: split 0001, 0004                1  25.0%          1  ####################
+0001: char a                          1  25.0%             ####################
+0002: char b                          0   0.0%
+0003: Jump 0005                       0   0.0%
+0004: char c                          1  25.0%             ####################
+0005: match 0                         1  25.0%             ####################
+steps: 4, backtracks: 1"
+        );
+
+        // 入れ子のsplitでも、戻り先を積んだsplitに数える
+        let program = Program::new("(ab|a)*c", false).unwrap();
+        let mut profile = program.profile();
+        let line: Vec<char> = "abx".chars().collect();
+        assert_eq!(profile.find_all(&line).unwrap(), []);
+        let splits: Vec<usize> = program
+            .code
+            .iter()
+            .enumerate()
+            .filter(|(_, inst)| matches!(inst, Instruction::Split(..)))
+            .map(|(pc, _)| profile.backtracks()[pc])
+            .collect();
+        assert_eq!(profile.n_backtracks(), splits.iter().sum::<usize>());
+        assert!(splits.iter().all(|n| *n > 0));
+
+        // 入力を消費しないまま繰り返しの先頭に戻ると失敗し、繰り返しを抜ける枝へ戻る。
+        // 0, 1, 2文字目からの3回の試行で1回ずつ数える
+        let program = Program::new("(a?b?)*c", false).unwrap();
+        let mut profile = program.profile();
+        assert_eq!(profile.find_all(&['x', 'c']).unwrap(), [(1, 2)]);
+        assert_eq!(program.code[0], Instruction::Split(1, 6));
+        assert_eq!(profile.backtracks()[0], 3);
+
+        // 試行ごとの結果はfind_allと変わらない
+        for (expr, line) in [("a+b", "aaab aab"), ("x*", "axxb"), ("(?m)^a$", "a\nb\na")] {
+            let program = Program::new(expr, true).unwrap();
+            let line: Vec<char> = line.chars().collect();
+            let mut profile = program.profile();
+            assert_eq!(profile.find_all(&line).unwrap(), program.find_all(&line).unwrap(), "{expr}");
+        }
+    }
+}
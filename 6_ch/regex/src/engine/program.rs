@@ -0,0 +1,709 @@
+//! コンパイル済みプログラムのバイナリ形式での保存と読み込み
+//!
+//! 数値は全てリトルエンディアン。文字列はu32の長さとUTF-8のバイト列で表す。
+//!
+//! ```text
+//! magic       b"RZRX"
+//! version     u16
+//! flags       u32
+//! exprs       u32の個数 + 文字列
+//! group_names u32の個数 + 文字列 (i番目はグループi+1の名前。名前が無ければ空)
+//! code        u32の個数 + 命令 (u8のオペコード + オペランド)
+//! ```
+use super::{
+    codegen, evaluator, flags, optimizer, parser, simplifier, Assertion, Enumerate, Flags,
+    Instruction, Profile, Step, StreamMatches,
+};
+use crate::helper::DynError;
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    io::{Read, Write},
+    sync::OnceLock,
+};
+
+const MAGIC: &[u8; 4] = b"RZRX";
+pub const PROGRAM_VERSION: u16 = 4;
+const MIN_PROGRAM_VERSION: u16 = 1;
+
+const FLAG_OPTIMIZED: u32 = 1 << 0;
+const FLAG_SET: u32 = 1 << 1;
+const FLAG_IGNORE_CASE: u32 = 1 << 2;
+const FLAG_WORD: u32 = 1 << 3;
+const FLAG_LINE: u32 = 1 << 4;
+const FLAG_ALL: u32 = FLAG_OPTIMIZED | FLAG_SET | FLAG_IGNORE_CASE | FLAG_WORD | FLAG_LINE;
+
+const OP_CHAR: u8 = 0;
+const OP_STR: u8 = 1;
+const OP_CLASS: u8 = 2;
+const OP_MATCH: u8 = 3;
+const OP_JUMP: u8 = 4;
+const OP_SPLIT: u8 = 5;
+const OP_ASSERT: u8 = 6;
+const OP_SAVE: u8 = 7;
+const OP_ANY: u8 = 8;
+
+const ASSERT_TEXT_START: u8 = 0;
+const ASSERT_TEXT_END: u8 = 1;
+const ASSERT_NO_WORD_BEFORE: u8 = 2;
+const ASSERT_NO_WORD_AFTER: u8 = 3;
+const ASSERT_LINE_START: u8 = 4;
+const ASSERT_LINE_END: u8 = 5;
+
+#[derive(Debug)]
+pub enum LoadError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    UnknownFlags(u32),
+    UnexpectedEof,
+    InvalidUtf8,
+    InvalidChar(u32),
+    InvalidOpcode(usize, u8),
+    InvalidAssertion(usize, u8),
+    InvalidTarget(usize, usize),
+    InvalidMatchId(usize, usize),
+    InvalidSlot(usize, usize),
+    EmptyStr(usize),
+    FallThrough(usize),
+    EmptyCode,
+    TrailingData,
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LoadError: {:?}", self)
+    }
+}
+
+impl Error for LoadError {}
+
+/// マッチ全体 (0番) と各キャプチャグループにマッチした範囲。関与しなかったグループはNone
+pub type Captures = Vec<Option<(usize, usize)>>;
+
+/// 命令列と、それを復元・検証するためのメタデータ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub exprs: Vec<String>,
+    pub group_names: Vec<String>, // キャプチャグループの名前。with_capturesの場合のみ要素を持つ
+    pub flags: Flags,
+    pub is_optimized: bool,
+    pub is_set: bool,
+    pub code: Vec<Instruction>,
+}
+
+impl Program {
+    pub fn new(expr: &str, is_optimize: bool) -> Result<Self, DynError> {
+        Self::with_flags(expr, Flags::default(), is_optimize)
+    }
+
+    pub fn with_flags(expr: &str, flags: Flags, is_optimize: bool) -> Result<Self, DynError> {
+        let ast = flags::apply(parser::parse(expr)?, &flags);
+        let ast = simplifier::simplify(&ast);
+        let code = codegen::get_code(&ast)?;
+        let code = if is_optimize { optimizer::optimize(&code) } else { code };
+
+        Ok(Program {
+            exprs: vec![expr.to_string()],
+            group_names: Vec::new(),
+            flags,
+            is_optimized: is_optimize,
+            is_set: false,
+            code,
+        })
+    }
+
+    /// キャプチャグループを記録するプログラムを作る
+    ///
+    /// new、with_flagsでは`(...)`はグループ化のみを表すが、こちらはcapturesで
+    /// 各グループにマッチした範囲を取り出せる
+    pub fn with_captures(expr: &str, flags: Flags, is_optimize: bool) -> Result<Self, DynError> {
+        let ast = parser::parse_with_captures(expr)?;
+        let group_names = parser::group_names(&ast);
+        let ast = simplifier::simplify(&flags::apply(ast, &flags));
+        let code = codegen::get_code(&ast)?;
+        let code = if is_optimize { optimizer::optimize(&code) } else { code };
+
+        Ok(Program {
+            exprs: vec![expr.to_string()],
+            group_names,
+            flags,
+            is_optimized: is_optimize,
+            is_set: false,
+            code,
+        })
+    }
+
+    /// 名前付きグループの番号
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.group_names.iter().position(|n| n == name).map(|i| i + 1)
+    }
+
+    /// line[start..]の中で最も左から始まるマッチについて、全体 (0番) と各グループの範囲を返す
+    ///
+    /// マッチに関与しなかったグループはNoneとなる。位置は文字単位
+    pub fn captures_at(
+        &self,
+        line: &[char],
+        start: usize,
+    ) -> Result<Option<Captures>, DynError> {
+        let n_slots = 2 * (self.group_names.len() + 1);
+        for begin in start..=line.len() {
+            if let Some((end, slots)) = evaluator::eval_captures(&self.code, line, begin, n_slots)? {
+                let mut caps = vec![Some((begin, end))];
+                for i in 1..=self.group_names.len() {
+                    caps.push(slots[2 * i].zip(slots[2 * i + 1]));
+                }
+                return Ok(Some(caps));
+            }
+        }
+        Ok(None)
+    }
+
+    /// line[start..]の中で最も左から始まるマッチの範囲を返す。位置は文字単位
+    pub fn find_at(&self, line: &[char], start: usize) -> Result<Option<(usize, usize)>, DynError> {
+        for begin in start..=line.len() {
+            if let Some(end) = evaluator::eval_at(&self.code, line, begin, true)? {
+                return Ok(Some((begin, end)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// find_atと同じくマッチを探し、命令を1つ実行するたびにtraceを呼ぶ
+    ///
+    /// traceがfalseを返すと探索を中断し、エラーを返す
+    pub fn trace_at<F: FnMut(&Step) -> bool>(
+        &self,
+        line: &[char],
+        start: usize,
+        mut trace: F,
+    ) -> Result<Option<(usize, usize)>, DynError> {
+        for begin in start..=line.len() {
+            if let Some(end) = evaluator::eval_trace(&self.code, line, begin, &mut trace)? {
+                return Ok(Some((begin, end)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// 重ならないマッチの範囲を全て返す
+    pub fn find_all(&self, line: &[char]) -> Result<Vec<(usize, usize)>, DynError> {
+        let mut result = Vec::new();
+        let mut start = 0;
+        while let Some((begin, end)) = self.find_at(line, start)? {
+            result.push((begin, end));
+            // 空マッチの場合は1文字進めて無限ループを防ぐ
+            start = if end == begin { end + 1 } else { end };
+            if start > line.len() {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// readerをチャンクごとに読みながら検索する。位置はバイト単位
+    pub fn stream_matches<R: Read>(&self, reader: R) -> StreamMatches<'_, R> {
+        StreamMatches::new(self, reader)
+    }
+
+    /// 文字列全体がマッチする、max_len文字以下の文字列をshortlex順に最大limit個返す
+    pub fn enumerate(&self, max_len: usize, limit: usize) -> Enumerate<'_> {
+        Enumerate::new(self, max_len, limit)
+    }
+
+    /// 命令ごとの実行回数を数えながらマッチを探す
+    pub fn profile(&self) -> Profile<'_> {
+        Profile::new(self)
+    }
+
+    /// lineのどこかにマッチするか
+    pub fn is_match(&self, line: &str) -> Result<bool, DynError> {
+        let line = line.chars().collect::<Vec<char>>();
+        Ok(self.find_at(&line, 0)?.is_some())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend(MAGIC);
+        buf.extend(PROGRAM_VERSION.to_le_bytes());
+
+        let mut flags = 0;
+        if self.is_optimized {
+            flags |= FLAG_OPTIMIZED;
+        }
+        if self.is_set {
+            flags |= FLAG_SET;
+        }
+        if self.flags.is_ignore_case {
+            flags |= FLAG_IGNORE_CASE;
+        }
+        if self.flags.is_word {
+            flags |= FLAG_WORD;
+        }
+        if self.flags.is_line {
+            flags |= FLAG_LINE;
+        }
+        put_u32(&mut buf, flags);
+
+        put_strs(&mut buf, &self.exprs);
+        put_strs(&mut buf, &self.group_names);
+
+        put_len(&mut buf, self.code.len());
+        for inst in &self.code {
+            match inst {
+                Instruction::Char(c) => {
+                    buf.push(OP_CHAR);
+                    put_u32(&mut buf, *c as u32);
+                }
+                Instruction::Str(s) => {
+                    buf.push(OP_STR);
+                    put_chars(&mut buf, s);
+                }
+                Instruction::Class(cs) => {
+                    buf.push(OP_CLASS);
+                    put_chars(&mut buf, cs);
+                }
+                Instruction::Any(is_newline) => {
+                    buf.push(OP_ANY);
+                    buf.push(*is_newline as u8);
+                }
+                Instruction::Match(id) => {
+                    buf.push(OP_MATCH);
+                    put_len(&mut buf, *id);
+                }
+                Instruction::Jump(addr) => {
+                    buf.push(OP_JUMP);
+                    put_len(&mut buf, *addr);
+                }
+                Instruction::Split(addr1, addr2) => {
+                    buf.push(OP_SPLIT);
+                    put_len(&mut buf, *addr1);
+                    put_len(&mut buf, *addr2);
+                }
+                Instruction::Save(slot) => {
+                    buf.push(OP_SAVE);
+                    put_len(&mut buf, *slot);
+                }
+                Instruction::Assert(a) => {
+                    buf.push(OP_ASSERT);
+                    buf.push(match a {
+                        Assertion::TextStart => ASSERT_TEXT_START,
+                        Assertion::TextEnd => ASSERT_TEXT_END,
+                        Assertion::NoWordBefore => ASSERT_NO_WORD_BEFORE,
+                        Assertion::NoWordAfter => ASSERT_NO_WORD_AFTER,
+                        Assertion::LineStart => ASSERT_LINE_START,
+                        Assertion::LineEnd => ASSERT_LINE_END,
+                    });
+                }
+            }
+        }
+
+        buf
+    }
+
+    /// バイト列からプログラムを復元する
+    ///
+    /// 飛び先やmatchのidを検証するので、読み込めたプログラムの実行中にInvalidPCとなることはない
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LoadError> {
+        let mut r = Reader { bytes, pos: 0 };
+
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(LoadError::InvalidMagic);
+        }
+        let version = u16::from_le_bytes([r.u8()?, r.u8()?]);
+        if !(MIN_PROGRAM_VERSION..=PROGRAM_VERSION).contains(&version) {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        let flags = r.u32()?;
+        if flags & !FLAG_ALL != 0 {
+            return Err(LoadError::UnknownFlags(flags));
+        }
+
+        let exprs = r.strs()?;
+        let group_names = r.strs()?;
+
+        let n = r.len()?;
+        let mut code = Vec::new();
+        for pc in 0..n {
+            let inst = match r.u8()? {
+                OP_CHAR => Instruction::Char(r.char()?),
+                OP_STR => Instruction::Str(r.chars()?),
+                OP_CLASS => Instruction::Class(r.chars()?),
+                OP_MATCH => Instruction::Match(r.len()?),
+                OP_JUMP => Instruction::Jump(r.len()?),
+                OP_SPLIT => Instruction::Split(r.len()?, r.len()?),
+                OP_ASSERT => Instruction::Assert(match r.u8()? {
+                    ASSERT_TEXT_START => Assertion::TextStart,
+                    ASSERT_TEXT_END => Assertion::TextEnd,
+                    ASSERT_NO_WORD_BEFORE => Assertion::NoWordBefore,
+                    ASSERT_NO_WORD_AFTER => Assertion::NoWordAfter,
+                    ASSERT_LINE_START => Assertion::LineStart,
+                    ASSERT_LINE_END => Assertion::LineEnd,
+                    kind => return Err(LoadError::InvalidAssertion(pc, kind)),
+                }),
+                OP_SAVE => Instruction::Save(r.len()?),
+                OP_ANY => match r.u8()? {
+                    0 => Instruction::Any(false),
+                    1 => Instruction::Any(true),
+                    _ => return Err(LoadError::InvalidOpcode(pc, OP_ANY)),
+                },
+                op => return Err(LoadError::InvalidOpcode(pc, op)),
+            };
+            code.push(inst);
+        }
+
+        if r.pos != bytes.len() {
+            return Err(LoadError::TrailingData);
+        }
+
+        let program = Program {
+            exprs,
+            group_names,
+            flags: Flags {
+                is_ignore_case: flags & FLAG_IGNORE_CASE != 0,
+                is_word: flags & FLAG_WORD != 0,
+                is_line: flags & FLAG_LINE != 0,
+            },
+            is_optimized: flags & FLAG_OPTIMIZED != 0,
+            is_set: flags & FLAG_SET != 0,
+            code,
+        };
+        program.validate()?;
+        Ok(program)
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_all(&self.to_bytes())
+    }
+
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self, DynError> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+        Ok(Self::from_bytes(&bytes)?)
+    }
+
+    /// 全ての飛び先がプログラム内を指し、末尾から実行が溢れないことを確認する
+    fn validate(&self) -> Result<(), LoadError> {
+        // パターンを1つも含まないRegexSetのみ命令列が空になる
+        let len = self.code.len();
+        if len == 0 && !self.exprs.is_empty() {
+            return Err(LoadError::EmptyCode);
+        }
+
+        for (pc, inst) in self.code.iter().enumerate() {
+            match inst {
+                Instruction::Char(_)
+                | Instruction::Str(_)
+                | Instruction::Class(_)
+                | Instruction::Any(_)
+                | Instruction::Assert(_)
+                | Instruction::Save(_) => {
+                    if pc + 1 >= len {
+                        return Err(LoadError::FallThrough(pc));
+                    }
+                    // スロット0, 1はマッチ全体を表すので、命令としては現れない
+                    if let Instruction::Save(slot) = inst {
+                        if !(1..=self.group_names.len()).contains(&(slot / 2)) {
+                            return Err(LoadError::InvalidSlot(pc, *slot));
+                        }
+                    }
+                    // strは2文字以上の連続したcharをまとめたものなので、空にはならない
+                    if let Instruction::Str(s) = inst {
+                        if s.is_empty() {
+                            return Err(LoadError::EmptyStr(pc));
+                        }
+                    }
+                }
+                Instruction::Match(id) => {
+                    if *id >= self.exprs.len().max(1) {
+                        return Err(LoadError::InvalidMatchId(pc, *id));
+                    }
+                }
+                Instruction::Jump(addr) => {
+                    if *addr >= len {
+                        return Err(LoadError::InvalidTarget(pc, *addr));
+                    }
+                }
+                Instruction::Split(addr1, addr2) => {
+                    for addr in [addr1, addr2] {
+                        if *addr >= len {
+                            return Err(LoadError::InvalidTarget(pc, *addr));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// staticに埋め込まれたバイナリ形式のプログラム。regex!マクロが生成する
+///
+/// 最初に使われた時点でProgramに復元する
+#[derive(Debug)]
+pub struct StaticProgram {
+    bytes: &'static [u8],
+    program: OnceLock<Program>,
+}
+
+impl StaticProgram {
+    pub const fn new(bytes: &'static [u8]) -> Self {
+        StaticProgram {
+            bytes,
+            program: OnceLock::new(),
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        self.program.get_or_init(|| {
+            Program::from_bytes(self.bytes).expect("regex! embedded a corrupt program")
+        })
+    }
+
+    pub fn code(&self) -> &[Instruction] {
+        &self.program().code
+    }
+
+    pub fn do_matching(&self, line: &str, is_depth: bool) -> Result<bool, DynError> {
+        let line = line.chars().collect::<Vec<char>>();
+        Ok(evaluator::eval(self.code(), &line, is_depth)?)
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, n: u32) {
+    buf.extend(n.to_le_bytes());
+}
+
+fn put_len(buf: &mut Vec<u8>, n: usize) {
+    put_u32(buf, u32::try_from(n).expect("program too large to serialize"));
+}
+
+fn put_chars(buf: &mut Vec<u8>, cs: &[char]) {
+    put_len(buf, cs.len());
+    for c in cs {
+        put_u32(buf, *c as u32);
+    }
+}
+
+fn put_strs(buf: &mut Vec<u8>, strs: &[String]) {
+    put_len(buf, strs.len());
+    for s in strs {
+        put_len(buf, s.len());
+        buf.extend(s.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.pos.checked_add(n).ok_or(LoadError::UnexpectedEof)?;
+        let s = self.bytes.get(self.pos..end).ok_or(LoadError::UnexpectedEof)?;
+        self.pos = end;
+        Ok(s)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn char(&mut self) -> Result<char, LoadError> {
+        let n = self.u32()?;
+        char::from_u32(n).ok_or(LoadError::InvalidChar(n))
+    }
+
+    fn chars(&mut self) -> Result<Vec<char>, LoadError> {
+        let n = self.len()?;
+        // 壊れた長さで巨大な領域を確保しないよう、1文字ずつ読む
+        let mut cs = Vec::new();
+        for _ in 0..n {
+            cs.push(self.char()?);
+        }
+        Ok(cs)
+    }
+
+    fn strs(&mut self) -> Result<Vec<String>, LoadError> {
+        let n = self.len()?;
+        let mut strs = Vec::new();
+        for _ in 0..n {
+            let len = self.len()?;
+            let s = std::str::from_utf8(self.take(len)?).map_err(|_| LoadError::InvalidUtf8)?;
+            strs.push(s.to_string());
+        }
+        Ok(strs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoadError, Program};
+    use crate::engine::{do_matching_code, Instruction, RegexSet};
+
+    #[test]
+    fn test_round_trip() {
+        for expr in ["abc|def", "(ab|cd)+e", "a|b|c", "x?yz*", "(?m)^a.(?s:.)$"] {
+            for is_optimize in [true, false] {
+                let program = Program::new(expr, is_optimize).unwrap();
+                let loaded = Program::from_bytes(&program.to_bytes()).unwrap();
+                assert_eq!(program, loaded);
+            }
+        }
+
+        let set = RegexSet::new(&["error", "warn"]).unwrap();
+        let loaded = RegexSet::from_program(Program::from_bytes(&set.to_program().to_bytes()).unwrap());
+        assert_eq!(loaded.matches("warn: error").unwrap(), vec![0, 1]);
+
+        let set = RegexSet::new::<&str>(&[]).unwrap();
+        let loaded = RegexSet::from_program(Program::from_bytes(&set.to_program().to_bytes()).unwrap());
+        assert!(loaded.is_empty());
+
+        let mut buf = Vec::new();
+        let program = Program::new("a+b", true).unwrap();
+        program.write_to(&mut buf).unwrap();
+        let loaded = Program::read_from(&mut buf.as_slice()).unwrap();
+        assert!(do_matching_code(&loaded.code, "aab", true).unwrap());
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let bytes = Program::new("abc", true).unwrap().to_bytes();
+
+        assert!(matches!(Program::from_bytes(b"XXXX"), Err(LoadError::InvalidMagic)));
+        assert!(matches!(
+            Program::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(LoadError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            Program::from_bytes(&[&bytes[..], &[0]].concat()),
+            Err(LoadError::TrailingData)
+        ));
+
+        let mut bad = bytes.clone();
+        bad[4] = 99;
+        assert!(matches!(Program::from_bytes(&bad), Err(LoadError::UnsupportedVersion(99))));
+
+        let mut program = Program::new("ab|c", false).unwrap();
+        program.code[0] = Instruction::Split(1, 100);
+        assert!(matches!(
+            Program::from_bytes(&program.to_bytes()),
+            Err(LoadError::InvalidTarget(0, 100))
+        ));
+
+        program.code = vec![Instruction::Char('a')];
+        assert!(matches!(
+            Program::from_bytes(&program.to_bytes()),
+            Err(LoadError::FallThrough(0))
+        ));
+
+        program.code = vec![Instruction::Match(3)];
+        assert!(matches!(
+            Program::from_bytes(&program.to_bytes()),
+            Err(LoadError::InvalidMatchId(0, 3))
+        ));
+
+        program.code = vec![Instruction::Save(2), Instruction::Match(0)];
+        assert!(matches!(
+            Program::from_bytes(&program.to_bytes()),
+            Err(LoadError::InvalidSlot(0, 2))
+        ));
+
+        program.code = vec![Instruction::Str(vec![]), Instruction::Match(0)];
+        assert!(matches!(
+            Program::from_bytes(&program.to_bytes()),
+            Err(LoadError::EmptyStr(0))
+        ));
+    }
+
+    #[test]
+    fn test_multi_line() {
+        let find_all = |expr: &str, text: &str| {
+            let text = text.chars().collect::<Vec<char>>();
+            Program::new(expr, true).unwrap().find_all(&text).unwrap()
+        };
+        let text = "ab\nab\n";
+        assert_eq!(find_all("^ab", text), vec![(0, 2)]);
+        assert_eq!(find_all("(?m)^ab$", text), vec![(0, 2), (3, 5)]);
+        assert_eq!(find_all("ab$", text), vec![]);
+        assert_eq!(find_all("b.a", text), vec![]);
+        assert_eq!(find_all("(?s)b.a", text), vec![(1, 4)]);
+        assert_eq!(find_all("(?s:b.)a|(?-s)b.", "b\nab\n"), vec![(0, 3)]);
+        assert_eq!(find_all("a\\.b", "a.b axb"), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn test_captures() {
+        let program = Program::with_captures("(?<key>a+)=(b|(c))*", Default::default(), true).unwrap();
+        assert_eq!(program.group_names, vec!["key", "", ""]);
+        assert_eq!(program.group_index("key"), Some(1));
+        assert_eq!(program.group_index("x"), None);
+
+        let caps = |line: &str| {
+            let line = line.chars().collect::<Vec<char>>();
+            program.captures_at(&line, 0).unwrap()
+        };
+        assert_eq!(caps("xaa=bcb"), Some(vec![Some((1, 7)), Some((1, 3)), Some((6, 7)), Some((5, 6))]));
+        assert_eq!(caps("a="), Some(vec![Some((0, 2)), Some((0, 1)), None, None]));
+        assert_eq!(caps("b="), None);
+
+        // キャプチャの有無でマッチする範囲は変わらない
+        let plain = Program::new("(?<key>a+)=(b|(c))*", true).unwrap();
+        let line = "xaa=bcb".chars().collect::<Vec<char>>();
+        assert_eq!(plain.find_at(&line, 0).unwrap(), Some((1, 7)));
+        assert_eq!(program.find_at(&line, 0).unwrap(), Some((1, 7)));
+        assert!(plain.group_names.is_empty());
+
+        let loaded = Program::from_bytes(&program.to_bytes()).unwrap();
+        assert_eq!(program, loaded);
+    }
+
+    #[test]
+    fn test_trace() {
+        // トレースしてもfind_atと同じ範囲を返す
+        for (expr, line) in [("ab|ac", "xac"), ("(a|ab)(c|bcd)", "abcd"), ("a*b", "aaac"), ("(?m)^b", "a\nb")] {
+            let program = Program::new(expr, false).unwrap();
+            let line = line.chars().collect::<Vec<char>>();
+            let traced = program.trace_at(&line, 0, |_| true).unwrap();
+            assert_eq!(traced, program.find_at(&line, 0).unwrap(), "{expr}");
+        }
+
+        // ab|cに"c"を与えると、char aで失敗してsplitの第2引数へ戻る
+        let program = Program::new("ab|c", false).unwrap();
+        let line = ['c'];
+        let mut steps = Vec::new();
+        let found = program
+            .trace_at(&line, 0, |step| {
+                steps.push((step.start, step.pc, step.sp, step.stack.to_vec(), step.is_backtrack));
+                true
+            })
+            .unwrap();
+        assert_eq!(found, Some((0, 1)));
+        assert_eq!(program.code[..2], [Instruction::Split(1, 4), Instruction::Char('a')]);
+        assert_eq!(
+            steps,
+            vec![
+                (0, 0, 0, vec![], false),
+                (0, 1, 0, vec![(4, 0)], false),
+                (0, 4, 0, vec![], true),
+                (0, 5, 1, vec![], false),
+            ]
+        );
+
+        // falseを返すと中断する
+        let mut n = 0;
+        assert!(program.trace_at(&line, 0, |_| { n += 1; n < 2 }).is_err());
+        assert_eq!(n, 2);
+    }
+}
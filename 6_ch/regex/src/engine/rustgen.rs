@@ -0,0 +1,188 @@
+//! 最小化したDFAからRustのソースコードを生成する
+//!
+//! DFAの状態ごとに、次の文字で分岐する`match`を1つずつ書き出す。命令列の解釈も
+//! バックトラックも行わず、入力の各文字を1度ずつ読むだけで判定する。生成した関数は
+//! ヒープを使わず、入力が長くてもスタックを使い果たさない。
+//!
+//! build.rsから以下のように使う。
+//!
+//! ```ignore
+//! let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("matcher.rs");
+//! regex::write_matcher(&out, "is_keyword", "if|else|while").unwrap();
+//! // 利用側: include!(concat!(env!("OUT_DIR"), "/matcher.rs"));
+//! ```
+use super::{is_word_char, table::DenseTable};
+use std::fmt::Write;
+
+/// `pub fn {name}(line: &str) -> bool`を定義するソースコードを返す
+///
+/// tableは文字列全体を照合する表。状態0から始め、入力を読み終えたときの状態で判定する。
+/// どの文字を読んでも移らない状態に着いたら、残りを読まずに判定する
+pub fn gen_matcher(table: &DenseTable, expr: &str, name: &str) -> String {
+    let is_sink = |state: usize| table.next[state].iter().all(|next| *next == state);
+
+    let mut out = String::new();
+    writeln!(out, "/// Generated by regex::gen_matcher from `{}`. Do not edit.", expr.escape_default()).unwrap();
+    writeln!(out, "pub fn {name}(line: &str) -> bool {{").unwrap();
+    if is_sink(0) {
+        writeln!(out, "    let _ = line;").unwrap();
+        writeln!(out, "    {}", table.is_accept[0]).unwrap();
+        writeln!(out, "}}").unwrap();
+        return out;
+    }
+
+    writeln!(out, "    let mut chars = line.chars();").unwrap();
+    writeln!(out, "    let mut state = 0;").unwrap();
+    writeln!(out, "    loop {{").unwrap();
+    writeln!(out, "        state = match state {{").unwrap();
+    for (state, next) in table.next.iter().enumerate() {
+        if is_sink(state) {
+            writeln!(out, "            {state} => return {},", table.is_accept[state]).unwrap();
+            continue;
+        }
+
+        let word = next[table.word_class];
+        let other = next[table.other_class];
+        // 既定のクラスと同じ状態へ移る文字は、既定の腕に任せる
+        let mut targets: Vec<(usize, Vec<char>)> = Vec::new();
+        for (c, class) in &table.chars {
+            let default = if is_word_char(*c) { word } else { other };
+            if next[*class] == default {
+                continue;
+            }
+            match targets.iter_mut().find(|(target, _)| *target == next[*class]) {
+                Some((_, cs)) => cs.push(*c),
+                None => targets.push((next[*class], vec![*c])),
+            }
+        }
+
+        writeln!(out, "            {state} => match chars.next() {{").unwrap();
+        for (target, cs) in targets {
+            let pat = cs.iter().map(|c| format!("{c:?}")).collect::<Vec<_>>().join(" | ");
+            writeln!(out, "                Some({pat}) => {target},").unwrap();
+        }
+        if word != other {
+            writeln!(out, "                Some(c) if {} => {word},", is_word("c")).unwrap();
+        }
+        writeln!(out, "                Some(_) => {other},").unwrap();
+        writeln!(out, "                None => return {},", table.is_accept[state]).unwrap();
+        writeln!(out, "            }},").unwrap();
+    }
+    writeln!(out, "            _ => unreachable!(),").unwrap();
+    writeln!(out, "        }};").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+/// is_word_charと同じ判定を行う式
+fn is_word(c: &str) -> String {
+    format!("{c}.is_alphanumeric() || {c} == '_'")
+}
+
+/// 関数名として使える識別子か
+pub fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_ident;
+    use crate::engine::{do_matching, gen_matcher};
+    use std::{env, fs, process::Command};
+
+    #[test]
+    fn test_is_ident() {
+        assert!(is_ident("is_match"));
+        assert!(is_ident("_m1"));
+        assert!(!is_ident("1m"));
+        assert!(!is_ident("a-b"));
+        assert!(!is_ident(""));
+    }
+
+    /// 生成したコードをrustcでコンパイル・実行し、インタプリタと結果を比べる
+    #[test]
+    fn test_same_as_interpreter() {
+        let exprs = [
+            "abc|def",
+            "(ab|cd)+e",
+            "x(a|b|c)?y",
+            "a**b",
+            "\\(\\)'\\\\",
+            "あ+い",
+            "(?m)^b.$",
+            "(?s)a.b",
+            "(a?b?)*c",
+            "ab$|a",
+            "a*",
+            "$",
+        ];
+        let lines = [
+            "", "abc", "def", "abcde", "ababe", "xy", "xby", "xdy", "aab", "b", "()'\\", "ああい", "a\nbc\n", "a\nb", "abxc", "bac", "abc",
+        ];
+
+        let mut src = String::new();
+        let mut expected = String::new();
+        for (i, expr) in exprs.iter().enumerate() {
+            let matcher = gen_matcher(expr, &format!("m{i}")).unwrap();
+            // 命令列を解釈するループもヒープも使わない
+            assert!(!matcher.contains("pc") && !matcher.contains("Vec"), "{matcher}");
+            src.push_str(&matcher);
+            for line in lines {
+                expected.push_str(&format!("{}\n", do_matching(expr, line, true).unwrap()));
+            }
+        }
+
+        src.push_str("fn main() {\n");
+        src.push_str(&format!("    let lines = {lines:?};\n"));
+        for i in 0..exprs.len() {
+            src.push_str(&format!("    for line in lines {{ println!(\"{{}}\", m{i}(line)); }}\n"));
+        }
+        src.push_str("}\n");
+
+        assert_eq!(run(&src, "same"), expected);
+    }
+
+    /// 入力の長さによらず一定のスタックで判定する
+    #[test]
+    fn test_long_input() {
+        let line = "a".repeat(100_000);
+        let mut src = String::new();
+        let mut expected = String::new();
+        for (i, expr) in ["(a|b)*c", "(a|b)+", "(a*)*$"].iter().enumerate() {
+            src.push_str(&gen_matcher(expr, &format!("m{i}")).unwrap());
+            src.push_str(&format!("fn main{i}(line: &str) {{ println!(\"{{}}\", m{i}(line)); }}\n"));
+            expected.push_str(&format!("{}\n", do_matching(expr, &line, true).unwrap()));
+        }
+        src.push_str("fn main() {\n    let line = \"a\".repeat(100_000);\n");
+        src.push_str("    main0(&line);\n    main1(&line);\n    main2(&line);\n}\n");
+
+        assert_eq!(expected, "false\ntrue\ntrue\n");
+        assert_eq!(run(&src, "long"), expected);
+    }
+
+    /// srcをrustcでコンパイル・実行し、標準出力を返す
+    fn run(src: &str, name: &str) -> String {
+        let dir = env::temp_dir().join(format!("regex_rustgen_{name}_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let src_path = dir.join("matcher.rs");
+        let bin_path = dir.join("matcher");
+        fs::write(&src_path, src).unwrap();
+
+        let status = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+            .arg("--edition=2021")
+            .arg("-o")
+            .arg(&bin_path)
+            .arg(&src_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = Command::new(&bin_path).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap()
+    }
+}
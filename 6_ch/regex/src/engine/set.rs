@@ -0,0 +1,110 @@
+use super::{codegen, evaluator, optimizer, parser, simplifier, Flags, Instruction, Program};
+use crate::helper::DynError;
+
+/// 複数のパターンを1つのプログラムにコンパイルし、1回の走査でどれにマッチしたかを調べる
+#[derive(Debug)]
+pub struct RegexSet {
+    program: Program,
+}
+
+impl RegexSet {
+    pub fn new<S: AsRef<str>>(exprs: &[S]) -> Result<Self, DynError> {
+        let mut asts = Vec::with_capacity(exprs.len());
+        for expr in exprs {
+            asts.push(simplifier::simplify(&parser::parse(expr.as_ref())?));
+        }
+        let code = codegen::get_code_set(&asts)?;
+
+        let program = Program {
+            exprs: exprs.iter().map(|e| e.as_ref().to_string()).collect(),
+            group_names: Vec::new(),
+            flags: Flags::default(),
+            is_optimized: true,
+            is_set: true,
+            code: optimizer::optimize(&code),
+        };
+        Ok(RegexSet { program })
+    }
+
+    /// 保存しておいたプログラムから復元する
+    pub fn from_program(program: Program) -> Self {
+        RegexSet { program }
+    }
+
+    pub fn to_program(&self) -> Program {
+        self.program.clone()
+    }
+
+    /// lineのどこかにマッチしたパターンの番号を昇順で返す
+    pub fn matches(&self, line: &str) -> Result<Vec<usize>, DynError> {
+        if self.program.code.is_empty() {
+            return Ok(Vec::new());
+        }
+        let line = line.chars().collect::<Vec<char>>();
+        Ok(evaluator::eval_set(&self.program.code, &line)?)
+    }
+
+    pub fn is_match(&self, line: &str) -> Result<bool, DynError> {
+        Ok(!self.matches(line)?.is_empty())
+    }
+
+    pub fn len(&self) -> usize {
+        self.program.exprs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.program.exprs.is_empty()
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.program.exprs
+    }
+
+    pub fn code(&self) -> &[Instruction] {
+        &self.program.code
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegexSet;
+    use crate::engine::do_matching;
+
+    #[test]
+    fn test_matches() {
+        let set = RegexSet::new(&["error", "warn(ing)?", "a+b", "x|y"]).unwrap();
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.matches("an error occurred").unwrap(), vec![0]);
+        assert_eq!(set.matches("warn: error, aab").unwrap(), vec![0, 1, 2]);
+        assert_eq!(set.matches("y").unwrap(), vec![3]);
+        assert!(set.matches("nothing here").unwrap().is_empty());
+        assert!(!set.is_match("").unwrap());
+
+        let set = RegexSet::new::<&str>(&[]).unwrap();
+        assert!(set.matches("abc").unwrap().is_empty());
+
+        assert!(RegexSet::new(&["abc", "a\\d"]).is_err());
+    }
+
+    #[test]
+    fn test_same_as_single() {
+        let exprs = ["abc|def", "(abc)*d", "(ab|cd)+", "abc?", "a**b", "(a|b)(c|d)*e", "abd|abe"];
+        let lines = ["", "def", "xxabcabcd", "cdab", "ab", "aaab", "bccde", "zabez", "ae"];
+        let set = RegexSet::new(&exprs).unwrap();
+
+        for line in lines {
+            let expected = exprs
+                .iter()
+                .enumerate()
+                .filter(|(_, expr)| {
+                    line.char_indices()
+                        .map(|(i, _)| i)
+                        .chain([line.len()])
+                        .any(|i| do_matching(expr, &line[i..], true).unwrap())
+                })
+                .map(|(id, _)| id)
+                .collect::<Vec<usize>>();
+            assert_eq!(set.matches(line).unwrap(), expected, "line = {line}");
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! codegen前にASTを正規化する
+//!
+//! マッチする言語と、選択の優先順位を変えない範囲で以下の書き換えを行う。
+//!
+//! - 入れ子のSeqの平坦化と、要素が1つのSeqの除去
+//! - `x**`、`(x*)+`、`(x?)*`などの繰り返しの繰り返しを1つにまとめる
+//! - 隣接するOrの枝の共通接頭辞をくくり出す (`abc|abd` => `ab(c|d)`)
+//! - 隣接する1文字の枝を文字クラスにまとめる (`a|b|c` => `[abc]`)
+use super::parser::AST;
+
+pub fn simplify(ast: &AST) -> AST {
+    match ast {
+        AST::Char(c) => AST::Char(*c),
+        AST::Class(cs) => mk_class(cs.clone()),
+        AST::Any(is_newline) => AST::Any(*is_newline),
+        AST::Assert(a) => AST::Assert(*a),
+        AST::Plus(e) => mk_plus(simplify(e)),
+        AST::Star(e) => mk_star(simplify(e)),
+        AST::Question(e) => mk_question(simplify(e)),
+        AST::Or(..) => {
+            let mut alts = Vec::new();
+            collect_or(ast, &mut alts);
+            mk_or(alts.into_iter().map(simplify).collect())
+        }
+        AST::Seq(v) => mk_seq(v.iter().map(simplify).collect()),
+        // キャプチャの範囲が変わらないよう、グループの外とはまとめない
+        AST::Capture(i, name, e) => AST::Capture(*i, name.clone(), Box::new(simplify(e))),
+        AST::And(e1, e2) => AST::And(Box::new(simplify(e1)), Box::new(simplify(e2))),
+        AST::Not(e) => AST::Not(Box::new(simplify(e))),
+    }
+}
+
+/// 右結合のOrを枝のリストに展開する
+fn collect_or<'a>(ast: &'a AST, alts: &mut Vec<&'a AST>) {
+    if let AST::Or(e1, e2) = ast {
+        collect_or(e1, alts);
+        collect_or(e2, alts);
+    } else {
+        alts.push(ast);
+    }
+}
+
+/// ASTを連接の要素列として見る
+fn into_seq(ast: AST) -> Vec<AST> {
+    match ast {
+        AST::Seq(v) => v,
+        e => vec![e],
+    }
+}
+
+fn mk_seq(v: Vec<AST>) -> AST {
+    let mut seq = Vec::new();
+    for e in v {
+        seq.extend(into_seq(e));
+    }
+
+    if seq.len() == 1 {
+        seq.pop().unwrap()
+    } else {
+        AST::Seq(seq)
+    }
+}
+
+fn mk_star(e: AST) -> AST {
+    match e {
+        AST::Star(e) | AST::Plus(e) | AST::Question(e) => AST::Star(e),
+        e => AST::Star(Box::new(e)),
+    }
+}
+
+fn mk_plus(e: AST) -> AST {
+    match e {
+        AST::Star(e) | AST::Question(e) => AST::Star(e),
+        e @ AST::Plus(_) => e,
+        e => AST::Plus(Box::new(e)),
+    }
+}
+
+fn mk_question(e: AST) -> AST {
+    match e {
+        AST::Star(e) | AST::Plus(e) => AST::Star(e),
+        e @ AST::Question(_) => e,
+        e => AST::Question(Box::new(e)),
+    }
+}
+
+fn mk_class(mut cs: Vec<char>) -> AST {
+    let mut seen = Vec::with_capacity(cs.len());
+    cs.retain(|c| {
+        if seen.contains(c) {
+            false
+        } else {
+            seen.push(*c);
+            true
+        }
+    });
+
+    if cs.len() == 1 {
+        AST::Char(cs[0])
+    } else {
+        AST::Class(cs)
+    }
+}
+
+fn mk_or(alts: Vec<AST>) -> AST {
+    let alts = merge_classes(factor_prefix(alts));
+    fold_alts(alts)
+}
+
+/// 枝のリストを右結合のOrに戻す。最後の枝が空の場合は`?`とする
+fn fold_alts(mut alts: Vec<AST>) -> AST {
+    if alts.len() > 1 && alts.last() == Some(&AST::Seq(Vec::new())) {
+        alts.pop();
+        return mk_question(fold_alts(alts));
+    }
+
+    let mut ast = alts.pop().unwrap_or(AST::Seq(Vec::new()));
+    while let Some(e) = alts.pop() {
+        ast = AST::Or(Box::new(e), Box::new(ast));
+    }
+    ast
+}
+
+fn factor_prefix(alts: Vec<AST>) -> Vec<AST> {
+    let mut alts = alts.into_iter().map(into_seq).collect::<Vec<_>>();
+    let mut result = Vec::new();
+
+    while !alts.is_empty() {
+        // 先頭の要素が同じ枝を集める
+        let mut n = 1;
+        while n < alts.len() && !alts[0].is_empty() && alts[n].first() == alts[0].first() {
+            n += 1;
+        }
+
+        if n == 1 {
+            result.push(mk_seq(alts.remove(0)));
+            continue;
+        }
+
+        // 共通接頭辞の長さ。途中の枝の残りが空になる場合は、優先順位を保つためその枝までで区切る
+        let mut group = alts.drain(..n).collect::<Vec<_>>();
+        let mut len = common_prefix_len(&group);
+        if let Some(i) = group[..group.len() - 1].iter().position(|v| v.len() == len) {
+            let rest = group.split_off(i + 1);
+            alts.splice(0..0, rest);
+            len = common_prefix_len(&group);
+        }
+
+        let mut seq = group[0][..len].to_vec();
+        let suffixes = group
+            .into_iter()
+            .map(|mut v| AST::Seq(v.split_off(len)))
+            .collect();
+        seq.push(mk_or(suffixes));
+        result.push(mk_seq(seq));
+    }
+
+    result
+}
+
+fn common_prefix_len(group: &[Vec<AST>]) -> usize {
+    let mut len = 0;
+    while group.iter().all(|v| v.get(len).is_some() && v.get(len) == group[0].get(len)) {
+        len += 1;
+    }
+    len
+}
+
+fn merge_classes(alts: Vec<AST>) -> Vec<AST> {
+    let mut result: Vec<AST> = Vec::new();
+
+    for e in alts {
+        let cs = match &e {
+            AST::Char(c) => vec![*c],
+            AST::Class(cs) => cs.clone(),
+            _ => {
+                result.push(e);
+                continue;
+            }
+        };
+
+        match result.pop() {
+            Some(AST::Char(c)) => result.push(mk_class([vec![c], cs].concat())),
+            Some(AST::Class(prev)) => result.push(mk_class([prev, cs].concat())),
+            Some(prev) => {
+                result.push(prev);
+                result.push(e);
+            }
+            None => result.push(e),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::simplify;
+    use crate::engine::{
+        codegen::get_code,
+        evaluator::eval,
+        parser::{parse, AST},
+    };
+
+    fn simplified(expr: &str) -> AST {
+        simplify(&parse(expr).unwrap())
+    }
+
+    #[test]
+    fn test_collapse() {
+        let a_star = AST::Star(Box::new(AST::Char('a')));
+        assert_eq!(simplified("a**"), a_star);
+        assert_eq!(simplified("(a*)+"), a_star);
+        assert_eq!(simplified("(a?)*"), a_star);
+        assert_eq!(simplified("((((a*)*)*)*)"), a_star);
+        assert_eq!(simplified("(a+)+"), AST::Plus(Box::new(AST::Char('a'))));
+    }
+
+    #[test]
+    fn test_flatten() {
+        assert_eq!(simplified("a"), AST::Char('a'));
+        assert_eq!(
+            simplified("a(b(c))"),
+            AST::Seq(vec![AST::Char('a'), AST::Char('b'), AST::Char('c')])
+        );
+    }
+
+    #[test]
+    fn test_factor() {
+        assert_eq!(simplified("abc|abd"), simplified("ab(c|d)"));
+        assert_eq!(simplified("ab|a"), simplified("ab?"));
+        // 空の枝が先に来る場合は優先順位が変わるのでくくり出さない
+        assert_eq!(
+            simplified("a|ab"),
+            AST::Or(
+                Box::new(AST::Char('a')),
+                Box::new(AST::Seq(vec![AST::Char('a'), AST::Char('b')]))
+            )
+        );
+    }
+
+    #[test]
+    fn test_class() {
+        assert_eq!(simplified("a|b|c"), AST::Class(vec!['a', 'b', 'c']));
+        assert_eq!(simplified("a|a"), AST::Char('a'));
+        assert_eq!(
+            simplified("a|bc|d|e"),
+            AST::Or(
+                Box::new(AST::Char('a')),
+                Box::new(AST::Or(
+                    Box::new(AST::Seq(vec![AST::Char('b'), AST::Char('c')])),
+                    Box::new(AST::Class(vec!['d', 'e']))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn test_idempotent() {
+        for expr in ["abc|abd|abe|x", "(a|b)*c|(a|b)*d", "ab|a|b", "((a|b)|(c|d))+"] {
+            let ast = simplified(expr);
+            assert_eq!(simplify(&ast), ast, "{expr}");
+        }
+    }
+
+    #[test]
+    fn test_equivalence() {
+        let exprs = [
+            "abc|abd|abe",
+            "ab|a",
+            "a|ab",
+            "x(a|b|c)y",
+            "(ab|ac)+d",
+            "(a|b)(c|d)e|(a|b)f",
+        ];
+        let lines = [
+            "", "a", "ab", "abc", "abd", "abe", "abf", "xby", "xdy", "abacd", "abd", "ace", "bf",
+            "bde", "cf",
+        ];
+
+        for expr in exprs {
+            let ast = parse(expr).unwrap();
+            let plain = get_code(&ast).unwrap();
+            let simple = get_code(&simplify(&ast)).unwrap();
+
+            for line in lines {
+                let line = line.chars().collect::<Vec<char>>();
+                assert_eq!(
+                    eval(&plain, &line, true).unwrap(),
+                    eval(&simple, &line, true).unwrap(),
+                    "expr = {expr}, line = {:?}",
+                    line
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,415 @@
+//! std::io::Readなどから少しずつ届く入力を、全体を保持せずに検索する
+//!
+//! 全ての開始位置のスレッドを同時に進めるVMの状態をチャンク間で持ち越すため、
+//! マッチがチャンクの境界をまたいでも見つけられる。結果はProgram::find_allと同じ
+//! 重ならない最左のマッチで、位置は入力の先頭からのバイト単位のオフセット。
+//!
+//! 入力全体を1つの行として扱うため、`^`と`$`は入力の先頭と末尾にのみマッチする。
+//! 各行の先頭と末尾にもマッチさせるには`(?m)`を指定する。
+//! 保持するのは、確定前のマッチの終端より後に読んだ文字だけである。
+use super::{evaluator::EvalError, is_any_match, Assertion, Instruction, Program};
+use crate::helper::DynError;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::{self, Display},
+    io::{self, Read},
+    mem,
+};
+
+/// StreamMatchesが一度に読み込むバイト数
+pub const CHUNK_SIZE: usize = 8192;
+
+#[derive(Debug)]
+pub enum StreamError {
+    InvalidUtf8(usize), // 不正なバイト列の開始位置
+}
+
+impl Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StreamError: {:?}", self)
+    }
+}
+
+impl Error for StreamError {}
+
+#[derive(Debug, Clone, Copy)]
+struct Thread {
+    pc: usize,
+    n: usize,     // str命令内で照合済みの文字数
+    start: usize, // マッチの開始位置
+}
+
+/// 見つかったが、より優先度の高いスレッドが残っているため確定していないマッチ
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    start: usize,
+    end: usize,
+    prev: Option<char>, // 終端の直前の文字
+}
+
+/// チャンクを順に与えてマッチを探す
+///
+/// ```ignore
+/// let mut matcher = StreamMatcher::new(&program);
+/// for chunk in chunks {
+///     for (start, end) in matcher.feed(chunk)? { ... }
+/// }
+/// for (start, end) in matcher.finish()? { ... }
+/// ```
+#[derive(Debug)]
+pub struct StreamMatcher<'a> {
+    code: &'a [Instruction],
+    is_lookahead: bool, // 次の文字を見るアサーションを含むか
+    pos: usize,           // 次に読む文字のバイトオフセット
+    prev: Option<char>,   // posの直前の文字
+    threads: Vec<Thread>, // posで空遷移を辿る前のスレッド。優先度の高い順
+    candidate: Option<Candidate>,
+    is_skip_seed: bool, // 空マッチの直後は同じ位置から新たに開始しない
+    rest: VecDeque<(char, usize)>, // candidateの終端より後に読んだ文字。確定後に読み直す
+    input: VecDeque<(char, usize)>, // 未処理の文字とそのバイト数
+    partial: Vec<u8>, // チャンクの末尾で途切れたUTF-8の文字
+    visited: Vec<bool>,
+    found: Vec<(usize, usize)>,
+}
+
+impl<'a> StreamMatcher<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        StreamMatcher {
+            code: &program.code,
+            is_lookahead: program.code.iter().any(|inst| {
+                matches!(
+                    inst,
+                    Instruction::Assert(Assertion::TextEnd | Assertion::NoWordAfter | Assertion::LineEnd)
+                )
+            }),
+            pos: 0,
+            prev: None,
+            threads: Vec::new(),
+            candidate: None,
+            is_skip_seed: false,
+            rest: VecDeque::new(),
+            input: VecDeque::new(),
+            partial: Vec::new(),
+            visited: vec![false; program.code.len()],
+            found: Vec::new(),
+        }
+    }
+
+    /// これまでに与えたバイト数
+    pub fn offset(&self) -> usize {
+        self.pos + self.input.iter().map(|(_, len)| len).sum::<usize>() + self.partial.len()
+    }
+
+    /// 次のチャンクを与え、確定したマッチを返す
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<(usize, usize)>, DynError> {
+        let base = self.offset() - self.partial.len(); // bytes[0]の位置
+        let mut bytes = mem::take(&mut self.partial);
+        bytes.extend_from_slice(chunk);
+
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(s) => s,
+            Err(e) if e.error_len().is_some() => {
+                return Err(Box::new(StreamError::InvalidUtf8(base + e.valid_up_to())));
+            }
+            Err(e) => {
+                // 文字の途中で途切れているので、残りは次のチャンクと合わせて読む
+                self.partial = bytes[e.valid_up_to()..].to_vec();
+                std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap()
+            }
+        };
+        self.input.extend(valid.chars().map(|c| (c, c.len_utf8())));
+
+        self.process()?;
+        Ok(mem::take(&mut self.found))
+    }
+
+    /// 入力の終わりを伝え、残りのマッチを返す
+    pub fn finish(mut self) -> Result<Vec<(usize, usize)>, DynError> {
+        if !self.partial.is_empty() {
+            return Err(Box::new(StreamError::InvalidUtf8(self.offset() - self.partial.len())));
+        }
+
+        loop {
+            self.closure(None)?;
+            if !self.confirm() {
+                break;
+            }
+            self.process()?;
+        }
+        Ok(self.found)
+    }
+
+    fn process(&mut self) -> Result<(), DynError> {
+        while let Some((c, len)) = self.input.pop_front() {
+            let list = self.closure(Some(c))?;
+            if list.is_empty() && self.candidate.is_some() {
+                // 残るスレッドが無いのでマッチが確定した。cは終端から読み直す
+                self.input.push_front((c, len));
+                self.confirm();
+                continue;
+            }
+
+            if self.candidate.is_some() {
+                self.rest.push_back((c, len));
+            }
+            self.step(&list, c);
+            self.pos += len;
+            self.prev = Some(c);
+
+            // 次の文字を待たずに済む場合は、ここでmatch命令に到達するか調べておく
+            if !self.is_lookahead {
+                self.threads = self.closure(None)?;
+            }
+            if self.threads.is_empty() && self.candidate.is_some() {
+                self.confirm();
+            }
+        }
+        Ok(())
+    }
+
+    /// 確定待ちのマッチがあれば確定させ、その終端から検索をやり直す
+    fn confirm(&mut self) -> bool {
+        let Some(m) = self.candidate.take() else {
+            return false;
+        };
+
+        self.found.push((m.start, m.end));
+        self.pos = m.end;
+        self.prev = m.prev;
+        self.threads.clear();
+        // 空マッチの場合は、find_allと同じく次の文字から開始する
+        self.is_skip_seed = m.start == m.end;
+        while let Some(x) = self.rest.pop_back() {
+            self.input.push_front(x);
+        }
+        true
+    }
+
+    /// posで空遷移を辿り、文字を待つスレッドを優先度の高い順に返す
+    ///
+    /// match命令に到達した場合は、それより優先度の低いスレッドを捨てる
+    fn closure(&mut self, next: Option<char>) -> Result<Vec<Thread>, EvalError> {
+        let mut seeds = mem::take(&mut self.threads);
+        // マッチが見つかった後は、それより右から始まるマッチを探さない
+        if self.candidate.is_none() && !self.is_skip_seed {
+            seeds.push(Thread { pc: 0, n: 0, start: self.pos });
+        }
+        self.is_skip_seed = false;
+        self.visited.iter_mut().for_each(|v| *v = false);
+
+        let mut list = Vec::new();
+        'seeds: for t in seeds {
+            if t.n > 0 {
+                list.push(t);
+                continue;
+            }
+
+            let mut stack = vec![t.pc];
+            while let Some(pc) = stack.pop() {
+                match self.visited.get(pc) {
+                    Some(true) => continue,
+                    Some(false) => self.visited[pc] = true,
+                    None => return Err(EvalError::InvalidPC),
+                }
+
+                match &self.code[pc] {
+                    Instruction::Jump(addr) => stack.push(*addr),
+                    Instruction::Split(addr1, addr2) => {
+                        stack.push(*addr2);
+                        stack.push(*addr1);
+                    }
+                    Instruction::Assert(a) => {
+                        if a.is_match_between(self.prev, next) {
+                            stack.push(pc + 1);
+                        }
+                    }
+                    Instruction::Save(_) => stack.push(pc + 1),
+                    Instruction::Match(_) => {
+                        self.candidate = Some(Candidate { start: t.start, end: self.pos, prev: self.prev });
+                        self.rest.clear();
+                        break 'seeds;
+                    }
+                    _ => list.push(Thread { pc, n: 0, start: t.start }),
+                }
+            }
+        }
+        Ok(list)
+    }
+
+    /// 文字cを読み、次の位置のスレッドを作る
+    fn step(&mut self, list: &[Thread], c: char) {
+        for t in list {
+            let next = match &self.code[t.pc] {
+                Instruction::Char(ch) if *ch == c => Thread { pc: t.pc + 1, n: 0, ..*t },
+                Instruction::Class(cs) if cs.contains(&c) => Thread { pc: t.pc + 1, n: 0, ..*t },
+                Instruction::Any(is_newline) if is_any_match(*is_newline, c) => Thread { pc: t.pc + 1, n: 0, ..*t },
+                Instruction::Str(s) if s.get(t.n) == Some(&c) => {
+                    if t.n + 1 < s.len() {
+                        Thread { n: t.n + 1, ..*t }
+                    } else {
+                        Thread { pc: t.pc + 1, n: 0, ..*t }
+                    }
+                }
+                _ => continue,
+            };
+            self.threads.push(next);
+        }
+    }
+}
+
+/// readerを最後まで読み、マッチを順に返すイテレータ
+pub struct StreamMatches<'a, R> {
+    matcher: Option<StreamMatcher<'a>>,
+    reader: R,
+    buf: Vec<u8>,
+    found: VecDeque<(usize, usize)>,
+}
+
+impl<'a, R: Read> StreamMatches<'a, R> {
+    pub fn new(program: &'a Program, reader: R) -> Self {
+        Self::with_chunk_size(program, reader, CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(program: &'a Program, reader: R, chunk_size: usize) -> Self {
+        StreamMatches {
+            matcher: Some(StreamMatcher::new(program)),
+            reader,
+            buf: vec![0; chunk_size.max(1)],
+            found: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for StreamMatches<'_, R> {
+    type Item = Result<(usize, usize), DynError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(m) = self.found.pop_front() {
+                return Some(Ok(m));
+            }
+            let matcher = self.matcher.as_mut()?;
+
+            let result = match self.reader.read(&mut self.buf) {
+                Ok(0) => self.matcher.take().unwrap().finish(),
+                Ok(n) => matcher.feed(&self.buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(found) => self.found.extend(found),
+                Err(e) => {
+                    // エラーの後は何も返さない
+                    self.matcher = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamMatcher, StreamMatches};
+    use crate::engine::{Flags, Program};
+
+    /// find_allの結果を文字単位からバイト単位の位置に直す
+    fn find_all_bytes(program: &Program, line: &str) -> Vec<(usize, usize)> {
+        let chars = line.chars().collect::<Vec<char>>();
+        let offset = |i: usize| chars[..i].iter().map(|c| c.len_utf8()).sum::<usize>();
+        program
+            .find_all(&chars)
+            .unwrap()
+            .into_iter()
+            .map(|(b, e)| (offset(b), offset(e)))
+            .collect()
+    }
+
+    #[test]
+    fn test_same_as_find_all() {
+        let exprs = [
+            "abc", "ab|a", "a|ab", "(a|ab)(c|bcd)", "a*", "a+b", "x(a|b)*y", "あ+い", "(ab)*c",
+        ];
+        let lines = [
+            "", "abc", "ac", "abcd", "xabcdx", "aaa baab", "xyxaby xbaay", "ああいあい", "ababc abab",
+            "aababab",
+        ];
+
+        for expr in exprs {
+            for flags in [Flags::default(), Flags { is_word: true, ..Default::default() }] {
+                let program = Program::with_flags(expr, flags, true).unwrap();
+                for line in lines {
+                    let expected = find_all_bytes(&program, line);
+                    for size in 1..=4 {
+                        let found = StreamMatches::with_chunk_size(&program, line.as_bytes(), size)
+                            .collect::<Result<Vec<_>, _>>()
+                            .unwrap();
+                        assert_eq!(found, expected, "expr = {expr}, line = {line:?}, size = {size}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_multi_line() {
+        let exprs = ["(?m)^a", "(?m)a$", "(?m)^$", "a.b", "(?s)a.b", "(?m)b$\nc", "(?ms)^.*$"];
+        let lines = ["ab\nab", "a\nb\n", "\n\naab\nb", "a\nb\nc", "a"];
+
+        for expr in exprs {
+            let program = Program::new(expr, true).unwrap();
+            for line in lines {
+                let expected = find_all_bytes(&program, line);
+                for size in 1..=3 {
+                    let found = StreamMatches::with_chunk_size(&program, line.as_bytes(), size)
+                        .collect::<Result<Vec<_>, _>>()
+                        .unwrap();
+                    assert_eq!(found, expected, "expr = {expr}, line = {line:?}, size = {size}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_line_flag() {
+        let program = Program::with_flags("ab", Flags { is_line: true, ..Default::default() }, true).unwrap();
+        let found = |s: &str| program.stream_matches(s.as_bytes()).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(found("ab"), vec![(0, 2)]);
+        assert!(found("abab").is_empty());
+    }
+
+    #[test]
+    fn test_feed() {
+        let program = Program::new("error", true).unwrap();
+        let mut matcher = StreamMatcher::new(&program);
+        assert!(matcher.feed(b"ok\nerr").unwrap().is_empty());
+        assert_eq!(matcher.feed(b"or\nok\n").unwrap(), vec![(3, 8)]);
+        assert_eq!(matcher.offset(), 12);
+        assert_eq!(matcher.feed(b"error").unwrap(), vec![(12, 17)]);
+        assert!(matcher.finish().unwrap().is_empty());
+
+        // 確定は入力の終わりまで待つ
+        let program = Program::new("ab*", true).unwrap();
+        let mut matcher = StreamMatcher::new(&program);
+        assert!(matcher.feed(b"abb").unwrap().is_empty());
+        assert_eq!(matcher.finish().unwrap(), vec![(0, 3)]);
+    }
+
+    #[test]
+    fn test_utf8() {
+        let program = Program::new("い", true).unwrap();
+        // 「い」(E3 81 84)がチャンクをまたぐ
+        let mut matcher = StreamMatcher::new(&program);
+        assert!(matcher.feed(b"a\xe3\x81").unwrap().is_empty());
+        assert_eq!(matcher.feed(b"\x84").unwrap(), vec![(1, 4)]);
+
+        let found = program.stream_matches(&b"a\xffb"[..]).collect::<Vec<_>>();
+        assert_eq!(found.len(), 1);
+        assert!(found[0].as_ref().unwrap_err().to_string().contains("InvalidUtf8(1)"));
+
+        let mut matcher = StreamMatcher::new(&program);
+        matcher.feed(b"a\xe3").unwrap();
+        assert!(matcher.finish().is_err());
+    }
+}
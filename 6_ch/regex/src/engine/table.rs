@@ -0,0 +1,393 @@
+//! 最小化したDFAの遷移表と、その書き出し
+//!
+//! 遷移が全く同じ記号は1つの文字クラスにまとめ、状態×文字クラスの密な表にする。
+//! 書き出した表は、インタプリタを載せられない環境でも配列の参照だけで照合に使える。
+//! 照合は文字列全体に対して行う (Dfaと同じ) 。
+//!
+//! 文字から文字クラスへの対応は、charsに挙げた文字と、それ以外の単語構成文字
+//! (word_class) 、それ以外の文字 (other_class) の3通りである。
+use super::{dfa::Dfa, rustgen::is_ident};
+use crate::helper::{json_string, DynError};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DenseTable {
+    pub chars: Vec<(char, usize)>, // 既定と異なる文字クラスに属する文字。文字の昇順
+    pub word_class: usize,
+    pub other_class: usize,
+    pub n_classes: usize,
+    pub next: Vec<Vec<usize>>, // next[状態][文字クラス]。状態0が初期状態
+    pub is_accept: Vec<bool>,
+}
+
+impl DenseTable {
+    /// dfaを最小化して表を作る
+    pub fn new(dfa: &Dfa) -> Self {
+        let dfa = dfa.minimize();
+        let alphabet = &dfa.alphabet;
+        let column = |s: usize| dfa.next.iter().map(|row| row[s]).collect::<Vec<_>>();
+
+        // 既定の2つのクラスを先に番号付けし、同じ列を持つ記号は同じクラスにする
+        let (word, other) = alphabet.default_symbols();
+        let mut columns: Vec<Vec<usize>> = Vec::new();
+        let mut class_of = |s: usize| {
+            let col = column(s);
+            columns.iter().position(|c| *c == col).unwrap_or_else(|| {
+                columns.push(col);
+                columns.len() - 1
+            })
+        };
+        let word_class = class_of(word);
+        let other_class = class_of(other);
+
+        let mut chars = Vec::new();
+        // 各クラスを代表する記号
+        let mut symbols = vec![word];
+        if other_class != word_class {
+            symbols.push(other);
+        }
+        for (s, c) in alphabet.chars().iter().enumerate() {
+            let class = class_of(s);
+            let default = if super::is_word_char(*c) { word_class } else { other_class };
+            if class != default {
+                chars.push((*c, class));
+            }
+            if class == symbols.len() {
+                symbols.push(s);
+            }
+        }
+
+        DenseTable {
+            chars,
+            word_class,
+            other_class,
+            n_classes: symbols.len(),
+            next: dfa.next.iter().map(|row| symbols.iter().map(|s| row[*s]).collect()).collect(),
+            is_accept: dfa.is_accept,
+        }
+    }
+
+    /// 文字cの文字クラス
+    pub fn class(&self, c: char) -> usize {
+        match self.chars.binary_search_by_key(&c, |(c, _)| *c) {
+            Ok(i) => self.chars[i].1,
+            Err(_) if super::is_word_char(c) => self.word_class,
+            Err(_) => self.other_class,
+        }
+    }
+
+    /// 文字列全体を受理するか
+    pub fn is_match(&self, s: &str) -> bool {
+        let state = s.chars().fold(0, |state, c| self.next[state][self.class(c)]);
+        self.is_accept[state]
+    }
+
+    /// 状態と文字クラスの番号を収める符号なし整数のビット数
+    fn bits(&self) -> u32 {
+        if self.next.len().max(self.n_classes) <= 256 {
+            8
+        } else {
+            16
+        }
+    }
+
+    pub fn to_json(&self, expr: &str) -> String {
+        let list = |v: &mut dyn Iterator<Item = String>| v.collect::<Vec<_>>().join(",");
+        let chars = list(&mut self.chars.iter().map(|(c, class)| format!("[{},{class}]", *c as u32)));
+        let accept = list(&mut self.is_accept.iter().map(|a| a.to_string()));
+        let next = list(&mut self.next.iter().map(|row| format!("[{}]", list(&mut row.iter().map(|q| q.to_string())))));
+
+        let mut out = String::new();
+        writeln!(out, "{{").unwrap();
+        writeln!(out, "  \"pattern\": {},", json_string(expr)).unwrap();
+        writeln!(out, "  \"states\": {},", self.next.len()).unwrap();
+        writeln!(out, "  \"start\": 0,").unwrap();
+        writeln!(out, "  \"classes\": {},", self.n_classes).unwrap();
+        writeln!(out, "  \"chars\": [{chars}],").unwrap();
+        writeln!(out, "  \"word_class\": {},", self.word_class).unwrap();
+        writeln!(out, "  \"other_class\": {},", self.other_class).unwrap();
+        writeln!(out, "  \"accept\": [{accept}],").unwrap();
+        writeln!(out, "  \"next\": [{next}]").unwrap();
+        writeln!(out, "}}").unwrap();
+        out
+    }
+
+    /// `{name}_next`などの配列と、UTF-32の文字列を照合する`{name}_is_match`を定義するCのコード
+    ///
+    /// word_classとother_classが異なる場合は、is_word_charと同じ判定をするため、
+    /// ASCII以外の単語構成文字の範囲の表も書き出す
+    pub fn to_c(&self, expr: &str, name: &str) -> Result<String, DynError> {
+        check_name(name)?;
+        let ty = format!("uint{}_t", self.bits());
+        let (n_states, n_classes) = (self.next.len(), self.n_classes);
+
+        let mut out = String::new();
+        writeln!(out, "/* Generated by regex table from `{}`. Do not edit. */", c_comment(expr)).unwrap();
+        writeln!(out, "#include <stdbool.h>\n#include <stddef.h>\n#include <stdint.h>\n").unwrap();
+        // Cでは要素数0の配列を定義できないので、文字が無ければ探索ごと省く
+        if !self.chars.is_empty() {
+            writeln!(out, "static const uint32_t {name}_chars[] = {{{}}};", self.join_chars(|c, _| format!("0x{:X}", c as u32)))
+                .unwrap();
+            writeln!(out, "static const {ty} {name}_char_class[] = {{{}}};", self.join_chars(|_, class| class.to_string()))
+                .unwrap();
+        }
+        writeln!(out, "static const bool {name}_accept[{n_states}] = {{{}}};", join(self.is_accept.iter().map(|a| *a as u8)))
+            .unwrap();
+        writeln!(out, "static const {ty} {name}_next[{n_states}][{n_classes}] = {{").unwrap();
+        for row in &self.next {
+            writeln!(out, "    {{{}}},", join(row.iter())).unwrap();
+        }
+        writeln!(out, "}};\n").unwrap();
+
+        let ranges = if self.word_class != self.other_class { word_ranges() } else { Vec::new() };
+        if !ranges.is_empty() {
+            writeln!(out, "static const uint32_t {name}_word_ranges[{}][2] = {{", ranges.len()).unwrap();
+            for line in ranges.chunks(6) {
+                let line = line.iter().map(|(lo, hi)| format!("{{0x{lo:X}, 0x{hi:X}}}")).collect::<Vec<_>>().join(", ");
+                writeln!(out, "    {line},").unwrap();
+            }
+            writeln!(out, "}};\n").unwrap();
+        }
+
+        writeln!(out, "static {ty} {name}_class(uint32_t c) {{").unwrap();
+        if !self.chars.is_empty() {
+            writeln!(out, "    size_t lo = 0, hi = {};", self.chars.len()).unwrap();
+            writeln!(out, "    while (lo < hi) {{").unwrap();
+            writeln!(out, "        size_t mid = (lo + hi) / 2;").unwrap();
+            writeln!(out, "        if ({name}_chars[mid] == c) return {name}_char_class[mid];").unwrap();
+            writeln!(out, "        if ({name}_chars[mid] < c) lo = mid + 1; else hi = mid;").unwrap();
+            writeln!(out, "    }}").unwrap();
+        }
+        if ranges.is_empty() {
+            writeln!(out, "    return {};", self.word_class).unwrap();
+        } else {
+            let (word, other) = (self.word_class, self.other_class);
+            writeln!(out, "    if (c < 0x80) {{").unwrap();
+            writeln!(
+                out,
+                "        bool is_word = c == '_' || (c >= '0' && c <= '9') || ((c | 0x20) >= 'a' && (c | 0x20) <= 'z');"
+            )
+            .unwrap();
+            writeln!(out, "        return is_word ? {word} : {other};").unwrap();
+            writeln!(out, "    }}").unwrap();
+            // charsの探索で宣言した変数は使い回す
+            let decl = if self.chars.is_empty() { "size_t " } else { "" };
+            writeln!(out, "    {decl}lo = 0, hi = {};", ranges.len()).unwrap();
+            writeln!(out, "    while (lo < hi) {{").unwrap();
+            writeln!(out, "        size_t mid = (lo + hi) / 2;").unwrap();
+            writeln!(out, "        if (c < {name}_word_ranges[mid][0]) hi = mid;").unwrap();
+            writeln!(out, "        else if (c > {name}_word_ranges[mid][1]) lo = mid + 1;").unwrap();
+            writeln!(out, "        else return {word};").unwrap();
+            writeln!(out, "    }}").unwrap();
+            writeln!(out, "    return {other};").unwrap();
+        }
+        writeln!(out, "}}\n").unwrap();
+
+        writeln!(out, "bool {name}_is_match(const uint32_t *s, size_t len) {{").unwrap();
+        writeln!(out, "    {ty} state = 0;").unwrap();
+        writeln!(out, "    for (size_t i = 0; i < len; i++) state = {name}_next[state][{name}_class(s[i])];").unwrap();
+        writeln!(out, "    return {name}_accept[state];").unwrap();
+        writeln!(out, "}}").unwrap();
+        Ok(out)
+    }
+
+    /// `{NAME}_NEXT`などの定数と、`pub fn {name}(s: &str) -> bool`を定義するRustのコード
+    pub fn to_rust(&self, expr: &str, name: &str) -> Result<String, DynError> {
+        check_name(name)?;
+        let ty = format!("u{}", self.bits());
+        let upper = name.to_ascii_uppercase();
+        let (n_states, n_classes) = (self.next.len(), self.n_classes);
+
+        let mut out = String::new();
+        writeln!(out, "/// Generated by regex table from `{}`. Do not edit.", expr.escape_default()).unwrap();
+        writeln!(
+            out,
+            "pub const {upper}_CHARS: [(char, {ty}); {}] = [{}];",
+            self.chars.len(),
+            self.join_chars(|c, class| format!("({c:?}, {class})"))
+        )
+        .unwrap();
+        writeln!(out, "pub const {upper}_WORD_CLASS: {ty} = {};", self.word_class).unwrap();
+        writeln!(out, "pub const {upper}_OTHER_CLASS: {ty} = {};", self.other_class).unwrap();
+        writeln!(out, "pub const {upper}_ACCEPT: [bool; {n_states}] = [{}];", join(self.is_accept.iter())).unwrap();
+        writeln!(out, "pub const {upper}_NEXT: [[{ty}; {n_classes}]; {n_states}] = [").unwrap();
+        for row in &self.next {
+            writeln!(out, "    [{}],", join(row.iter())).unwrap();
+        }
+        writeln!(out, "];\n").unwrap();
+
+        writeln!(out, "pub fn {name}(s: &str) -> bool {{").unwrap();
+        writeln!(out, "    let mut state = 0;").unwrap();
+        writeln!(out, "    for c in s.chars() {{").unwrap();
+        writeln!(out, "        let class = match {upper}_CHARS.binary_search_by_key(&c, |(c, _)| *c) {{").unwrap();
+        writeln!(out, "            Ok(i) => {upper}_CHARS[i].1,").unwrap();
+        writeln!(out, "            Err(_) if c.is_alphanumeric() || c == '_' => {upper}_WORD_CLASS,").unwrap();
+        writeln!(out, "            Err(_) => {upper}_OTHER_CLASS,").unwrap();
+        writeln!(out, "        }};").unwrap();
+        writeln!(out, "        state = {upper}_NEXT[state][class as usize] as usize;").unwrap();
+        writeln!(out, "    }}").unwrap();
+        writeln!(out, "    {upper}_ACCEPT[state]").unwrap();
+        writeln!(out, "}}").unwrap();
+        Ok(out)
+    }
+
+    fn join_chars(&self, f: impl Fn(char, usize) -> String) -> String {
+        self.chars.iter().map(|(c, class)| f(*c, *class)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// is_word_charが真となるASCII以外の文字の範囲。両端を含む
+fn word_ranges() -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for c in ('\u{80}'..=char::MAX).filter(|c| super::is_word_char(*c)) {
+        let c = c as u32;
+        match ranges.last_mut() {
+            Some((_, hi)) if *hi + 1 == c => *hi = c,
+            _ => ranges.push((c, c)),
+        }
+    }
+    ranges
+}
+
+fn check_name(name: &str) -> Result<(), DynError> {
+    if is_ident(name) {
+        Ok(())
+    } else {
+        Err(format!("invalid name: {name}").into())
+    }
+}
+
+fn join<T: ToString>(v: impl Iterator<Item = T>) -> String {
+    v.map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Cのコメント中に書けるよう、コメントの終わりと制御文字をエスケープする
+fn c_comment(s: &str) -> String {
+    s.escape_default().to_string().replace("*/", "*\\/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DenseTable;
+    use crate::engine::{Assertion, Dfa, AST};
+    use std::{env, fs, process::Command};
+
+    #[test]
+    fn test_table() {
+        let table = DenseTable::new(&Dfa::new("(a|b)*abb").unwrap());
+        assert_eq!(table.next.len(), 5);
+        // aとb、それ以外の文字の3クラス
+        assert_eq!(table.n_classes, 3);
+        assert_eq!(table.chars, vec![('a', 1), ('b', 2)]);
+        assert_eq!(table.word_class, table.other_class);
+        assert!(table.is_match("babb") && !table.is_match("abab") && !table.is_match("xabb"));
+
+        let json = DenseTable::new(&Dfa::new("a+").unwrap()).to_json("a+");
+        assert_eq!(
+            json,
+            "{\n  \"pattern\": \"a+\",\n  \"states\": 3,\n  \"start\": 0,\n  \"classes\": 2,\n  \
+             \"chars\": [[97,1]],\n  \"word_class\": 0,\n  \"other_class\": 0,\n  \
+             \"accept\": [false,false,true],\n  \"next\": [[1,2],[1,1],[1,2]]\n}\n"
+        );
+
+        let c = DenseTable::new(&Dfa::new("x*/").unwrap()).to_c("x*/", "m").unwrap();
+        assert!(DenseTable::new(&Dfa::new("a").unwrap()).to_rust("a", "1m").is_err());
+        assert!(c.starts_with("/* Generated by regex table from `x*\\/`. Do not edit. */\n"), "{c}");
+        assert!(c.contains("static const uint8_t m_next[3][3] = {\n"), "{c}");
+        assert!(c.contains("bool m_is_match(const uint32_t *s, size_t len) {\n"), "{c}");
+    }
+
+    /// 書き出したRustのコードをrustcでコンパイル・実行し、表と結果を比べる
+    #[test]
+    fn test_rust_output() {
+        let exprs = ["(a|b)*abb", "(?m)^d?x$\\n?", "あ.い|(?s:.)", "\\(\\)'\\\\"];
+        let lines = ["", "abb", "aabb", "x\n", "\nx", "dx", "あaい", "\n", "()'\\", "é"];
+
+        let mut src = String::new();
+        let mut expected = String::new();
+        for (i, expr) in exprs.iter().enumerate() {
+            let table = DenseTable::new(&Dfa::new(expr).unwrap());
+            src.push_str(&table.to_rust(expr, &format!("m{i}")).unwrap());
+            for line in lines {
+                expected.push_str(&format!("{}\n", table.is_match(line)));
+            }
+        }
+        src.push_str("fn main() {\n");
+        src.push_str(&format!("    let lines = {lines:?};\n"));
+        for i in 0..exprs.len() {
+            src.push_str(&format!("    for line in lines {{ println!(\"{{}}\", m{i}(line)); }}\n"));
+        }
+        src.push_str("}\n");
+
+        let dir = env::temp_dir().join(format!("regex_table_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let src_path = dir.join("table.rs");
+        let bin_path = dir.join("table");
+        fs::write(&src_path, src).unwrap();
+
+        let status = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string()))
+            .arg("--edition=2021")
+            .arg("-o")
+            .arg(&bin_path)
+            .arg(&src_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = Command::new(&bin_path).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+
+    /// 書き出したCのコードをccでコンパイル・実行し、表と結果を比べる
+    #[test]
+    fn test_c_output() {
+        let mut tables: Vec<DenseTable> =
+            ["(a|b)*abb", "(?m)^d?x$\\n?", "あ.い|(?s:.)"].iter().map(|expr| DenseTable::new(&Dfa::new(expr).unwrap())).collect();
+        // 直後が単語構成文字でないa。ASCII以外の文字もis_word_charと同じく分類する
+        let ast = AST::Seq(vec![AST::Char('a'), AST::Assert(Assertion::NoWordAfter), AST::Any(false)]);
+        tables.push(DenseTable::new(&Dfa::from_ast(&ast).unwrap()));
+        assert_ne!(tables[3].word_class, tables[3].other_class);
+        let lines = ["", "abb", "aabb", "x\n", "dx", "あaい", "\n", "a、", "aé", "aあ", "a-", "a_", "a\u{10FFFF}", "a\u{1F600}"];
+
+        let mut src = String::from("#include <stdio.h>\n");
+        let mut expected = String::new();
+        for (i, table) in tables.iter().enumerate() {
+            src.push_str(&table.to_c("", &format!("m{i}")).unwrap());
+            for line in lines {
+                expected.push_str(&format!("{}\n", table.is_match(line)));
+            }
+        }
+        src.push_str("int main(void) {\n");
+        for (j, line) in lines.iter().enumerate() {
+            // 要素数0の配列を避けるため、末尾に0を置く
+            let code_points = line.chars().map(|c| format!("0x{:X}, ", c as u32)).collect::<String>();
+            src.push_str(&format!("    static const uint32_t l{j}[] = {{{code_points}0}};\n"));
+        }
+        for i in 0..tables.len() {
+            for (j, line) in lines.iter().enumerate() {
+                let len = line.chars().count();
+                src.push_str(&format!("    puts(m{i}_is_match(l{j}, {len}) ? \"true\" : \"false\");\n"));
+            }
+        }
+        src.push_str("    return 0;\n}\n");
+
+        let dir = env::temp_dir().join(format!("regex_table_c_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let src_path = dir.join("table.c");
+        let bin_path = dir.join("table");
+        fs::write(&src_path, src).unwrap();
+
+        let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+            .arg("-o")
+            .arg(&bin_path)
+            .arg(&src_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = Command::new(&bin_path).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+}
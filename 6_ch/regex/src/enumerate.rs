@@ -0,0 +1,121 @@
+//! `regex enumerate PATTERN`: 式がマッチする文字列を短い順に全て書き出す
+use crate::grep;
+use regex::{DynError, Enumerate, Flags, Program};
+use std::io::{self, Write};
+
+pub const USAGE: &str = "\
+usage: regex enumerate [-i] [-w] [--max-len=N] [--limit=N] [--alphabet=CHARS] [-z] PATTERN
+
+Print every string that PATTERN matches as a whole, shortest first and in
+code point order among strings of the same length.
+
+  -i               ignore case distinctions
+  -w               match only whole words
+  --max-len=N      print strings of at most N characters (default: 8)
+  --limit=N        stop after printing N strings (default: 1000)
+  --alphabet=CHARS build strings only from CHARS. By default, the characters
+                   in PATTERN are used, plus one word character, one other
+                   character and newline standing for the rest where `.`
+                   can match them
+  -z               end each string with NUL instead of newline
+
+Exit status is 0 if any string was printed, 1 if none, and 2 if an error occurred.";
+
+#[derive(Debug)]
+struct Options {
+    flags: Flags,
+    max_len: usize,
+    limit: usize,
+    alphabet: Option<Vec<char>>,
+    is_null_data: bool,
+    pattern: String,
+}
+
+pub fn main(args: &[String]) -> i32 {
+    let opts = match parse_args(args) {
+        Ok(opts) => opts,
+        Err(e) => return grep::usage_error(&e, USAGE),
+    };
+    if let Err(e) = grep::check_pattern(&opts.pattern) {
+        eprintln!("{e}");
+        return 2;
+    }
+
+    match enumerate(&opts, &mut io::stdout().lock()) {
+        Ok(0) => 1,
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("regex: {e}");
+            2
+        }
+    }
+}
+
+/// 引数を解釈する。Errが空文字列であれば使い方だけを表示する
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut max_len = 8;
+    let mut limit = 1000;
+    let mut alphabet = None;
+    let mut is_null_data = false;
+
+    let number = |name: &str, s: &str| s.parse::<usize>().map_err(|_| format!("invalid {name}: {s}"));
+    let (flags, patterns) = grep::parse_subcommand_args(args, false, |arg, _| {
+        if arg == "-z" {
+            is_null_data = true;
+        } else if let Some(s) = arg.strip_prefix("--max-len=") {
+            max_len = number("max length", s)?;
+        } else if let Some(s) = arg.strip_prefix("--limit=") {
+            limit = number("limit", s)?;
+        } else if let Some(s) = arg.strip_prefix("--alphabet=") {
+            alphabet = Some(s.chars().collect());
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    })?;
+
+    let [pattern] = <[String; 1]>::try_from(patterns).map_err(|_| String::new())?;
+    Ok(Options { flags, max_len, limit, alphabet, is_null_data, pattern })
+}
+
+/// 文字列を書き出し、その個数を返す
+fn enumerate<W: Write>(opts: &Options, out: &mut W) -> Result<usize, DynError> {
+    let program = Program::with_flags(&opts.pattern, opts.flags, true)?;
+    let strings = match &opts.alphabet {
+        Some(alphabet) => Enumerate::with_alphabet(&program, alphabet.clone(), opts.max_len, opts.limit),
+        None => program.enumerate(opts.max_len, opts.limit),
+    };
+    let terminator = if opts.is_null_data { '\0' } else { '\n' };
+    let mut count = 0;
+    for s in strings {
+        write!(out, "{s}{terminator}")?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enumerate, parse_args};
+
+    fn run(args: &str) -> (String, usize) {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        let opts = parse_args(&args).unwrap();
+        let mut out = Vec::new();
+        let count = enumerate(&opts, &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), count)
+    }
+
+    #[test]
+    fn test_enumerate() {
+        assert_eq!(run("(a|b)c?"), ("a\nb\nac\nbc\n".into(), 4));
+        assert_eq!(run("--max-len=2 a*"), ("\na\naa\n".into(), 3));
+        assert_eq!(run("--limit=2 -z a*"), ("\0a\0".into(), 2));
+        assert_eq!(run("-i --max-len=1 a"), ("A\na\n".into(), 2));
+        assert_eq!(run("--alphabet=xy --max-len=2 x."), ("xx\nxy\n".into(), 2));
+        assert_eq!(run("a^b"), (String::new(), 0));
+
+        assert!(parse_args(&["--limit=x".to_string(), "a".to_string()]).is_err());
+        assert!(parse_args(&[]).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! --extract: 名前付きグループにマッチした部分をJSON LinesまたはCSVで出力する
+//!
+//! 1つのマッチが1レコードとなり、ファイル名、行番号、名前付きグループの順に並べる。
+//! マッチに関与しなかったグループはJSONではnull、CSVでは空欄とする。
+use regex::{json_string, Captures, DynError, Program};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+}
+
+/// ファイル名と行番号の列名。グループ名と重なってはならない
+const FILE: &str = "file";
+const LINE: &str = "line";
+
+/// 出力する名前付きグループの(番号, 名前)
+pub fn fields(program: &Program) -> Vec<(usize, &str)> {
+    program
+        .group_names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| !name.is_empty())
+        .map(|(i, name)| (i + 1, name.as_str()))
+        .collect()
+}
+
+/// --extractで使えるプログラムか確かめる
+pub fn check(program: &Program) -> Result<(), String> {
+    let fields = fields(program);
+    if fields.is_empty() {
+        return Err("--extract requires at least one named group (?<name>...)".to_string());
+    }
+    if let Some((_, name)) = fields.iter().find(|(_, name)| [FILE, LINE].contains(name)) {
+        return Err(format!("--extract reserves the group name '{name}' for its own column"));
+    }
+    Ok(())
+}
+
+/// 行の中の重ならないマッチを全て左から順に返す。空マッチはfind_allと同じく除かない
+pub fn captures_all(program: &Program, chars: &[char]) -> Result<Vec<Captures>, DynError> {
+    let mut all = Vec::new();
+    let mut start = 0;
+    while start <= chars.len() {
+        let Some(caps) = program.captures_at(chars, start)? else {
+            break;
+        };
+        let (begin, end) = caps[0].unwrap();
+        all.push(caps);
+        // 空マッチの場合は1文字進めて無限ループを防ぐ
+        start = if end == begin { end + 1 } else { end };
+    }
+    Ok(all)
+}
+
+/// CSVの見出し行を書き出す
+pub fn write_header<W: Write>(program: &Program, out: &mut W) -> io::Result<()> {
+    let mut row = vec![FILE.to_string(), LINE.to_string()];
+    row.extend(fields(program).into_iter().map(|(_, name)| name.to_string()));
+    writeln!(out, "{}", row.iter().map(|s| csv_field(s)).collect::<Vec<_>>().join(","))
+}
+
+/// 1つのマッチを1レコードとして書き出す。nは0から数えた行番号
+pub fn write_record<W: Write>(
+    format: Format,
+    program: &Program,
+    name: &str,
+    n: usize,
+    chars: &[char],
+    caps: &Captures,
+    out: &mut W,
+) -> io::Result<()> {
+    let values = fields(program)
+        .into_iter()
+        .map(|(i, field)| (field, caps[i].map(|(b, e)| chars[b..e].iter().collect::<String>())))
+        .collect::<Vec<_>>();
+
+    match format {
+        Format::Json => {
+            let mut obj = format!("{{{}:{},{}:{}", json_string(FILE), json_string(name), json_string(LINE), n + 1);
+            for (field, value) in values {
+                let value = value.map_or("null".to_string(), |v| json_string(&v));
+                obj.push_str(&format!(",{}:{value}", json_string(field)));
+            }
+            writeln!(out, "{obj}}}")
+        }
+        Format::Csv => {
+            let mut row = vec![csv_field(name), (n + 1).to_string()];
+            row.extend(values.into_iter().map(|(_, value)| csv_field(&value.unwrap_or_default())));
+            writeln!(out, "{}", row.join(","))
+        }
+    }
+}
+
+/// RFC 4180に従い、必要な場合のみ引用符で囲む
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{captures_all, check, csv_field, write_header, write_record, Format};
+    use regex::{Flags, Program};
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn test_record() {
+        let program = Program::with_captures("(?<key>a+)=(b)?(?<value>c*)", Flags::default(), true).unwrap();
+        assert!(check(&program).is_ok());
+
+        let line = "x aa=c".chars().collect::<Vec<char>>();
+        let caps = program.captures_at(&line, 0).unwrap().unwrap();
+
+        let mut out = Vec::new();
+        write_record(Format::Json, &program, "in,1.log", 4, &line, &caps, &mut out).unwrap();
+        write_header(&program, &mut out).unwrap();
+        write_record(Format::Csv, &program, "in,1.log", 4, &line, &caps, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"file\":\"in,1.log\",\"line\":5,\"key\":\"aa\",\"value\":\"c\"}\n\
+             file,line,key,value\n\
+             \"in,1.log\",5,aa,c\n"
+        );
+
+        let line = "a=c aa=".chars().collect::<Vec<char>>();
+        let all = captures_all(&program, &line).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1][3], Some((7, 7)));
+
+        let no_group = Program::with_captures("(a)", Flags::default(), true).unwrap();
+        assert!(check(&no_group).is_err());
+        let reserved = Program::with_captures("(?<line>a)", Flags::default(), true).unwrap();
+        assert!(check(&reserved).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! --follow: tail -fのように、追記されたデータを読み続けて選択された行を表示する
+//!
+//! 読んだバイト列はその場でStreamMatcherに与えるため、ファイルを読み直すことはない。
+//! 表示のために保持するのは読みかけの1行だけである。
+use crate::grep::{Options, Printer};
+use regex::{DynError, Program, StreamMatcher};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem, thread,
+    time::Duration,
+};
+
+/// ファイルの変化を調べる間隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 中断されるまでファイルを監視する。-qの場合は最初に行が選択された時点で0を返す
+pub fn run<W: Write>(opts: &Options, program: &Program, out: &mut W) -> i32 {
+    let mut followers = Vec::new();
+    for file in &opts.files {
+        match Follower::new(opts, program, file, true) {
+            Ok(f) => followers.push(f),
+            Err(e) => {
+                eprintln!("regex: {file}: {e}");
+                return 2;
+            }
+        }
+    }
+
+    loop {
+        for f in followers.iter_mut() {
+            match f.poll(out) {
+                Ok(true) if opts.is_quiet => return 0,
+                Ok(_) => (),
+                Err(e) => eprintln!("regex: {}: {e}", f.path),
+            }
+        }
+        if let Err(e) = out.flush() {
+            eprintln!("regex: {e}");
+            return 2;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// 1つのファイルを監視する
+pub struct Follower<'a> {
+    opts: &'a Options,
+    program: &'a Program,
+    path: String,
+    file: File,
+    id: Option<(u64, u64)>, // 開いているファイルの(デバイス, inode)
+    pos: u64,               // 読み終えたバイト数
+    line: Vec<u8>,          // 読みかけの行
+    matcher: StreamMatcher<'a>,
+    spans: Vec<(usize, usize)>, // lineの中で見つかったマッチ。バイト単位
+    is_invalid: bool,           // lineが不正なUTF-8を含む
+}
+
+impl<'a> Follower<'a> {
+    /// pathを開く。is_from_endであれば既存の内容は読み飛ばす
+    pub fn new(opts: &'a Options, program: &'a Program, path: &str, is_from_end: bool) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let id = file_id(&file.metadata()?);
+        let pos = if is_from_end { file.seek(SeekFrom::End(0))? } else { 0 };
+
+        Ok(Follower {
+            opts,
+            program,
+            path: path.to_string(),
+            file,
+            id,
+            pos,
+            line: Vec::new(),
+            matcher: StreamMatcher::new(program),
+            spans: Vec::new(),
+            is_invalid: false,
+        })
+    }
+
+    /// 追記された分を読んで選択された行を書き出し、切り詰めや置き換えがあれば対応する
+    ///
+    /// 行が選択された場合にtrueを返す
+    pub fn poll<W: Write>(&mut self, out: &mut W) -> Result<bool, DynError> {
+        let mut is_selected = self.read_to_end(out)?;
+
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            // ローテーション中で、まだ新しいファイルが作られていない
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(is_selected),
+            Err(e) => return Err(e.into()),
+        };
+
+        if self.id.is_some() && file_id(&meta) != self.id {
+            // 別のファイルに置き換えられたので、読みかけの行を終えて新しいファイルを先頭から読む
+            is_selected |= self.end_line(out)?;
+            self.file = File::open(&self.path)?;
+            self.id = file_id(&self.file.metadata()?);
+            self.pos = 0;
+            is_selected |= self.read_to_end(out)?;
+        } else if meta.len() < self.pos {
+            eprintln!("regex: {}: file truncated", self.path);
+            self.file.seek(SeekFrom::Start(0))?;
+            self.pos = 0;
+            self.reset_line();
+            is_selected |= self.read_to_end(out)?;
+        }
+
+        Ok(is_selected)
+    }
+
+    fn read_to_end<W: Write>(&mut self, out: &mut W) -> Result<bool, DynError> {
+        let mut buf = [0; 8192];
+        let mut is_selected = false;
+        loop {
+            let n = match self.file.read(&mut buf) {
+                Ok(0) => return Ok(is_selected),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            self.pos += n as u64;
+
+            for (i, part) in buf[..n].split(|b| *b == b'\n').enumerate() {
+                if i > 0 {
+                    is_selected |= self.end_line(out)?;
+                }
+                self.feed(part);
+            }
+        }
+    }
+
+    /// 行の続きを読む
+    fn feed(&mut self, part: &[u8]) {
+        self.line.extend_from_slice(part);
+        if self.is_invalid {
+            return;
+        }
+        match self.matcher.feed(part) {
+            Ok(spans) => self.spans.extend(spans),
+            Err(_) => self.is_invalid = true,
+        }
+    }
+
+    /// 読みかけの行を終え、選択されていれば書き出す
+    fn end_line<W: Write>(&mut self, out: &mut W) -> Result<bool, DynError> {
+        let matcher = mem::replace(&mut self.matcher, StreamMatcher::new(self.program));
+        if !self.is_invalid {
+            match matcher.finish() {
+                Ok(spans) => self.spans.extend(spans),
+                Err(_) => self.is_invalid = true,
+            }
+        }
+        let line = mem::take(&mut self.line);
+        let spans = mem::take(&mut self.spans);
+        let is_invalid = mem::replace(&mut self.is_invalid, false);
+
+        if is_invalid {
+            eprintln!("regex: {}: skipped a line that is not valid UTF-8", self.path);
+            return Ok(false);
+        }
+        if spans.is_empty() != self.opts.is_invert {
+            return Ok(false);
+        }
+        if self.opts.is_quiet {
+            return Ok(true);
+        }
+
+        // バイト単位の位置を文字単位に直す
+        let line = String::from_utf8(line)?;
+        let offsets = line.char_indices().map(|(i, _)| i).collect::<Vec<_>>();
+        let to_char = |b: usize| offsets.binary_search(&b).unwrap_or_else(|i| i);
+        let spans = spans.into_iter().map(|(b, e)| (to_char(b), to_char(e))).collect::<Vec<_>>();
+        let chars = line.chars().collect::<Vec<char>>();
+
+        let printer = Printer::new(self.opts, &self.path, self.opts.files.len() > 1);
+        if self.opts.is_only_matching {
+            for (begin, end) in spans.into_iter().filter(|(b, e)| b != e) {
+                printer.write_line(out, 0, ':', &chars[begin..end], &[(0, end - begin)])?;
+            }
+        } else {
+            printer.write_line(out, 0, ':', &chars, &spans)?;
+        }
+        Ok(true)
+    }
+
+    fn reset_line(&mut self) {
+        self.matcher = StreamMatcher::new(self.program);
+        self.line.clear();
+        self.spans.clear();
+        self.is_invalid = false;
+    }
+}
+
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+/// ファイルを識別できない環境では、切り詰めだけを検出する
+#[cfg(not(unix))]
+fn file_id(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Follower;
+    use crate::grep::{compile_patterns, parse_args};
+    use std::{env, fs, io::Write};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_follow() {
+        let dir = env::temp_dir().join(format!("regex_follow_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.log");
+        let p = path.display().to_string();
+        fs::write(&path, "error: old\n").unwrap();
+
+        let append = |s: &str| {
+            let mut f = fs::OpenOptions::new().append(true).open(&path).unwrap();
+            f.write_all(s.as_bytes()).unwrap();
+        };
+
+        let opts = parse_args(&args(&format!("--follow error {p}"))).unwrap();
+        let program = compile_patterns(&opts.patterns, opts.flags, false).unwrap();
+        let mut follower = Follower::new(&opts, &program, &p, true).unwrap();
+        let mut out = Vec::new();
+
+        // 既存の内容は表示しない
+        assert!(!follower.poll(&mut out).unwrap());
+        assert!(out.is_empty());
+
+        // 行の途中までしか書かれていなくても、行が終わるまで待つ
+        append("info: a\nerr");
+        assert!(!follower.poll(&mut out).unwrap());
+        append("or: b\ninfo: c\n");
+        assert!(follower.poll(&mut out).unwrap());
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), "error: b\n");
+
+        // 切り詰められた場合は先頭から読み直す
+        fs::write(&path, "error: c\n").unwrap();
+        follower.poll(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), "error: b\nerror: c\n");
+
+        // ローテーションされた場合は、古いファイルの残りを読んでから新しいファイルに移る
+        append("error: d\n");
+        fs::rename(&path, dir.join("app.log.1")).unwrap();
+        assert!(follower.poll(&mut out).unwrap());
+        fs::write(&path, "info: e\nerror: f\n").unwrap();
+        follower.poll(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "error: b\nerror: c\nerror: d\nerror: f\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_follow_args() {
+        assert!(parse_args(&args("--follow a")).is_err());
+        assert!(parse_args(&args("--follow a -")).is_err());
+        assert!(parse_args(&args("--follow -n a f")).is_err());
+        assert!(parse_args(&args("--follow -io a f")).is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+//! `regex generate PATTERN`: 式にマッチする文字列や、マッチしない近い文字列を乱数で作る
+use crate::grep;
+use regex::{DynError, Flags, Generator, DEFAULT_MAX_REPEAT};
+use std::{
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const USAGE: &str = "\
+usage: regex generate [-i] [-w] [-n COUNT] [--seed=N] [--max-repeat=N] [--near-miss] [-z] PATTERN
+
+Print random strings that PATTERN matches as a whole, one per line.
+
+  -i              ignore case distinctions
+  -w              match only whole words
+  -n COUNT        number of strings to print (default: 10)
+  --seed=N        seed of the random numbers; the same seed prints the same
+                  strings (default: taken from the current time)
+  --max-repeat=N  repeat * and + at most N times (default: 8)
+  --near-miss     print strings that PATTERN does not match, each made by
+                  deleting, inserting, replacing or swapping one character of
+                  a matching string
+  -z              end each string with NUL instead of newline
+
+Exit status is 0 if COUNT strings were printed, 1 if no such string could be
+found, and 2 if an error occurred.";
+
+#[derive(Debug)]
+struct Options {
+    flags: Flags,
+    count: usize,
+    seed: u64,
+    max_repeat: usize,
+    is_near_miss: bool,
+    is_null_data: bool,
+    pattern: String,
+}
+
+pub fn main(args: &[String]) -> i32 {
+    let opts = match parse_args(args) {
+        Ok(opts) => opts,
+        Err(e) => return grep::usage_error(&e, USAGE),
+    };
+    if let Err(e) = grep::check_pattern(&opts.pattern) {
+        eprintln!("{e}");
+        return 2;
+    }
+
+    match generate(&opts, &mut io::stdout().lock()) {
+        Ok(true) => 0,
+        Ok(false) => {
+            let kind = if opts.is_near_miss { "not matching" } else { "matching" };
+            eprintln!("regex: no string {kind} {} was found", opts.pattern);
+            1
+        }
+        Err(e) => {
+            eprintln!("regex: {e}");
+            2
+        }
+    }
+}
+
+/// 引数を解釈する。Errが空文字列であれば使い方だけを表示する
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut count = 10;
+    let mut seed = None;
+    let mut max_repeat = DEFAULT_MAX_REPEAT;
+    let mut is_near_miss = false;
+    let mut is_null_data = false;
+
+    let number = |name: &str, s: &str| s.parse::<u64>().map_err(|_| format!("invalid {name}: {s}"));
+    let (flags, patterns) = grep::parse_subcommand_args(args, false, |arg, iter| {
+        match arg {
+            "-n" => {
+                let s = iter.next().ok_or("-n requires COUNT")?;
+                count = number("count", s)? as usize;
+            }
+            "--near-miss" => is_near_miss = true,
+            "-z" => is_null_data = true,
+            _ => {
+                if let Some(s) = arg.strip_prefix("--seed=") {
+                    seed = Some(number("seed", s)?);
+                } else if let Some(s) = arg.strip_prefix("--max-repeat=") {
+                    max_repeat = number("max repeat", s)? as usize;
+                } else {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    })?;
+
+    let [pattern] = <[String; 1]>::try_from(patterns).map_err(|_| String::new())?;
+    let seed = seed.unwrap_or_else(|| {
+        let d = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        d.as_secs() ^ (u64::from(d.subsec_nanos()) << 32)
+    });
+    Ok(Options { flags, count, seed, max_repeat, is_near_miss, is_null_data, pattern })
+}
+
+/// 文字列をcount個書き出す。作れなかった場合はfalseを返す
+fn generate<W: Write>(opts: &Options, out: &mut W) -> Result<bool, DynError> {
+    let mut g = Generator::with_max_repeat(&opts.pattern, opts.flags, opts.seed, opts.max_repeat)?;
+    let terminator = if opts.is_null_data { '\0' } else { '\n' };
+    for _ in 0..opts.count {
+        let s = if opts.is_near_miss { g.near_miss()? } else { g.sample()? };
+        let Some(s) = s else {
+            return Ok(false);
+        };
+        write!(out, "{s}{terminator}")?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate, parse_args};
+
+    fn run(args: &str) -> (String, bool) {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        let opts = parse_args(&args).unwrap();
+        let mut out = Vec::new();
+        let is_found = generate(&opts, &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), is_found)
+    }
+
+    #[test]
+    fn test_generate() {
+        let (out, is_found) = run("-n 5 --seed=1 a(b|c)+");
+        assert!(is_found);
+        assert_eq!(out.lines().count(), 5);
+        assert!(out.lines().all(|s| s.starts_with('a') && s.len() >= 2 && s[1..].chars().all(|c| "bc".contains(c))));
+        assert_eq!(run("-n 5 --seed=1 a(b|c)+"), (out, true));
+
+        let (out, _) = run("-n 20 --seed=2 --max-repeat=1 x*");
+        assert!(out.lines().all(|s| s.is_empty() || s == "x"));
+
+        let (out, _) = run("-n 3 --seed=3 -z ab");
+        assert_eq!(out, "ab\0ab\0ab\0");
+
+        let (out, is_found) = run("-n 5 --seed=4 --near-miss abc");
+        assert!(is_found);
+        assert!(out.lines().all(|s| s != "abc"));
+        assert_eq!(run("--seed=5 a^b"), (String::new(), false));
+
+        assert!(parse_args(&["-n".to_string()]).is_err());
+        assert!(parse_args(&["--seed=x".to_string(), "a".to_string()]).is_err());
+        assert!(parse_args(&["a".to_string(), "b".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,1167 @@
+//! grep互換のコマンドラインオプションと検索処理
+use crate::{
+    encoding::{self, Encoding},
+    extract::{self, Format},
+    walk::{self, Filter},
+};
+use regex::{DynError, Flags, Program, StreamMatcher};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+pub const USAGE: &str = "\
+usage: regex [OPTION]... PATTERN [FILE]...
+       regex [OPTION]... -e PATTERN... [FILE]...
+       regex [OPTION]... -f PATTERN_FILE [FILE]...
+
+  -e PATTERN  use PATTERN (may be given more than once)
+  -f FILE     read patterns from FILE, one per line. Empty lines are errors
+  -i          ignore case distinctions
+  -v          select non-matching lines
+  -w          match only whole words
+  -x          match only whole lines
+  -c          print only a count of selected lines per file
+  -n          prefix each line with its line number
+  -o          print only the matched parts of a line
+  -l          print only names of files with selected lines
+  -L          print only names of files without selected lines
+  -q          print nothing; exit with 0 on the first selected line
+  -A N        print N lines of trailing context after selected lines
+  -B N        print N lines of leading context before selected lines
+  -C N        print N lines of context before and after selected lines
+  -r          search directories recursively
+  -a          search binary files as if they were text
+  -j N        search with N worker threads (default: number of CPUs)
+  -z          like --multiline, but split input into NUL-terminated records
+              and end each output region with NUL instead of a newline
+  --include=GLOB      with -r, search only files whose name matches GLOB
+  --exclude=GLOB      with -r, skip files whose name matches GLOB
+  --exclude-dir=GLOB  with -r, skip directories whose name matches GLOB
+  --color=WHEN        highlight matches: auto (if stdout is a terminal), always or never
+  --multiline search each whole file as one text, so matches may span lines;
+              prints each region of lines with matches, prefixed with its
+              line range (N or N-M). Use (?m) to make ^ and $ match at line
+              boundaries and (?s) to make . match a newline
+  --follow    keep reading FILEs as they grow, like tail -f, and print new
+              selected lines; follows FILEs that are truncated or rotated
+  --extract[=FORMAT]  print the named groups of each match as one record per
+                      match, with file and line fields: json (JSON Lines,
+                      the default) or csv (with a header row)
+  --encoding=NAME     decode input from NAME before matching: shift_jis,
+                      euc-jp, utf-16le, utf-16be or utf-8. Selected lines are
+                      written in NAME, with undecodable bytes kept as they were
+                      (UTF-16 is written as UTF-8). A byte order mark overrides
+                      NAME; with utf-8, files with invalid UTF-8 are searched too
+  --dump      print the AST and compiled code of the pattern first
+
+In PATTERN, . matches any character but a newline, ^ and $ match at the start
+and end of a line, | separates alternatives, + * ? repeat and ( ) group.
+Earlier versions matched . ^ $ literally; write \\. \\^ \\$ (and \\\\ for a
+backslash) to match them as characters, e.g. 1\\.0 or \\$HOME.
+
+With no FILE, read standard input, or the current directory with -r.
+Binary files (containing a NUL byte) are skipped unless -a is given.
+Exit status is 0 if a line is selected, 1 if not, and 2 if an error occurred.";
+
+/// 標準入力を表すファイル名
+const STDIN: &str = "-";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    pub patterns: Vec<String>,
+    pub files: Vec<String>,
+    pub flags: Flags,
+    pub is_invert: bool,
+    pub is_count: bool,
+    pub is_line_number: bool,
+    pub is_only_matching: bool,
+    pub is_files_with_matches: bool,
+    pub is_files_without_match: bool,
+    pub is_quiet: bool,
+    pub is_recursive: bool,
+    pub is_text: bool,
+    pub filter: Filter,
+    pub jobs: Option<usize>,
+    pub before: usize,
+    pub after: usize,
+    pub color: Color,
+    pub is_multiline: bool,
+    pub is_null_data: bool,
+    pub is_follow: bool,
+    pub extract: Option<Format>,
+    pub encoding: Option<Encoding>,
+    pub is_dump: bool,
+}
+
+/// --colorの指定
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// 標準出力が端末の場合のみ色付けする
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// コマンドライン引数を解析する。argsにはプログラム名を含めない
+pub fn parse_args(args: &[String]) -> Result<Options, DynError> {
+    let mut opts = Options::default();
+    let mut has_pattern = false;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            rest.extend(iter.by_ref().cloned());
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            match long.split_once('=') {
+                Some(("include", glob)) => opts.filter.includes.push(glob.to_string()),
+                Some(("exclude", glob)) => opts.filter.excludes.push(glob.to_string()),
+                Some(("exclude-dir", glob)) => opts.filter.exclude_dirs.push(glob.to_string()),
+                Some(("jobs", n)) => opts.jobs = Some(parse_jobs(n)?),
+                Some(("color", when)) => {
+                    opts.color = match when {
+                        "auto" => Color::Auto,
+                        "always" => Color::Always,
+                        "never" => Color::Never,
+                        _ => return Err(format!("invalid argument for --color: {when}").into()),
+                    }
+                }
+                Some(("extract", format)) => {
+                    opts.extract = match format {
+                        "json" => Some(Format::Json),
+                        "csv" => Some(Format::Csv),
+                        _ => return Err(format!("invalid argument for --extract: {format}").into()),
+                    }
+                }
+                Some(("encoding", name)) => {
+                    opts.encoding = Some(Encoding::from_name(name).ok_or_else(|| {
+                        format!("invalid argument for --encoding: {name} (supported: {})", encoding::NAMES)
+                    })?)
+                }
+                None if long == "extract" => opts.extract = Some(Format::Json),
+                None if long == "color" => opts.color = Color::Auto,
+                None if long == "recursive" => opts.is_recursive = true,
+                None if long == "text" => opts.is_text = true,
+                None if long == "multiline" => opts.is_multiline = true,
+                None if long == "null-data" => opts.is_null_data = true,
+                None if long == "follow" => opts.is_follow = true,
+                None if long == "dump" => opts.is_dump = true,
+                _ => return Err(format!("unknown option: {arg}").into()),
+            }
+            continue;
+        }
+
+        let shorts = match arg.strip_prefix('-') {
+            Some(shorts) if !shorts.is_empty() => shorts,
+            _ => {
+                rest.push(arg.clone());
+                continue;
+            }
+        };
+
+        // -invのように短いオプションはまとめて指定できる
+        for (i, c) in shorts.char_indices() {
+            match c {
+                'i' => opts.flags.is_ignore_case = true,
+                'w' => opts.flags.is_word = true,
+                'x' => opts.flags.is_line = true,
+                'v' => opts.is_invert = true,
+                'c' => opts.is_count = true,
+                'n' => opts.is_line_number = true,
+                'o' => opts.is_only_matching = true,
+                'l' => opts.is_files_with_matches = true,
+                'L' => opts.is_files_without_match = true,
+                'q' => opts.is_quiet = true,
+                'r' => opts.is_recursive = true,
+                'a' => opts.is_text = true,
+                'z' => opts.is_null_data = true,
+                'e' | 'f' | 'j' | 'A' | 'B' | 'C' => {
+                    // 引数は-ePATTERNのように続けるか、次の引数で与える
+                    let value = &shorts[i + c.len_utf8()..];
+                    let value = if value.is_empty() {
+                        iter.next()
+                            .cloned()
+                            .ok_or_else(|| format!("option requires an argument: -{c}"))?
+                    } else {
+                        value.to_string()
+                    };
+
+                    match c {
+                        'e' => opts.patterns.push(value),
+                        'f' => opts.patterns.extend(read_patterns(&value)?),
+                        'j' => {
+                            opts.jobs = Some(parse_jobs(&value)?);
+                            break;
+                        }
+                        _ => {
+                            let n = value
+                                .parse()
+                                .map_err(|_| format!("invalid context length: {value}"))?;
+                            if c != 'B' {
+                                opts.after = n;
+                            }
+                            if c != 'A' {
+                                opts.before = n;
+                            }
+                            break;
+                        }
+                    }
+                    has_pattern = true;
+                    break;
+                }
+                _ => return Err(format!("unknown option: -{c}").into()),
+            }
+        }
+    }
+
+    let mut rest = rest.into_iter();
+    if !has_pattern {
+        opts.patterns.push(rest.next().ok_or("no pattern given")?);
+    }
+    // 空の-fファイルだけではパターンが無い。空の式として解析させずにここで断る
+    if opts.patterns.is_empty() {
+        return Err("no pattern given: the -f pattern file is empty".into());
+    }
+    opts.files = rest.collect();
+
+    if opts.is_follow {
+        if opts.files.is_empty() || opts.files.iter().any(|f| f == STDIN) {
+            return Err("--follow requires a FILE".into());
+        }
+        if opts.is_count
+            || opts.is_line_number
+            || opts.is_files_with_matches
+            || opts.is_files_without_match
+            || opts.is_recursive
+            || opts.before > 0
+            || opts.after > 0
+        {
+            return Err("--follow cannot be used with -c, -n, -l, -L, -r, -A, -B or -C".into());
+        }
+        if opts.encoding.is_some_and(|e| e != Encoding::Utf8) {
+            return Err("--follow can only read UTF-8 input".into());
+        }
+    }
+
+    if (opts.is_multiline || opts.is_null_data)
+        && (opts.is_invert
+            || opts.is_follow
+            || opts.extract.is_some()
+            || opts.before > 0
+            || opts.after > 0)
+    {
+        return Err("-z and --multiline cannot be used with -v, -A, -B, -C, --follow or --extract".into());
+    }
+
+    if opts.extract.is_some()
+        && (opts.is_invert
+            || opts.is_count
+            || opts.is_only_matching
+            || opts.is_files_with_matches
+            || opts.is_files_without_match
+            || opts.is_follow
+            || opts.before > 0
+            || opts.after > 0)
+    {
+        return Err("--extract cannot be used with -v, -c, -o, -l, -L, -A, -B, -C or --follow".into());
+    }
+
+    Ok(opts)
+}
+
+fn parse_jobs(value: &str) -> Result<usize, DynError> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid number of jobs: {value}").into()),
+    }
+}
+
+/// パターンファイルを読む
+///
+/// grepでは空行が全ての行にマッチするが、空のパターンは書けないので、空行があればエラーとする
+fn read_patterns(file: &str) -> Result<Vec<String>, DynError> {
+    let content = if file == STDIN {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(file).map_err(|e| format!("{file}: {e}"))?
+    };
+    let mut patterns = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        if line.is_empty() {
+            return Err(format!("{file}:{}: empty pattern; an empty pattern is not supported", n + 1).into());
+        }
+        patterns.push(line.to_string());
+    }
+    Ok(patterns)
+}
+
+/// 全てのパターンを1つの式にまとめてコンパイルする
+///
+/// 個々のパターンを先に検査し、不正なものがあればその箇所を示すエラーメッセージを返す。
+/// is_capturesであればグループの位置を記録するコードを生成する
+pub fn compile_patterns(patterns: &[String], flags: Flags, is_captures: bool) -> Result<Program, String> {
+    if patterns.is_empty() {
+        return Err("regex: no pattern given".to_string());
+    }
+    for pattern in patterns {
+        check_pattern(pattern)?;
+    }
+
+    let expr = if patterns.len() == 1 {
+        patterns[0].clone()
+    } else {
+        patterns.iter().map(|p| format!("({p})")).collect::<Vec<_>>().join("|")
+    };
+    let program = if is_captures {
+        Program::with_captures(&expr, flags, true)
+    } else {
+        Program::with_flags(&expr, flags, true)
+    };
+    program.map_err(|e| e.to_string())
+}
+
+/// 式を解析だけ行い、構文エラーであれば箇所を示したメッセージを返す
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    regex::parse(pattern).map(|_| ()).map_err(|e| e.render(pattern).trim_end().to_string())
+}
+
+/// サブコマンドの引数を解釈し、フラグとオペランドを返す
+///
+/// -i、-w、is_line_allowedであれば-x、--と--helpはここで扱い、それ以外のオプションはoptionに渡す。
+/// optionは値を取るオプションであればiterから読み、知らないオプションであればOk(false)を返す。
+/// Errが空文字列であれば使い方だけを表示する
+pub fn parse_subcommand_args<'a>(
+    args: &'a [String],
+    is_line_allowed: bool,
+    mut option: impl FnMut(&str, &mut slice::Iter<'a, String>) -> Result<bool, String>,
+) -> Result<(Flags, Vec<String>), String> {
+    let mut flags = Flags::default();
+    let mut operands = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--" => {
+                operands.extend(iter.by_ref().cloned());
+                break;
+            }
+            "-i" => flags.is_ignore_case = true,
+            "-w" => flags.is_word = true,
+            "-x" if is_line_allowed => flags.is_line = true,
+            "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                if !option(arg, &mut iter)? {
+                    return Err(format!("unknown option: {arg}"));
+                }
+            }
+            _ => operands.push(arg.clone()),
+        }
+    }
+    Ok((flags, operands))
+}
+
+/// parse_subcommand_argsなどのエラーと使い方を表示し、終了ステータスを返す
+pub fn usage_error(e: &str, usage: &str) -> i32 {
+    if !e.is_empty() {
+        eprintln!("regex: {e}");
+    }
+    eprintln!("{usage}");
+    2
+}
+
+/// 検索を行い、grepと同じ終了ステータスを返す
+///
+/// ファイルはワーカースレッドで並列に検索するが、各ファイルの出力はバッファに溜めておき、
+/// 引数 (-rの場合は辿った順) のとおりにファイル単位でまとめて書き出す
+pub fn run<W: Write>(opts: &Options, program: &Program, out: &mut W) -> i32 {
+    let mut errors = Vec::new();
+    let files = collect_files(opts, &mut errors);
+    for (path, e) in &errors {
+        eprintln!("regex: {path}: {e}");
+    }
+
+    // CSVの見出しはファイルごとではなく最初に1度だけ書く
+    if opts.extract == Some(Format::Csv) && !opts.is_quiet {
+        if let Err(e) = extract::write_header(program, out) {
+            eprintln!("regex: {e}");
+            return 2;
+        }
+    }
+
+    let is_multi = opts.is_recursive || files.len() > 1;
+    let jobs = opts
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, files.len().max(1));
+
+    let next = AtomicUsize::new(0);
+    let is_done = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+
+    let (is_selected, is_error) = thread::scope(|s| {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (files, next, is_done) = (&files, &next, &is_done);
+            s.spawn(move || {
+                while !is_done.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(file) = files.get(i) else {
+                        break;
+                    };
+                    let mut buf = Vec::new();
+                    let result = search_file(opts, program, file, &mut buf, is_multi).map_err(|e| e.to_string());
+                    if tx.send((i, buf, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        // 終わった順に届く結果を、ファイルの順に並べ直して書き出す
+        let mut pending = BTreeMap::new();
+        let mut written = 0;
+        let mut is_selected = false;
+        let mut is_error = !errors.is_empty();
+        'recv: for (i, buf, result) in rx {
+            pending.insert(i, (buf, result));
+            while let Some((buf, result)) = pending.remove(&written) {
+                let file = &files[written];
+                written += 1;
+
+                if let Err(e) = out.write_all(&buf).and_then(|_| out.flush()) {
+                    eprintln!("regex: {e}");
+                    is_error = true;
+                    break 'recv;
+                }
+                match result {
+                    Ok(true) => {
+                        is_selected = true;
+                        if opts.is_quiet {
+                            break 'recv;
+                        }
+                    }
+                    Ok(false) => (),
+                    Err(e) => {
+                        eprintln!("regex: {file}: {e}");
+                        is_error = true;
+                    }
+                }
+            }
+        }
+
+        // 途中で打ち切った場合は、残りのファイルを検索させない
+        is_done.store(true, Ordering::Relaxed);
+        (is_selected, is_error)
+    });
+
+    if opts.is_quiet && is_selected {
+        return 0;
+    }
+
+    if is_error {
+        2
+    } else if is_selected {
+        0
+    } else {
+        1
+    }
+}
+
+/// 検索するファイルの一覧を作る。-rの場合はディレクトリを辿る
+fn collect_files(opts: &Options, errors: &mut Vec<(String, io::Error)>) -> Vec<String> {
+    if !opts.is_recursive {
+        return if opts.files.is_empty() {
+            vec![STDIN.to_string()]
+        } else {
+            opts.files.clone()
+        };
+    }
+
+    if opts.files.is_empty() {
+        // grep -rと同じく、カレントディレクトリのファイルは"./"を付けずに表示する
+        let mut files = Vec::new();
+        walk::walk(Path::new("."), &opts.filter, &mut files, errors);
+        return files
+            .into_iter()
+            .map(|f| f.strip_prefix("./").unwrap_or(&f).to_string())
+            .collect();
+    }
+
+    let mut files = Vec::new();
+    for path in &opts.files {
+        if path != STDIN && Path::new(path).is_dir() {
+            walk::walk(Path::new(path), &opts.filter, &mut files, errors);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// 1つのファイルを検索する。-aが無ければバイナリファイルは読み飛ばす
+fn search_file(
+    opts: &Options,
+    program: &Program,
+    file: &str,
+    out: &mut Vec<u8>,
+    is_multi: bool,
+) -> Result<bool, DynError> {
+    if file == STDIN {
+        return search_reader(opts, program, "(standard input)", io::stdin().lock(), out, is_multi);
+    }
+    let reader = BufReader::with_capacity(walk::BINARY_CHECK_LEN, fs::File::open(file)?);
+    search_reader(opts, program, file, reader, out, is_multi)
+}
+
+/// 変換が不要であれば、先頭のバッファだけでバイナリか調べ、読みながら検索する
+///
+/// BOMがあるか--encodingが指定されていれば、入力全体を読んでからsearch_bytesで変換する
+fn search_reader<R: BufRead>(
+    opts: &Options,
+    program: &Program,
+    name: &str,
+    mut reader: R,
+    out: &mut Vec<u8>,
+    is_multi: bool,
+) -> Result<bool, DynError> {
+    let head = reader.fill_buf()?;
+    if opts.encoding.is_some() || Encoding::detect_bom(head).is_some() {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        return search_bytes(opts, program, name, &bytes, out, is_multi);
+    }
+    // -zの入力はNULを区切りとして含むので、バイナリとはみなさない
+    if !opts.is_text && !opts.is_null_data && walk::is_binary(head) {
+        return Ok(false);
+    }
+    search(opts, program, name, reader, out, is_multi)
+}
+
+/// 読み込んだ入力全体を、BOMまたは--encodingに従って変換してから検索する。BOMの方を優先する
+///
+/// --encodingもBOMも無ければUTF-8として検索し、UTF-8として不正な入力はエラーとする
+fn search_bytes(
+    opts: &Options,
+    program: &Program,
+    name: &str,
+    bytes: &[u8],
+    out: &mut Vec<u8>,
+    is_multi: bool,
+) -> Result<bool, DynError> {
+    // -zの入力はNULを区切りとして含むので、バイナリとはみなさない
+    let is_binary = |bytes: &[u8]| !opts.is_text && !opts.is_null_data && walk::is_binary(bytes);
+
+    let (encoding, bom) = match (Encoding::detect_bom(bytes), opts.encoding) {
+        (Some(found), _) => found,
+        (None, Some(encoding)) => (encoding, 0),
+        (None, None) => {
+            if is_binary(bytes) {
+                return Ok(false);
+            }
+            return search(opts, program, name, bytes, out, is_multi);
+        }
+    };
+
+    // UTF-16のテキストはNULを含むので、変換した後でバイナリか調べる
+    let text = encoding.decode(&bytes[bom..]);
+    if is_binary(text.as_bytes()) {
+        return Ok(false);
+    }
+    let printer = Printer::with_encoding(opts, name, is_multi, encoding);
+    search_with(opts, program, &printer, text.as_bytes(), out)
+}
+
+/// 1つの入力を検索して結果を書き出す
+///
+/// 選択された行 (-Lの場合は表示したファイル) があった場合にtrueを返す
+pub fn search<R: BufRead, W: Write>(
+    opts: &Options,
+    program: &Program,
+    name: &str,
+    reader: R,
+    out: &mut W,
+    is_multi: bool,
+) -> Result<bool, DynError> {
+    search_with(opts, program, &Printer::new(opts, name, is_multi), reader, out)
+}
+
+/// printerの書式で1つの入力を検索する
+fn search_with<R: BufRead, W: Write>(
+    opts: &Options,
+    program: &Program,
+    printer: &Printer,
+    reader: R,
+    out: &mut W,
+) -> Result<bool, DynError> {
+    if opts.is_multiline || opts.is_null_data {
+        return search_multiline(opts, program, printer, reader, out);
+    }
+
+    let is_context = (opts.before > 0 || opts.after > 0)
+        && !(opts.is_only_matching
+            || opts.is_count
+            || opts.is_quiet
+            || opts.is_files_with_matches
+            || opts.is_files_without_match);
+
+    let mut count = 0;
+    let mut before = VecDeque::with_capacity(opts.before);
+    let mut after = 0; // 選択行の後に続けて表示する残りの行数
+    let mut last = None; // 最後に表示した行番号
+
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        let chars = line.chars().collect::<Vec<char>>();
+        let (spans, captures) = if opts.extract.is_some() {
+            let captures = extract::captures_all(program, &chars)?;
+            (captures.iter().map(|caps| caps[0].unwrap()).collect(), captures)
+        } else {
+            (program.find_all(&chars)?, Vec::new())
+        };
+        if spans.is_empty() != opts.is_invert {
+            if after > 0 {
+                printer.write_line(out, n, '-', &chars, &spans)?;
+                last = Some(n);
+                after -= 1;
+            } else if is_context && opts.before > 0 {
+                if before.len() == opts.before {
+                    before.pop_front();
+                }
+                before.push_back((n, chars, spans));
+            }
+            continue;
+        }
+
+        count += 1;
+        if opts.is_quiet || opts.is_files_with_matches || opts.is_files_without_match {
+            break;
+        }
+        if opts.is_count {
+            continue;
+        }
+
+        if is_context {
+            // 前に表示した行と離れている場合はグループの区切りを入れる
+            let first = before.front().map_or(n, |(m, _, _)| *m);
+            if last.is_some_and(|l| l + 1 < first) {
+                printer.write_separator(out)?;
+            }
+            for (m, chars, spans) in before.drain(..) {
+                printer.write_line(out, m, '-', &chars, &spans)?;
+            }
+            last = Some(n);
+            after = opts.after;
+        }
+
+        if let Some(format) = opts.extract {
+            for caps in &captures {
+                extract::write_record(format, program, printer.name, n, &chars, caps, out)?;
+            }
+        } else if opts.is_only_matching {
+            // -vと併用した場合、選ばれた行にはマッチ部分が無いので何も出力しない
+            for (begin, end) in spans.into_iter().filter(|(b, e)| b != e) {
+                printer.write_line(out, n, ':', &chars[begin..end], &[(0, end - begin)])?;
+            }
+        } else {
+            printer.write_line(out, n, ':', &chars, &spans)?;
+        }
+    }
+
+    Ok(write_summary(opts, printer, count, out)?)
+}
+
+/// -z、--multilineの検索。ファイル全体 (-zの場合はNULで終わる各レコード) を1つの入力として探し、
+/// マッチを含む行をまとめて書き出す
+///
+/// 行の範囲が重なるマッチは1つの範囲にまとめる。選択されたマッチがあった場合にtrueを返す
+fn search_multiline<R: BufRead, W: Write>(
+    opts: &Options,
+    program: &Program,
+    printer: &Printer,
+    mut reader: R,
+    out: &mut W,
+) -> Result<bool, DynError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let records = if opts.is_null_data {
+        text.split_terminator('\0').collect::<Vec<_>>()
+    } else {
+        vec![text.as_str()]
+    };
+
+    let mut count = 0;
+    let mut next_line = 0; // 次のレコードの先頭の行番号。NULは行を区切らない
+    for record in records {
+        let mut matcher = StreamMatcher::new(program);
+        let mut spans = matcher.feed(record.as_bytes())?;
+        spans.extend(matcher.finish()?);
+
+        count += spans.len();
+        if count > 0 && (opts.is_quiet || opts.is_files_with_matches || opts.is_files_without_match) {
+            break;
+        }
+
+        // レコード内の各行の先頭のバイト位置
+        let line_starts = [0]
+            .into_iter()
+            .chain(record.match_indices('\n').map(|(i, _)| i + 1))
+            .collect::<Vec<_>>();
+        let line_of = |b: usize| line_starts.partition_point(|s| *s <= b) - 1;
+        let line_end = |n: usize| line_starts.get(n + 1).map_or(record.len(), |s| s - 1);
+        let base_line = next_line;
+        next_line += line_starts.len() - 1;
+        if opts.is_count {
+            continue;
+        }
+
+        if opts.is_only_matching {
+            for (begin, end) in spans.into_iter().filter(|(b, e)| b != e) {
+                let text = &record[begin..end];
+                let (first, last) = (line_of(begin), line_of(end - 1));
+                printer.write_region(out, base_line + first, base_line + last, text, &[(0, text.len())])?;
+            }
+            continue;
+        }
+
+        /// 行の範囲が重なるマッチをまとめたもの
+        struct Region {
+            first: usize,
+            last: usize,
+            spans: Vec<(usize, usize)>,
+        }
+
+        let mut regions: Vec<Region> = Vec::new();
+        for (begin, end) in spans {
+            let (first, last) = (line_of(begin), line_of(end.saturating_sub(1).max(begin)));
+            match regions.last_mut() {
+                Some(r) if first <= r.last => {
+                    r.last = r.last.max(last);
+                    r.spans.push((begin, end));
+                }
+                _ => regions.push(Region { first, last, spans: vec![(begin, end)] }),
+            }
+        }
+
+        for r in regions {
+            let start = line_starts[r.first];
+            let spans = r.spans.into_iter().map(|(b, e)| (b - start, e - start)).collect::<Vec<_>>();
+            let text = &record[start..line_end(r.last)];
+            printer.write_region(out, base_line + r.first, base_line + r.last, text, &spans)?;
+        }
+    }
+
+    Ok(write_summary(opts, printer, count, out)?)
+}
+
+/// -l、-L、-cの結果を書き出し、ファイルが選択されたかを返す
+fn write_summary<W: Write>(opts: &Options, printer: &Printer, count: usize, out: &mut W) -> io::Result<bool> {
+    if opts.is_files_with_matches {
+        if count > 0 {
+            writeln!(out, "{}", printer.name())?;
+        }
+    } else if opts.is_files_without_match {
+        if count == 0 {
+            writeln!(out, "{}", printer.name())?;
+        }
+        return Ok(count == 0);
+    } else if opts.is_count && !opts.is_quiet {
+        if printer.is_multi {
+            writeln!(out, "{}{}{count}", printer.name(), printer.paint(SEPARATOR, ":"))?;
+        } else {
+            writeln!(out, "{count}")?;
+        }
+    }
+
+    Ok(count > 0)
+}
+
+/// エスケープシーケンスの色。grepの既定の配色に合わせる
+const MATCH: &str = "01;31";
+const FILE_NAME: &str = "35";
+const LINE_NUMBER: &str = "32";
+const SEPARATOR: &str = "36";
+
+/// 1つの入力についての出力の書式
+pub struct Printer<'a> {
+    opts: &'a Options,
+    name: &'a str,
+    is_multi: bool,
+    /// 行の内容を書き出すときのエンコーディング。ファイル名などはUTF-8のまま書く
+    encoding: Encoding,
+}
+
+impl<'a> Printer<'a> {
+    pub fn new(opts: &'a Options, name: &'a str, is_multi: bool) -> Self {
+        Self::with_encoding(opts, name, is_multi, Encoding::Utf8)
+    }
+
+    pub fn with_encoding(opts: &'a Options, name: &'a str, is_multi: bool, encoding: Encoding) -> Self {
+        Printer { opts, name, is_multi, encoding }
+    }
+
+    fn is_color(&self) -> bool {
+        self.opts.color == Color::Always
+    }
+
+    /// 色付けが有効であればsを色付けする
+    fn paint(&self, color: &str, s: &str) -> String {
+        if self.is_color() {
+            format!("\x1b[{color}m\x1b[K{s}\x1b[m\x1b[K")
+        } else {
+            s.to_string()
+        }
+    }
+
+    fn name(&self) -> String {
+        self.paint(FILE_NAME, self.name)
+    }
+
+    /// 1行を書き出す。sepは選択された行であれば':'、前後の文脈の行であれば'-'
+    pub fn write_line<W: Write>(
+        &self,
+        out: &mut W,
+        n: usize,
+        sep: char,
+        chars: &[char],
+        spans: &[(usize, usize)],
+    ) -> io::Result<()> {
+        let sep = self.paint(SEPARATOR, &sep.to_string());
+        if self.is_multi {
+            write!(out, "{}{sep}", self.name())?;
+        }
+        if self.opts.is_line_number {
+            write!(out, "{}{sep}", self.paint(LINE_NUMBER, &(n + 1).to_string()))?;
+        }
+
+        let mut text = String::new();
+        let mut pos = 0;
+        if self.is_color() {
+            for &(begin, end) in spans.iter().filter(|(b, e)| b != e) {
+                text.extend(&chars[pos..begin]);
+                text.push_str(&self.paint(MATCH, &chars[begin..end].iter().collect::<String>()));
+                pos = end;
+            }
+        }
+        text.extend(&chars[pos..]);
+        text.push('\n');
+        out.write_all(&self.encoding.encode(&text))
+    }
+
+    /// --multiline、-zで、firstからlastまでの行にまたがるtextを書き出す。spansはtext内のバイト位置
+    pub fn write_region<W: Write>(
+        &self,
+        out: &mut W,
+        first: usize,
+        last: usize,
+        text: &str,
+        spans: &[(usize, usize)],
+    ) -> io::Result<()> {
+        let sep = self.paint(SEPARATOR, ":");
+        if self.is_multi {
+            write!(out, "{}{sep}", self.name())?;
+        }
+        let range = if first == last {
+            (first + 1).to_string()
+        } else {
+            format!("{}-{}", first + 1, last + 1)
+        };
+        write!(out, "{}{sep}", self.paint(LINE_NUMBER, &range))?;
+
+        let mut content = String::new();
+        let mut pos = 0;
+        if self.is_color() {
+            for &(begin, end) in spans.iter().filter(|(b, e)| b != e) {
+                // 端末の行末まで色が付かないよう、改行をまたがずに色付けする
+                let painted = text[begin..end]
+                    .split('\n')
+                    .map(|s| if s.is_empty() { String::new() } else { self.paint(MATCH, s) })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                content.push_str(&text[pos..begin]);
+                content.push_str(&painted);
+                pos = end;
+            }
+        }
+        content.push_str(&text[pos..]);
+        out.write_all(&self.encoding.encode(&content))?;
+        out.write_all(if self.opts.is_null_data { b"\0" } else { b"\n" })
+    }
+
+    fn write_separator<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "{}", self.paint(SEPARATOR, "--"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_pattern, compile_patterns, parse_args, parse_subcommand_args, run, search, search_reader, Options};
+    use std::{env, fs};
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    fn grep(opts: &str, input: &str) -> (String, bool) {
+        let opts = parse_args(&args(opts)).unwrap();
+        let program = compile_patterns(&opts.patterns, opts.flags, opts.extract.is_some()).unwrap();
+        let mut out = Vec::new();
+        let found = search(&opts, &program, "in.txt", input.as_bytes(), &mut out, false).unwrap();
+        (String::from_utf8(out).unwrap(), found)
+    }
+
+    const INPUT: &str = "foo bar\nFoo\nfoobar\nbaz\nfoo foo\n";
+
+    #[test]
+    fn test_parse_args() {
+        let opts = parse_args(&args("-inv -e a -eb c.txt d.txt")).unwrap();
+        assert!(opts.flags.is_ignore_case && opts.is_line_number && opts.is_invert);
+        assert_eq!(opts.patterns, vec!["a", "b"]);
+        assert_eq!(opts.files, vec!["c.txt", "d.txt"]);
+
+        let opts = parse_args(&args("-- -a")).unwrap();
+        assert_eq!(opts.patterns, vec!["-a"]);
+        assert!(opts.files.is_empty());
+
+        let opts = parse_args(&args("-rj2 --include=*.rs --exclude-dir=target a")).unwrap();
+        assert!(opts.is_recursive);
+        assert_eq!(opts.jobs, Some(2));
+        assert_eq!(opts.filter.includes, vec!["*.rs"]);
+        assert_eq!(opts.filter.exclude_dirs, vec!["target"]);
+        assert_eq!(opts.patterns, vec!["a"]);
+        assert!(parse_args(&args("-j 0 a")).is_err());
+
+        assert!(parse_args(&args("-Z a")).is_err());
+        assert!(parse_args(&args("-e")).is_err());
+        assert!(parse_args(&args("-n")).is_err());
+        assert_eq!(parse_args(&args("a")).unwrap(), Options { patterns: vec!["a".into()], ..Default::default() });
+
+        let path = env::temp_dir().join(format!("regex_patterns_{}", std::process::id()));
+        let p = path.display();
+        fs::write(&path, "a\nb|c\n").unwrap();
+        assert_eq!(parse_args(&args(&format!("-f {p} -e d"))).unwrap().patterns, vec!["a", "b|c", "d"]);
+        // 空行は全ての行にマッチさせられないので、黙って読み飛ばさずにエラーとする
+        fs::write(&path, "a\n\nb\n").unwrap();
+        let err = parse_args(&args(&format!("-f {p}"))).unwrap_err().to_string();
+        assert!(err.ends_with(":2: empty pattern; an empty pattern is not supported"), "{err}");
+        // 空のファイルではパターンが1つも無いのでエラーとし、-eと併せれば使える
+        fs::write(&path, "").unwrap();
+        let err = parse_args(&args(&format!("-f {p} a.txt"))).unwrap_err().to_string();
+        assert_eq!(err, "no pattern given: the -f pattern file is empty");
+        assert_eq!(parse_args(&args(&format!("-f {p} -e d"))).unwrap().patterns, vec!["d"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_search() {
+        assert_eq!(grep("foo", INPUT), ("foo bar\nfoobar\nfoo foo\n".into(), true));
+        assert_eq!(grep("-i foo", INPUT).0, "foo bar\nFoo\nfoobar\nfoo foo\n");
+        assert_eq!(grep("-v foo", INPUT).0, "Foo\nbaz\n");
+        assert_eq!(grep("-c foo", INPUT).0, "3\n");
+        assert_eq!(grep("-n ba", INPUT).0, "1:foo bar\n3:foobar\n4:baz\n");
+        assert_eq!(grep("-o fo+", INPUT).0, "foo\nfoo\nfoo\nfoo\n");
+        assert_eq!(grep("-w foo", INPUT).0, "foo bar\nfoo foo\n");
+        assert_eq!(grep("-x foobar|baz", INPUT).0, "foobar\nbaz\n");
+        assert_eq!(grep("-e baz -e Foo", INPUT).0, "Foo\nbaz\n");
+        assert_eq!(grep("-l foo", INPUT).0, "in.txt\n");
+        assert_eq!(grep("-L foo", INPUT), (String::new(), false));
+        assert_eq!(grep("-L qux", INPUT), ("in.txt\n".into(), true));
+        assert_eq!(grep("-q foo", INPUT), (String::new(), true));
+        assert_eq!(grep("qux", INPUT), (String::new(), false));
+
+        // .、^、$はメタ文字なので、文字として探すにはエスケープする
+        let input = "1.0\n110\n$HOME\nHOME\n^a\na\n";
+        assert_eq!(grep("1.0", input).0, "1.0\n110\n");
+        assert_eq!(grep("1\\.0", input).0, "1.0\n");
+        assert_eq!(grep("$HOME", input).0, "");
+        assert_eq!(grep("\\$HOME", input).0, "$HOME\n");
+        assert_eq!(grep("-x ^a", input).0, "a\n");
+        assert_eq!(grep("\\^a", input).0, "^a\n");
+
+        // 長い行でもスタックを使い切らずに検索できる
+        let line = format!("{}\n", "a".repeat(100_000));
+        assert_eq!(grep("-c a+", &line), ("1\n".into(), true));
+        assert_eq!(grep("-o (a|b)+", &line).0, line);
+    }
+
+    #[test]
+    fn test_context() {
+        let input = "1\nfoo\n3\n4\n5\n6\nfoo\n8\nfoo\n10\n11\n";
+        assert_eq!(grep("-A 1 foo", input).0, "foo\n3\n--\nfoo\n8\nfoo\n10\n");
+        assert_eq!(grep("-B1 foo", input).0, "1\nfoo\n--\n6\nfoo\n8\nfoo\n");
+        assert_eq!(
+            grep("-nC 2 foo", input).0,
+            "1-1\n2:foo\n3-3\n4-4\n5-5\n6-6\n7:foo\n8-8\n9:foo\n10-10\n11-11\n"
+        );
+        assert_eq!(grep("-C1 -n 1", input).0, "1:1\n2-foo\n--\n9-foo\n10:10\n11:11\n");
+        // 出力が行単位でない場合は文脈を表示しない
+        assert_eq!(grep("-c -C1 foo", input).0, "3\n");
+        assert!(parse_args(&args("-A x foo")).is_err());
+    }
+
+    #[test]
+    fn test_color() {
+        let (out, _) = grep("--color=always -n o+", "foo boo\nbar\n");
+        assert_eq!(
+            out,
+            "\x1b[32m\x1b[K1\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\
+             f\x1b[01;31m\x1b[Koo\x1b[m\x1b[K b\x1b[01;31m\x1b[Koo\x1b[m\x1b[K\n"
+        );
+        assert_eq!(grep("--color=never o+", "foo\n").0, "foo\n");
+        assert_eq!(grep("--color o+", "foo\n").0, "foo\n");
+        assert!(parse_args(&args("--color=sometimes a")).is_err());
+    }
+
+    #[test]
+    fn test_multiline() {
+        let input = "start\nfoo bar\nbaz end\nother\nfoo\n";
+        assert_eq!(grep("--multiline bar\\nbaz", input), ("2-3:foo bar\nbaz end\n".into(), true));
+        assert_eq!(grep("--multiline (?m)^foo", input).0, "2:foo bar\n5:foo\n");
+        assert_eq!(grep("--multiline ^foo", input), (String::new(), false));
+        assert_eq!(grep("--multiline (?s)bar.*d", input).0, "2-3:foo bar\nbaz end\n");
+        // 行の範囲が重なるマッチは1つにまとめる
+        assert_eq!(grep("--multiline r\\nb|end|other", input).0, "2-3:foo bar\nbaz end\n4:other\n");
+        assert_eq!(grep("--multiline -o r\\nb|end", input).0, "2-3:r\nb\n3:end\n");
+        assert_eq!(grep("--multiline -c foo", input).0, "2\n");
+        assert_eq!(grep("--multiline -l end\\no", input).0, "in.txt\n");
+
+        // NULはレコードを区切るが行は区切らない
+        let input = "a\nfoo\0foo\nb\0";
+        assert_eq!(grep("-z (?m)^foo$", input).0, "2:foo\x002:foo\0");
+        assert_eq!(grep("-z a(?s).*b", input), (String::new(), false));
+        assert_eq!(grep("-z o\\nb", input).0, "2-3:foo\nb\0");
+
+        assert!(parse_args(&args("--multiline -v a")).is_err());
+        assert!(parse_args(&args("-z -A1 a")).is_err());
+    }
+
+    #[test]
+    fn test_extract() {
+        let input = "user=alice;id=1\nnone\nuser=bob;user=carol;id=2\n";
+        assert_eq!(
+            grep("--extract user=(?<user>alice|bob|carol)(;id=(?<id>1|2))?", input),
+            (
+                "{\"file\":\"in.txt\",\"line\":1,\"user\":\"alice\",\"id\":\"1\"}\n\
+                 {\"file\":\"in.txt\",\"line\":3,\"user\":\"bob\",\"id\":null}\n\
+                 {\"file\":\"in.txt\",\"line\":3,\"user\":\"carol\",\"id\":\"2\"}\n"
+                    .into(),
+                true
+            )
+        );
+        assert_eq!(grep("--extract=csv -q (?<user>bob)", input), (String::new(), true));
+        assert!(parse_args(&args("--extract=xml a")).is_err());
+        assert!(parse_args(&args("--extract -v a")).is_err());
+    }
+
+    #[test]
+    fn test_encoding() {
+        let grep_bytes = |opts: &str, input: &[u8]| {
+            let opts = parse_args(&args(opts)).unwrap();
+            let program = compile_patterns(&opts.patterns, opts.flags, false).unwrap();
+            let mut out = Vec::new();
+            let found = search_reader(&opts, &program, "in.txt", input, &mut out, false).unwrap();
+            (out, found)
+        };
+
+        // 不正なバイトを含む行も元のバイトのまま書き出す
+        let sjis = b"\x82\xa0\x82\xa2\n\x88\x9f\xff\n\x82\xa4\n";
+        assert_eq!(grep_bytes("--encoding=sjis -n 亜", sjis), (b"2:\x88\x9f\xff\n".to_vec(), true));
+        assert_eq!(grep_bytes("--encoding=sjis -o あい|う", sjis).0, b"\x82\xa0\x82\xa2\n\x82\xa4\n");
+        assert_eq!(grep_bytes("--encoding=euc-jp -c 亜", b"\xb0\xa1\n").0, b"1\n");
+
+        // BOMがあれば--encodingが無くても変換し、UTF-16はUTF-8で書き出す
+        let utf16 = b"\xff\xfea\x00\n\x00B0\n\x00";
+        assert_eq!(grep_bytes("あ", utf16), ("あ\n".as_bytes().to_vec(), true));
+        assert_eq!(grep_bytes("--encoding=sjis a", utf16).0, b"a\n");
+        assert_eq!(grep_bytes("--encoding=utf-8 b", b"\xffb\n").0, b"\xffb\n");
+        assert_eq!(grep_bytes("b", b"\xef\xbb\xbfb\n").0, b"b\n");
+        // 変換しない入力は先頭だけでバイナリか判定し、そのまま読みながら検索する
+        assert_eq!(grep_bytes("b", b"b\0\n"), (Vec::new(), false));
+        assert_eq!(grep_bytes("-a b", b"b\0\n").0, b"b\0\n");
+        assert_eq!(grep_bytes("--encoding=sjis b", b"b\0\n"), (Vec::new(), false));
+
+        assert!(parse_args(&args("--encoding=latin1 a")).is_err());
+        assert!(parse_args(&args("--encoding=sjis --follow a f")).is_err());
+    }
+
+    #[test]
+    fn test_recursive() {
+        let dir = env::temp_dir().join(format!("regex_grep_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "foo 1\nbar\nfoo 2\n").unwrap();
+        fs::write(dir.join("bin.txt"), "foo\0\n").unwrap();
+        fs::write(dir.join("c.log"), "foo\n").unwrap();
+        fs::write(dir.join("sub/b.txt"), "foo 3\n").unwrap();
+        for i in 0..20 {
+            fs::write(dir.join(format!("sub/n{i:02}.txt")), "none\n").unwrap();
+        }
+        let d = dir.display();
+
+        let grep_r = |opts: &str| {
+            let opts = parse_args(&args(&format!("{opts} {d}"))).unwrap();
+            let program = compile_patterns(&opts.patterns, opts.flags, false).unwrap();
+            let mut out = Vec::new();
+            let status = run(&opts, &program, &mut out);
+            (String::from_utf8(out).unwrap(), status)
+        };
+
+        // 並列に検索しても出力はファイルの順に並ぶ
+        let expected = format!("{d}/a.txt:foo 1\n{d}/a.txt:foo 2\n{d}/sub/b.txt:foo 3\n");
+        for _ in 0..5 {
+            assert_eq!(grep_r("-r -j 4 --include=*.txt foo"), (expected.clone(), 0));
+        }
+        assert_eq!(
+            grep_r("-rc --exclude=n* --exclude-dir=s?b foo"),
+            (format!("{d}/a.txt:2\n{d}/c.log:1\n"), 0)
+        );
+        assert_eq!(grep_r("-ral --include=bin.* foo"), (format!("{d}/bin.txt\n"), 0));
+        assert_eq!(grep_r("-rq foo"), (String::new(), 0));
+        assert_eq!(grep_r("-r qux"), (String::new(), 1));
+        // -rが無ければディレクトリは読めずエラーになる
+        assert_eq!(grep_r("foo"), (String::new(), 2));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_error() {
+        assert_eq!(compile_patterns(&[], Default::default(), false).unwrap_err(), "regex: no pattern given");
+        let err = compile_patterns(&["ok".to_string(), "a(b".to_string()], Default::default(), false).unwrap_err();
+        assert!(err.contains("  | a(b\n  |  ^"), "{err}");
+        assert_eq!(check_pattern("a(b").unwrap_err(), err);
+        assert!(check_pattern("a(b)").is_ok());
+    }
+
+    #[test]
+    fn test_parse_subcommand_args() {
+        let mut name = None;
+        let (flags, operands) = parse_subcommand_args(&args("-i -x --name N a -- -w"), true, |arg, iter| {
+            if arg != "--name" {
+                return Ok(false);
+            }
+            name = iter.next().cloned();
+            Ok(true)
+        })
+        .unwrap();
+        assert!(flags.is_ignore_case && flags.is_line && !flags.is_word);
+        assert_eq!(operands, vec!["a", "-w"]);
+        assert_eq!(name.as_deref(), Some("N"));
+
+        let no_option = |_: &str, _: &mut std::slice::Iter<String>| Ok(false);
+        assert_eq!(parse_subcommand_args(&args("-x a"), false, no_option).unwrap_err(), "unknown option: -x");
+        assert_eq!(parse_subcommand_args(&args("--help a"), false, no_option).unwrap_err(), "");
+        assert_eq!(parse_subcommand_args(&args("-w -"), false, no_option).unwrap().1, vec!["-"]);
+    }
+}
@@ -1,4 +1,62 @@
 mod engine;
 mod helper;
 
-pub use engine::{do_matching, print};
\ No newline at end of file
+pub use engine::{
+    ast_dot, code_dot, compare, compile, do_matching, do_matching_code, gen_matcher, is_meta,
+    normalize, parse, parse_boolean, print, simplify, to_pattern, write_matcher, Alphabet,
+    Assertion, Captures, Comparison, DenseTable, DerivativeMatcher, Dfa, DfaError, Enumerate, Flags,
+    Generator, Instruction, LoadError, ParseError, PrintError, Profile, Program, RegexSet, Rng,
+    Span, StaticProgram, Step, StreamError, StreamMatcher, StreamMatches, AST, DEFAULT_MAX_REPEAT,
+    PROGRAM_VERSION,
+};
+pub use helper::{json_string, DynError};
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        engine::do_matching,
+        helper::{json_string, safe_add, SafeAdd},
+    };
+
+    #[test]
+    fn test_safe_add() {
+        let n: usize = 10;
+        assert_eq!(Some(30), n.safe_add(&20));
+
+        let n: usize = !0;
+        assert_eq!(None, n.safe_add(&1));
+
+        let mut n: usize = 10;
+        assert!(safe_add(&mut n, &20, || ()).is_ok());
+
+        let mut n:usize = !0;
+        assert!(safe_add(&mut n, &1, || ()).is_err());
+    }
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("a\"b\\c\nd\u{1}"), "\"a\\\"b\\\\c\\nd\\u0001\"");
+    }
+
+    #[test]
+    fn test_matching() {
+        assert!(do_matching("+b", "bbb", true).is_err());
+        assert!(do_matching("*b", "bbb", true).is_err());
+        assert!(do_matching("|b", "bbb", true).is_err());
+        assert!(do_matching("?b", "bbb", true).is_err());
+
+        assert!(do_matching("abc|def", "def", true).unwrap());
+        assert!(do_matching("(abc)*", "abcabc", true).unwrap());
+        assert!(do_matching("(ab|cd)+", "abcdcd", true).unwrap());
+        assert!(do_matching("abc?", "ab", true).unwrap());
+        assert!(do_matching("((((a*)*)*)*)", "aaaaaaaaa", true).unwrap());
+        assert!(do_matching("(a*)*b", "aaaaaaaaab", true).unwrap());
+        assert!(do_matching("(a*)*b", "b", true).unwrap());
+        assert!(do_matching("a**b", "aaaaaaaaab", true).unwrap());
+        assert!(do_matching("a**b", "b", true).unwrap());
+
+        assert!(!do_matching("abc|def", "efa", true).unwrap());
+        assert!(!do_matching("(ab|cd)+", "", true).unwrap());
+        assert!(!do_matching("abc?", "acb", true).unwrap());
+    }
+}
@@ -1,87 +1,131 @@
-mod engine;
-mod helper;
-
-use helper::DynError;
+mod compare;
+mod debug;
+mod encoding;
+mod enumerate;
+mod extract;
+mod follow;
+mod generate;
+mod grep;
+mod profile;
+mod sub;
+mod table;
+mod walk;
+
+use regex::{ast_dot, code_dot, print};
 use std::{
     env,
-    fs::File,
-    io::{BufRead, BufReader},
+    io::{self, IsTerminal},
+    process,
 };
 
-fn main() -> Result<(), DynError> {
-    let args: Vec<String> = env::args().collect();
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    process::exit(run(&args));
+}
 
-    if args.len() <= 2 {
-        eprintln!("usage: {} regex file", args[0]);
-        return Err("Invalid arguments".into())
-    } else {
-        match_file(&args[1], &args[2])?;
+fn run(args: &[String]) -> i32 {
+    if args.is_empty() || args[0] == "--help" {
+        eprintln!("{}", grep::USAGE);
+        eprintln!("       regex sub [--in-place[=SUFFIX]] 's/PATTERN/REPLACEMENT/FLAGS' [FILE]...");
+        eprintln!("       regex compare [-i] [-w] PATTERN1 PATTERN2");
+        eprintln!("       regex table [-i] [-w] [--format=json|c|rust] [--name=NAME] PATTERN");
+        eprintln!("       regex enumerate [-i] [-w] [--max-len=N] [--limit=N] [--alphabet=CHARS] [-z] PATTERN");
+        eprintln!("       regex generate [-i] [-w] [-n COUNT] [--seed=N] [--max-repeat=N] [--near-miss] [-z] PATTERN");
+        eprintln!("       regex debug [-i] [-w] [-x] [--trace] PATTERN STRING");
+        eprintln!("       regex profile [-i] [-w] [-x] PATTERN [FILE]...");
+        eprintln!("       regex --dot-ast PATTERN");
+        eprintln!("       regex --dot-code PATTERN");
+        return 2;
     }
 
-    Ok(())
+    match args[0].as_str() {
+        "sub" => sub::main(&args[1..]),
+        "compare" => compare::main(&args[1..]),
+        "table" => table::main(&args[1..]),
+        "generate" => generate::main(&args[1..]),
+        "enumerate" => enumerate::main(&args[1..]),
+        "debug" => debug::main(&args[1..]),
+        "profile" => profile::main(&args[1..]),
+        "--dot-ast" | "--dot-code" => dot(&args[0], &args[1..]),
+        _ => grep_main(args),
+    }
 }
 
-fn match_file(expr: &str, file: &str) -> Result<(), DynError> {
-    let f = File::open(file)?;
-    let reader = BufReader::new(f);
+fn dot(mode: &str, args: &[String]) -> i32 {
+    let expr = if let [expr] = args {
+        expr
+    } else {
+        eprintln!("usage: regex {mode} PATTERN");
+        return 2;
+    };
 
-    engine::print(expr)?;
-    println!();
+    if let Err(e) = grep::check_pattern(expr) {
+        eprintln!("{e}");
+        return 2;
+    }
 
-    for line in reader.lines() {
-        let line = line?;
-        for (i, _) in line.char_indices() {
-            if engine::do_matching(expr, &line[i..], true)? {
-                println!("{line}");
-                break;
-            }
+    let result = if mode == "--dot-ast" {
+        ast_dot(expr)
+    } else {
+        code_dot(expr, true)
+    };
+    match result {
+        Ok(dot) => {
+            print!("{dot}");
+            0
+        }
+        Err(e) => {
+            eprintln!("regex: {e}");
+            2
         }
     }
-
-    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::{
-        engine::do_matching,
-        helper::{safe_add, SafeAdd},
+fn grep_main(args: &[String]) -> i32 {
+    let mut opts = match grep::parse_args(args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("regex: {e}");
+            eprintln!("{}", grep::USAGE);
+            return 2;
+        }
     };
 
-    #[test]
-    fn test_safe_add() {
-        let n: usize = 10;
-        assert_eq!(Some(30), n.safe_add(&20));
-
-        let n: usize = !0;
-        assert_eq!(None, n.safe_add(&1));
-
-        let mut n: usize = 10;
-        assert!(safe_add(&mut n, &20, || ()).is_ok());
-
-        let mut n:usize = !0;
-        assert!(safe_add(&mut n, &1, || ()).is_err());
+    if opts.color == grep::Color::Auto {
+        opts.color = if io::stdout().is_terminal() {
+            grep::Color::Always
+        } else {
+            grep::Color::Never
+        };
     }
 
-    #[test]
-    fn test_matching() {
-        assert!(do_matching("+b", "bbb", true).is_err());
-        assert!(do_matching("*b", "bbb", true).is_err());
-        assert!(do_matching("|b", "bbb", true).is_err());
-        assert!(do_matching("?b", "bbb", true).is_err());
+    let program = match grep::compile_patterns(&opts.patterns, opts.flags, opts.extract.is_some()) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+    if opts.extract.is_some() {
+        if let Err(e) = extract::check(&program) {
+            eprintln!("regex: {e}");
+            return 2;
+        }
+    }
 
-        assert!(do_matching("abc|def", "def", true).unwrap());
-        assert!(do_matching("(abc)*", "abcabc", true).unwrap());
-        assert!(do_matching("(ab|cd)+", "abcdcd", true).unwrap());
-        assert!(do_matching("abc?", "ab", true).unwrap());
-        assert!(do_matching("((((a*)*)*)*)", "aaaaaaaaa", true).unwrap());
-        assert!(do_matching("(a*)*b", "aaaaaaaaab", true).unwrap());
-        assert!(do_matching("(a*)*b", "b", true).unwrap());
-        assert!(do_matching("a**b", "aaaaaaaaab", true).unwrap());
-        assert!(do_matching("a**b", "b", true).unwrap());
+    if opts.is_dump {
+        for expr in &program.exprs {
+            if let Err(e) = print(expr) {
+                eprintln!("regex: {e}");
+                return 2;
+            }
+            println!();
+        }
+    }
 
-        assert!(!do_matching("abc|def", "efa", true).unwrap());
-        assert!(!do_matching("(ab|cd)+", "", true).unwrap());
-        assert!(!do_matching("abc?", "acb", true).unwrap());
+    if opts.is_follow {
+        follow::run(&opts, &program, &mut io::stdout().lock())
+    } else {
+        grep::run(&opts, &program, &mut io::stdout().lock())
     }
-}
\ No newline at end of file
+}
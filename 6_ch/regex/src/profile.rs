@@ -0,0 +1,122 @@
+//! `regex profile PATTERN [FILE]...`: 命令ごとの実行回数を数え、命令列のヒートマップを表示する
+use crate::grep;
+use regex::{DynError, Program};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+};
+
+pub const USAGE: &str = "\
+usage: regex profile [-i] [-w] [-x] PATTERN [FILE]...
+
+Search each line of FILEs (standard input if none, or if FILE is -) for
+PATTERN with the backtracking VM, then print the compiled code with the
+number of times each instruction was executed, its share of all executed
+instructions, and a bar scaled to the most executed one. For each split
+instruction, the number of backtracks to the alternative it pushed is shown
+as well. Hot spots point to the part of PATTERN that makes the search slow.
+
+  -i          ignore case distinctions
+  -w          match only whole words
+  -x          match only whole lines
+
+Exit status is 0 if any line matched, 1 if none, and 2 if an error occurred.";
+
+pub fn main(args: &[String]) -> i32 {
+    let (flags, operands) = match grep::parse_subcommand_args(args, true, |_, _| Ok(false)) {
+        Ok(parsed) => parsed,
+        Err(e) => return grep::usage_error(&e, USAGE),
+    };
+    let Some((pattern, files)) = operands.split_first() else {
+        return grep::usage_error("", USAGE);
+    };
+    if let Err(e) = grep::check_pattern(pattern) {
+        eprintln!("{e}");
+        return 2;
+    }
+    let files = if files.is_empty() { vec!["-".to_string()] } else { files.to_vec() };
+
+    let result = Program::with_flags(pattern, flags, true).and_then(|program| {
+        // 読み終えたファイルを閉じてから次を開く。-が続いても標準入力のロックは1つずつ取る
+        let readers = files.iter().map(|file| -> Result<Box<dyn BufRead>, DynError> {
+            if file == "-" {
+                Ok(Box::new(io::stdin().lock()))
+            } else {
+                Ok(Box::new(BufReader::new(File::open(file).map_err(|e| format!("{file}: {e}"))?)))
+            }
+        });
+        profile(&program, readers, &mut io::stdout().lock())
+    });
+    match result {
+        Ok(0) => 1,
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("regex: {e}");
+            2
+        }
+    }
+}
+
+/// readersを1つずつ開いて全ての行を検索し、ヒートマップを書き出してマッチした行の数を返す
+fn profile<R, I, W>(program: &Program, readers: I, out: &mut W) -> Result<usize, DynError>
+where
+    R: BufRead,
+    I: IntoIterator<Item = Result<R, DynError>>,
+    W: Write,
+{
+    let mut profile = program.profile();
+    let mut n_lines = 0;
+    let mut n_matched = 0;
+    for reader in readers {
+        let reader = reader?;
+        for line in reader.split(b'\n') {
+            let mut line = line?;
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let chars: Vec<char> = String::from_utf8_lossy(&line).chars().collect();
+            n_lines += 1;
+            if !profile.find_all(&chars)?.is_empty() {
+                n_matched += 1;
+            }
+        }
+    }
+
+    writeln!(out, "{profile}")?;
+    writeln!(out, "lines: {n_lines}, matched: {n_matched}")?;
+    Ok(n_matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::profile;
+    use regex::Program;
+
+    #[test]
+    fn test_profile() {
+        let program = Program::new("ab|c", false).unwrap();
+        let mut out = Vec::new();
+        let n = profile(&program, [Ok("c\n".as_bytes()), Ok("x".as_bytes())], &mut out).unwrap();
+        assert_eq!(n, 1);
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("code                              count      % backtracks\n"));
+        assert!(out.contains("\n0000: split 0001, 0004                4  30.8%          4  ####################\n"));
+        assert!(out.contains("\n0002: char b                          0   0.0%\n"));
+        assert!(out.ends_with("\nsteps: 13, backtracks: 4\nlines: 2, matched: 1\n"));
+    }
+
+    #[test]
+    fn test_profile_lazy() {
+        // 入力は1つずつ開き、開けなければ前の入力を読んだ後でエラーとする
+        let program = Program::new("a", false).unwrap();
+        let mut n_read = 0;
+        let readers = (0..2).map(|_| {
+            n_read += 1;
+            Ok("a\n".as_bytes())
+        });
+        assert_eq!(profile(&program, readers.take(1), &mut Vec::new()).unwrap(), 1);
+        assert_eq!(n_read, 1);
+        let readers = [Ok("a\n".as_bytes()), Err("b.txt: not found".into())];
+        assert_eq!(profile(&program, readers, &mut Vec::new()).unwrap_err().to_string(), "b.txt: not found");
+    }
+}
@@ -0,0 +1,469 @@
+//! `regex sub 's/PATTERN/REPLACEMENT/FLAGS' [FILE]...`: sedのs命令に相当する置換
+use regex::{DynError, Flags, Program};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+    process,
+};
+
+pub const USAGE: &str = "\
+usage: regex sub [--in-place[=SUFFIX]] 's/PATTERN/REPLACEMENT/FLAGS' [FILE]...
+
+Replace matches of PATTERN in each line and print the result.
+Any character other than a backslash or newline may be used instead of '/';
+write \\/ for a literal delimiter inside PATTERN or REPLACEMENT.
+
+REPLACEMENT may refer to groups of PATTERN:
+  $0          the whole match
+  $n, ${n}    the n-th group
+  ${name}     the group named (?<name>...)
+  $$          a literal $
+  \\\\          a literal backslash
+  \\n          a newline
+
+FLAGS:
+  g           replace all matches instead of the first one
+  i           ignore case distinctions
+  N           replace only the N-th match; with g, the N-th and later ones
+
+  --in-place[=SUFFIX]  rewrite FILEs instead of printing; keep a backup in FILE
+                       + SUFFIX if given. Each file is replaced atomically.
+
+With no FILE, read standard input.";
+
+/// 置換文字列の要素
+#[derive(Debug, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Group(usize),
+}
+
+/// 解析済みのs命令
+#[derive(Debug)]
+pub struct Command {
+    program: Program,
+    replacement: Vec<Piece>,
+    is_global: bool,
+    nth: usize, // 置換を始めるマッチ。1から数える
+}
+
+pub fn main(args: &[String]) -> i32 {
+    let mut suffix = None;
+    let mut is_in_place = false;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--" => {
+                rest.extend(iter.by_ref().cloned());
+                break;
+            }
+            "--help" => {
+                eprintln!("{USAGE}");
+                return 2;
+            }
+            "--in-place" => is_in_place = true,
+            _ => {
+                if let Some(s) = arg.strip_prefix("--in-place=") {
+                    is_in_place = true;
+                    suffix = Some(s.to_string());
+                } else if arg.starts_with("--") {
+                    eprintln!("regex: unknown option: {arg}");
+                    eprintln!("{USAGE}");
+                    return 2;
+                } else {
+                    rest.push(arg.clone());
+                }
+            }
+        }
+    }
+
+    let (script, files) = match rest.split_first() {
+        Some((script, files)) => (script, files),
+        None => {
+            eprintln!("{USAGE}");
+            return 2;
+        }
+    };
+    let cmd = match parse_command(script) {
+        Ok(cmd) => cmd,
+        Err(e) => {
+            eprintln!("regex: {e}");
+            return 2;
+        }
+    };
+
+    if is_in_place && files.is_empty() {
+        eprintln!("regex: --in-place requires a FILE");
+        return 2;
+    }
+
+    if files.is_empty() {
+        let result = io::read_to_string(io::stdin())
+            .map_err(|e| e.into())
+            .and_then(|input| substitute_all(&cmd, &input))
+            .and_then(|output| Ok(io::stdout().lock().write_all(output.as_bytes())?));
+        return match result {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("regex: (standard input): {e}");
+                2
+            }
+        };
+    }
+
+    let mut status = 0;
+    for file in files {
+        let result = fs::read_to_string(file)
+            .map_err(|e| e.into())
+            .and_then(|input| substitute_all(&cmd, &input))
+            .and_then(|output| {
+                if is_in_place {
+                    write_in_place(Path::new(file), output.as_bytes(), suffix.as_deref())?;
+                } else {
+                    io::stdout().lock().write_all(output.as_bytes())?;
+                }
+                Ok(())
+            });
+        if let Err(e) = result {
+            eprintln!("regex: {file}: {e}");
+            status = 2;
+        }
+    }
+    status
+}
+
+/// `s/PATTERN/REPLACEMENT/FLAGS`を解析する
+pub fn parse_command(script: &str) -> Result<Command, DynError> {
+    let mut chars = script.chars();
+    if chars.next() != Some('s') {
+        return Err(format!("not a substitution: {script}").into());
+    }
+    let delim = match chars.next() {
+        Some(c) if c != '\\' && c != '\n' => c,
+        _ => return Err(format!("invalid delimiter: {script}").into()),
+    };
+
+    // 区切り文字で3つに分ける。\と区切り文字の組は区切り文字そのものを表すので、
+    // 特殊文字であればPATTERNではエスケープし、REPLACEMENTの$は$$とする
+    let mut fields = vec![String::new()];
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(n) if n == delim => {
+                    let index = fields.len() - 1;
+                    let field = fields.last_mut().unwrap();
+                    match index {
+                        0 if regex::is_meta(n) => field.push('\\'),
+                        1 if n == '$' => field.push('$'),
+                        _ => (),
+                    }
+                    field.push(n);
+                }
+                Some(n) => {
+                    let field = fields.last_mut().unwrap();
+                    field.push('\\');
+                    field.push(n);
+                }
+                None => fields.last_mut().unwrap().push('\\'),
+            }
+        } else if c == delim {
+            fields.push(String::new());
+        } else {
+            fields.last_mut().unwrap().push(c);
+        }
+    }
+    let (pattern, replacement, flags) = match &fields[..] {
+        [p, r, f] => (p, r, f),
+        _ => return Err(format!("expected s{delim}PATTERN{delim}REPLACEMENT{delim}FLAGS: {script}").into()),
+    };
+
+    let mut is_global = false;
+    let mut is_ignore_case = false;
+    let mut nth = String::new();
+    for c in flags.chars() {
+        match c {
+            'g' => is_global = true,
+            'i' => is_ignore_case = true,
+            '0'..='9' => nth.push(c),
+            _ => return Err(format!("unknown flag: {c}").into()),
+        }
+    }
+    let nth = if nth.is_empty() {
+        1
+    } else {
+        match nth.parse() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("invalid occurrence: {nth}").into()),
+        }
+    };
+
+    if let Err(e) = regex::parse(pattern) {
+        return Err(e.render(pattern).trim_end().into());
+    }
+    let flags = Flags { is_ignore_case, ..Default::default() };
+    let program = Program::with_captures(pattern, flags, true)?;
+    let replacement = parse_replacement(replacement, &program)?;
+
+    Ok(Command { program, replacement, is_global, nth })
+}
+
+fn parse_replacement(s: &str, program: &Program) -> Result<Vec<Piece>, DynError> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('\\') => literal.push('\\'),
+                Some('n') => literal.push('\n'),
+                _ => return Err(format!("invalid escape in replacement: {s}; write \\\\ for a literal backslash").into()),
+            }
+            continue;
+        }
+        if c != '$' {
+            literal.push(c);
+            continue;
+        }
+
+        let name = match chars.peek() {
+            Some('$') => {
+                chars.next();
+                literal.push('$');
+                continue;
+            }
+            Some('{') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("unclosed ${{ in replacement: {s}").into()),
+                    }
+                }
+                name
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+                    name.push(c);
+                }
+                name
+            }
+            Some(c) if *c == '_' || c.is_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| *c == '_' || c.is_alphanumeric()) {
+                    name.push(c);
+                }
+                name
+            }
+            _ => return Err(format!("invalid reference in replacement: {s}; write $$ for a literal $").into()),
+        };
+
+        let index = match name.parse::<usize>() {
+            Ok(n) if n <= program.group_names.len() => n,
+            Ok(_) => return Err(format!("no such group: ${name}").into()),
+            Err(_) => program.group_index(&name).ok_or_else(|| format!("no such group: ${{{name}}}"))?,
+        };
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+        }
+        pieces.push(Piece::Group(index));
+    }
+
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+/// 入力の各行を置換する。改行はそのまま残す
+fn substitute_all(cmd: &Command, input: &str) -> Result<String, DynError> {
+    let mut output = String::with_capacity(input.len());
+    for line in input.split_inclusive('\n') {
+        let (body, newline) = match line.strip_suffix('\n') {
+            Some(body) => (body, "\n"),
+            None => (line, ""),
+        };
+        output.push_str(&substitute(cmd, body)?);
+        output.push_str(newline);
+    }
+    Ok(output)
+}
+
+/// 1行を置換する
+pub fn substitute(cmd: &Command, line: &str) -> Result<String, DynError> {
+    let chars = line.chars().collect::<Vec<char>>();
+    let mut output = String::new();
+    let mut copied = 0; // chars[..copied]は出力済み
+    let mut start = 0;
+    let mut count = 0;
+    let mut last_end = None; // 直前の空でないマッチの終端
+
+    while start <= chars.len() {
+        let caps = match cmd.program.captures_at(&chars, start)? {
+            Some(caps) => caps,
+            None => break,
+        };
+        let (begin, end) = caps[0].unwrap();
+        // sedと同じく、空でないマッチの直後から始まる空マッチは置換せず、数えもしない
+        if begin == end && last_end == Some(begin) {
+            start = end + 1;
+            continue;
+        }
+        if begin != end {
+            last_end = Some(end);
+        }
+        count += 1;
+
+        if count >= cmd.nth {
+            output.extend(&chars[copied..begin]);
+            for piece in &cmd.replacement {
+                match piece {
+                    Piece::Literal(s) => output.push_str(s),
+                    Piece::Group(i) => {
+                        if let Some((b, e)) = caps[*i] {
+                            output.extend(&chars[b..e]);
+                        }
+                    }
+                }
+            }
+            copied = end;
+            if !cmd.is_global {
+                break;
+            }
+        }
+
+        // 空マッチの場合は1文字進めて無限ループを防ぐ
+        start = if end == begin { end + 1 } else { end };
+    }
+
+    output.extend(&chars[copied..]);
+    Ok(output)
+}
+
+/// pathの内容をbytesに置き換える。suffixがあれば元の内容をpath + suffixに残す
+///
+/// 同じディレクトリの一時ファイルに書いてからrenameするので、
+/// 途中で失敗しても元のファイルが中途半端な内容になることはない
+fn write_in_place(path: &Path, bytes: &[u8], suffix: Option<&str>) -> io::Result<()> {
+    let perm = fs::metadata(path)?.permissions();
+
+    if let Some(suffix) = suffix {
+        let mut original = Vec::new();
+        File::open(path)?.read_to_end(&mut original)?;
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(suffix);
+        write_atomic(Path::new(&backup), &original, &perm)?;
+    }
+
+    write_atomic(path, bytes, &perm)
+}
+
+fn write_atomic(path: &Path, bytes: &[u8], perm: &fs::Permissions) -> io::Result<()> {
+    let name = path.file_name().ok_or_else(|| io::Error::other("not a file"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(format!(".regex-{}.tmp", process::id()));
+    let tmp = path.with_file_name(tmp_name);
+
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::set_permissions(&tmp, perm.clone())?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{main, parse_command, substitute, Piece};
+    use std::{env, fs};
+
+    fn sub(script: &str, line: &str) -> String {
+        substitute(&parse_command(script).unwrap(), line).unwrap()
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let cmd = parse_command("s|a\\|b|x\\||2g").unwrap();
+        assert_eq!(cmd.program.exprs, vec!["a\\|b"]);
+        assert_eq!(cmd.replacement, vec![Piece::Literal("x|".into())]);
+        assert!(cmd.is_global);
+        assert_eq!(cmd.nth, 2);
+
+        // エスケープした区切り文字は、特殊文字でも文字そのものとして扱う
+        assert_eq!(parse_command("s,a\\,b,x,").unwrap().program.exprs, vec!["a,b"]);
+        let cmd = parse_command("s$a\\$$\\$1$").unwrap();
+        assert_eq!(cmd.program.exprs, vec!["a\\$"]);
+        assert_eq!(cmd.replacement, vec![Piece::Literal("$1".into())]);
+
+        let err = |script| parse_command(script).unwrap_err().to_string();
+        assert!(err("s/a/b").contains("expected"));
+        assert!(err("s/a/b/c/").contains("expected"));
+        assert!(err("t/a/b/").contains("not a substitution"));
+        assert!(err("s/a/b/x").contains("unknown flag"));
+        assert!(err("s/a/b/0").contains("invalid occurrence"));
+        assert!(err("s/(a/b/").contains("  | (a\n"));
+        assert!(err("s/(a)/$2/").contains("no such group"));
+        assert!(err("s/(a)/${x}/").contains("no such group"));
+        assert!(err("s/(a)/${1/").contains("unclosed"));
+        assert!(err("s/(a)/$-/").contains("invalid reference"));
+        assert!(err("s/a/\\x/").contains("invalid escape"));
+        assert!(err("s/a/b\\").contains("expected"));
+    }
+
+    #[test]
+    fn test_substitute() {
+        assert_eq!(sub("s/o/0/", "foo boo"), "f0o boo");
+        assert_eq!(sub("s/o/0/g", "foo boo"), "f00 b00");
+        assert_eq!(sub("s/o/0/3", "foo boo"), "foo b0o");
+        assert_eq!(sub("s/o/0/3g", "foo boo"), "foo b00");
+        assert_eq!(sub("s/O/0/gi", "foo"), "f00");
+        assert_eq!(sub("s/x/y/", "foo"), "foo");
+        assert_eq!(sub("s/(a+)=(b+)/$2=$1/g", "aa=b a=bb"), "b=aa bb=a");
+        assert_eq!(sub("s/(?<k>a+)=(?<v>b+)/${v}:${k}$$/", "a=b"), "b:a$");
+        assert_eq!(sub("s/x(y)?/[$0$1]/g", "x xy"), "[x] [xyy]");
+        assert_eq!(sub("s/x*/-/g", "abc"), "-a-b-c-");
+        assert_eq!(sub("s/b*/-/g", "abc"), "-a-c-");
+        assert_eq!(sub("s/b*/-/2", "abc"), "a-c");
+        assert_eq!(sub("s|/|\\\\|g", "a/b/c"), "a\\b\\c");
+        assert_eq!(sub("s/,/\\n/g", "a,b"), "a\nb");
+        assert_eq!(sub("s/a/\\\\$$\\\\/", "a"), "\\$\\");
+        assert_eq!(sub("s/い+/$0$0/", "あいい"), "あいいいい");
+        assert_eq!(sub("s|x\\|y|Z|", "x|y"), "Z");
+        assert_eq!(sub("s.a\\.b.c.", "axb a.b"), "axb c");
+    }
+
+    #[test]
+    fn test_in_place() {
+        let dir = env::temp_dir().join(format!("regex_sub_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("in.txt");
+        fs::write(&path, "foo\nbar foo\nno newline foo").unwrap();
+        let p = path.display().to_string();
+
+        let status = main(&["--in-place=.bak".to_string(), "s/foo/baz/".to_string(), p.clone()]);
+        assert_eq!(status, 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "baz\nbar baz\nno newline baz");
+        assert_eq!(fs::read_to_string(format!("{p}.bak")).unwrap(), "foo\nbar foo\nno newline foo");
+        // 一時ファイルは残らない
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        assert_eq!(main(&["--in-place".to_string(), "s/baz/qux/g".to_string(), p.clone()]), 0);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "qux\nbar qux\nno newline qux");
+
+        assert_eq!(main(&["--in-place".to_string(), "s/a/b/".to_string()]), 2);
+        assert_eq!(main(&["s/a/b/".to_string(), dir.join("none").display().to_string()]), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+//! `regex table PATTERN`: 最小化したDFAの遷移表をJSON、C、Rustのコードとして書き出す
+use crate::grep;
+use regex::{DenseTable, Dfa, DynError, Flags};
+use std::io::{self, Write};
+
+pub const USAGE: &str = "\
+usage: regex table [-i] [-w] [--format=FORMAT] [--name=NAME] PATTERN
+
+Build the minimal DFA that matches whole strings against PATTERN and print
+its transition table, with characters grouped into classes that behave alike.
+
+  -i              ignore case distinctions
+  -w              match only whole words
+  --format=FORMAT json (the default), c or rust
+  --name=NAME     prefix of the arrays and name of the matching function in
+                  C and Rust output (default: matcher). C output defines
+                  bool NAME_is_match(const uint32_t *s, size_t len) over code
+                  points; Rust output defines pub fn NAME(s: &str) -> bool";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Json,
+    C,
+    Rust,
+}
+
+pub fn main(args: &[String]) -> i32 {
+    let mut format = Format::Json;
+    let mut name = "matcher".to_string();
+    let parsed = grep::parse_subcommand_args(args, false, |arg, _| {
+        match arg {
+            "--format=json" => format = Format::Json,
+            "--format=c" => format = Format::C,
+            "--format=rust" => format = Format::Rust,
+            _ => match arg.strip_prefix("--name=") {
+                Some(s) => name = s.to_string(),
+                None => return Ok(false),
+            },
+        }
+        Ok(true)
+    });
+    let (flags, patterns) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => return grep::usage_error(&e, USAGE),
+    };
+    let [pattern] = patterns.as_slice() else {
+        return grep::usage_error("", USAGE);
+    };
+    if let Err(e) = grep::check_pattern(pattern) {
+        eprintln!("{e}");
+        return 2;
+    }
+
+    match table(pattern, flags, format, &name) {
+        Ok(s) => match io::stdout().lock().write_all(s.as_bytes()) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("regex: {e}");
+                2
+            }
+        },
+        Err(e) => {
+            eprintln!("regex: {e}");
+            2
+        }
+    }
+}
+
+fn table(pattern: &str, flags: Flags, format: Format, name: &str) -> Result<String, DynError> {
+    let table = DenseTable::new(&Dfa::with_flags(pattern, flags)?);
+    match format {
+        Format::Json => Ok(table.to_json(pattern)),
+        Format::C => table.to_c(pattern, name),
+        Format::Rust => table.to_rust(pattern, name),
+    }
+}
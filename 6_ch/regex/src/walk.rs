@@ -0,0 +1,199 @@
+//! -rで検索するファイルの列挙と、--include/--excludeのglob照合
+use std::{fs, io, path::Path};
+
+/// ファイル名に対するglobの照合。`*`、`?`、`[abc]`、`[a-z]`、`[!abc]`に対応する
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = name.chars().collect::<Vec<char>>();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None; // 直前の*の位置と、そこで読み飛ばした位置
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            Some('[') => {
+                if let Some((is_match, len)) = match_bracket(&pattern[p..], name[n]) {
+                    if is_match {
+                        p += len;
+                        n += 1;
+                        continue;
+                    }
+                } else if name[n] == '[' {
+                    p += 1;
+                    n += 1;
+                    continue;
+                }
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+                continue;
+            }
+            _ => (),
+        }
+
+        // 直前の*にもう1文字吸収させてやり直す
+        if let Some((bp, bn)) = backtrack {
+            backtrack = Some((bp, bn + 1));
+            p = bp + 1;
+            n = bn + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// `[...]`をcと照合し、結果と`[...]`の長さを返す。閉じていなければNone
+fn match_bracket(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let is_negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if is_negate {
+        i += 1;
+    }
+
+    let mut is_match = false;
+    let mut is_first = true;
+    loop {
+        let lo = *pattern.get(i)?;
+        if lo == ']' && !is_first {
+            return Some((is_match != is_negate, i + 1));
+        }
+        is_first = false;
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|hi| *hi != ']') {
+            let hi = pattern[i + 2];
+            is_match |= lo <= c && c <= hi;
+            i += 3;
+        } else {
+            is_match |= lo == c;
+            i += 1;
+        }
+    }
+}
+
+/// ファイルを選別する条件
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Filter {
+    pub includes: Vec<String>,
+    pub excludes: Vec<String>,
+    pub exclude_dirs: Vec<String>,
+}
+
+impl Filter {
+    fn is_file_selected(&self, name: &str) -> bool {
+        (self.includes.is_empty() || self.includes.iter().any(|g| glob_match(g, name)))
+            && !self.excludes.iter().any(|g| glob_match(g, name))
+    }
+
+    fn is_dir_selected(&self, name: &str) -> bool {
+        !self.exclude_dirs.iter().any(|g| glob_match(g, name))
+    }
+}
+
+/// pathがディレクトリであれば再帰的に辿り、検索するファイルをfilesに追加する
+///
+/// 結果が毎回同じ順序になるよう、ディレクトリの中身は名前順に辿る。
+/// 辿れなかったパスはerrorsに追加する
+pub fn walk(path: &Path, filter: &Filter, files: &mut Vec<String>, errors: &mut Vec<(String, io::Error)>) {
+    let mut entries = match fs::read_dir(path) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).collect::<Vec<_>>(),
+        Err(e) => {
+            errors.push((path.display().to_string(), e));
+            return;
+        }
+    };
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        // シンボリックリンクは辿らない
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => {
+                if filter.is_dir_selected(&name) {
+                    walk(&path, filter, files, errors);
+                }
+            }
+            Ok(t) if t.is_file() => {
+                if filter.is_file_selected(&name) {
+                    files.push(path.display().to_string());
+                }
+            }
+            Ok(_) => (),
+            Err(e) => errors.push((path.display().to_string(), e)),
+        }
+    }
+}
+
+/// バイナリか調べる先頭のバイト数
+pub const BINARY_CHECK_LEN: usize = 8192;
+
+/// 先頭8KiBにNULを含むファイルをバイナリとみなす
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(BINARY_CHECK_LEN).any(|b| *b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, is_binary, walk, Filter};
+    use std::{env, fs, path::Path};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(glob_match("*.rs", ".rs"));
+        assert!(!glob_match("*.rs", "main.rs.bak"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(glob_match("[ab]*.[!o]", "b1.c"));
+        assert!(!glob_match("[ab]*.[!o]", "b1.o"));
+        assert!(glob_match("file[0-9]", "file7"));
+        assert!(!glob_match("file[0-9]", "filex"));
+        assert!(glob_match("[]x]", "]"));
+        assert!(glob_match("a[b", "a[b"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_walk() {
+        let dir = env::temp_dir().join(format!("regex_walk_{}", std::process::id()));
+        fs::create_dir_all(dir.join("src/sub")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        for f in ["a.rs", "b.txt", "src/c.rs", "src/sub/d.rs", "target/e.rs"] {
+            fs::write(dir.join(f), "x").unwrap();
+        }
+
+        let filter = Filter {
+            includes: vec!["*.rs".into()],
+            excludes: vec!["d.*".into()],
+            exclude_dirs: vec!["target".into()],
+        };
+        let mut files = Vec::new();
+        let mut errors = Vec::new();
+        walk(&dir, &filter, &mut files, &mut errors);
+        walk(Path::new("/nonexistent/regex"), &filter, &mut files, &mut errors);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let expected = ["a.rs", "src/c.rs"].map(|f| dir.join(f).display().to_string());
+        assert_eq!(files, expected);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_is_binary() {
+        assert!(!is_binary(b"hello\nworld\n"));
+        assert!(is_binary(b"ELF\0\x01"));
+    }
+}